@@ -0,0 +1,355 @@
+// Join a tslatencystamper log with a tslatencymeasure log by stamped value
+//
+// The stamper log (`log-location` on tslatencystamper) has the columns
+// `frame,pts,timestamp` and the measurement log (`log-location` on
+// tslatencymeasure) has `frame,pts,timestamp,arrival,latency`. Frames
+// are matched by their stamped timestamp, so the clocks of both sides
+// need not be synchronized. The relative delay is the arrival time
+// minus the stamped time, shifted so that the fastest frame has zero
+// delay.
+//
+// Readers falling back to millisecond precision (the coarse layer of
+// `hierarchical`) log whole milliseconds. A whole millisecond without
+// an exact match is matched to the frame stamped within it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    process::ExitCode,
+};
+
+struct SenderFrame {
+    frame: u64,
+    pts: String,
+    timestamp: u64,
+}
+
+struct ReceiverFrame {
+    frame: u64,
+    pts: String,
+    arrival: u64,
+}
+
+/// Sender frames with the receiver frame each one arrived as
+struct Joined<'a> {
+    /// Every sender frame, with the matching receiver frame and its
+    /// relative delay if it arrived
+    rows: Vec<(&'a SenderFrame, Option<(&'a ReceiverFrame, i64)>)>,
+    /// Receiver frames repeating an earlier stamp
+    repeated: usize,
+    /// Frames matched at millisecond precision
+    coarse: usize,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if !(3..=4).contains(&args.len()) {
+        eprintln!("Usage: {} SENDER_LOG RECEIVER_LOG [OUTPUT]", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    match run(&args[1], &args[2], args.get(3).map(String::as_str)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(sender_path: &str, receiver_path: &str, output_path: Option<&str>) -> io::Result<()> {
+    let sender = read_sender_log(sender_path, open(sender_path)?)?;
+    let (receiver, undecoded) = read_receiver_log(receiver_path, open(receiver_path)?)?;
+    let joined = join(&sender, &receiver);
+
+    let mut output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    writeln!(
+        output,
+        "timestamp,sender_frame,sender_pts,receiver_frame,receiver_pts,arrival,relative_delay"
+    )?;
+
+    let mut delays = vec![];
+    for (sent, received) in &joined.rows {
+        match received {
+            Some((received, delay)) => {
+                delays.push(*delay);
+                writeln!(
+                    output,
+                    "{},{},{},{},{},{},{}",
+                    sent.timestamp,
+                    sent.frame,
+                    sent.pts,
+                    received.frame,
+                    received.pts,
+                    received.arrival,
+                    delay
+                )?;
+            }
+            None => {
+                writeln!(output, "{},{},{},,,,", sent.timestamp, sent.frame, sent.pts)?;
+            }
+        }
+    }
+    output.flush()?;
+
+    let sent = sender.len();
+    let matched = delays.len();
+    let lost = sent - matched;
+    eprintln!("sent frames:       {}", sent);
+    eprintln!("received frames:   {}", matched);
+    eprintln!("  to the msec:     {}", joined.coarse);
+    eprintln!(
+        "lost frames:       {} ({:.2}%)",
+        lost,
+        100.0 * lost as f64 / sent.max(1) as f64
+    );
+    eprintln!("repeated frames:   {}", joined.repeated);
+    eprintln!("undecoded frames:  {}", undecoded);
+
+    if let (Some(min), Some(max)) = (delays.iter().min(), delays.iter().max()) {
+        let mean = delays.iter().sum::<i64>() as f64 / matched as f64;
+        eprintln!(
+            "relative delay:    min {} / mean {:.1} / max {} usecs",
+            min, mean, max
+        );
+    }
+
+    Ok(())
+}
+
+/// Match every sender frame with its first arrival by stamped value
+fn join<'a>(sender: &'a [SenderFrame], receiver: &'a [(u64, ReceiverFrame)]) -> Joined<'a> {
+    // Keep the first arrival of each stamp, repeats are frozen frames
+    let mut arrivals: HashMap<u64, &ReceiverFrame> = HashMap::new();
+    let mut repeated = 0;
+    for (timestamp, frame) in receiver {
+        if arrivals.contains_key(timestamp) {
+            repeated += 1;
+        } else {
+            arrivals.insert(*timestamp, frame);
+        }
+    }
+
+    // Exact matches first, whole milliseconds left over then match the
+    // frame stamped within them
+    let mut claimed: HashSet<u64> = sender
+        .iter()
+        .map(|sent| sent.timestamp)
+        .filter(|timestamp| arrivals.contains_key(timestamp))
+        .collect();
+    let mut coarse = 0;
+    let matches: Vec<_> = sender
+        .iter()
+        .map(|sent| {
+            if let Some(&received) = arrivals.get(&sent.timestamp) {
+                return Some(received);
+            }
+            let ms = sent.timestamp / 1000 * 1000;
+            let received = *arrivals.get(&ms)?;
+            claimed.insert(ms).then(|| {
+                coarse += 1;
+                received
+            })
+        })
+        .collect();
+
+    let raw_delay = |sent: &SenderFrame, received: &ReceiverFrame| {
+        received.arrival as i64 - sent.timestamp as i64
+    };
+    let min_delay = sender
+        .iter()
+        .zip(&matches)
+        .filter_map(|(sent, received)| Some(raw_delay(sent, (*received)?)))
+        .min()
+        .unwrap_or_default();
+
+    Joined {
+        rows: sender
+            .iter()
+            .zip(matches)
+            .map(|(sent, received)| {
+                (
+                    sent,
+                    received.map(|received| (received, raw_delay(sent, received) - min_delay)),
+                )
+            })
+            .collect(),
+        repeated,
+        coarse,
+    }
+}
+
+fn open(path: &str) -> io::Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| io::Error::new(err.kind(), format!("Could not open {}: {}", path, err)))
+}
+
+fn read_sender_log(path: &str, reader: impl BufRead) -> io::Result<Vec<SenderFrame>> {
+    read_csv(path, reader, 3)?
+        .into_iter()
+        .map(|(line_no, fields)| {
+            Ok(SenderFrame {
+                frame: parse(path, line_no, &fields[0])?,
+                pts: fields[1].clone(),
+                timestamp: parse(path, line_no, &fields[2])?,
+            })
+        })
+        .collect()
+}
+
+/// Returns the decoded frames keyed by timestamp in arrival order, and
+/// the number of frames that failed to decode.
+fn read_receiver_log(
+    path: &str,
+    reader: impl BufRead,
+) -> io::Result<(Vec<(u64, ReceiverFrame)>, usize)> {
+    let mut frames = vec![];
+    let mut undecoded = 0;
+
+    for (line_no, fields) in read_csv(path, reader, 5)? {
+        if fields[2].is_empty() {
+            undecoded += 1;
+            continue;
+        }
+
+        let timestamp = parse(path, line_no, &fields[2])?;
+        frames.push((
+            timestamp,
+            ReceiverFrame {
+                frame: parse(path, line_no, &fields[0])?,
+                pts: fields[1].clone(),
+                arrival: parse(path, line_no, &fields[3])?,
+            },
+        ));
+    }
+
+    Ok((frames, undecoded))
+}
+
+/// Read a CSV log, skipping the header line, `path` names it in errors
+fn read_csv(
+    path: &str,
+    reader: impl BufRead,
+    columns: usize,
+) -> io::Result<Vec<(usize, Vec<String>)>> {
+    let mut rows = vec![];
+    for (line_no, line) in reader.lines().enumerate().skip(1) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<String> = line
+            .split(',')
+            .map(|field| field.trim().to_string())
+            .collect();
+        if fields.len() != columns {
+            return Err(invalid(path, line_no, "unexpected number of columns"));
+        }
+        rows.push((line_no, fields));
+    }
+
+    Ok(rows)
+}
+
+fn parse(path: &str, line_no: usize, field: &str) -> io::Result<u64> {
+    field
+        .parse()
+        .map_err(|_| invalid(path, line_no, &format!("invalid number {:?}", field)))
+}
+
+fn invalid(path: &str, line_no: usize, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path, line_no + 1, reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENDER_LOG: &str = "\
+frame,pts,timestamp
+0,0:00:00.000000000,1000000100
+1,0:00:00.033333333,1000033433
+2,0:00:00.066666666,1000066766
+3,0:00:00.100000000,1000100100
+";
+
+    /// Frame 1 failed to decode, frame 2 only to the millisecond and
+    /// arrived twice
+    const RECEIVER_LOG: &str = "\
+frame,pts,timestamp,arrival,latency
+0,0:00:00.000000000,1000000100,1000050100,50000
+1,0:00:00.033333333,,1000083433,
+2,0:00:00.066666666,1000066000,1000126766,60766
+3,0:00:00.100000000,1000066000,1000160100,94100
+4,0:00:00.133333333,1000100100,1000160100,60000
+";
+
+    fn delays(joined: &Joined) -> Vec<Option<i64>> {
+        joined
+            .rows
+            .iter()
+            .map(|(_, received)| received.map(|(_, delay)| delay))
+            .collect()
+    }
+
+    #[test]
+    fn joins_logs_by_stamped_value() {
+        let sender = read_sender_log("sender", SENDER_LOG.as_bytes()).unwrap();
+        let (receiver, undecoded) = read_receiver_log("receiver", RECEIVER_LOG.as_bytes()).unwrap();
+        let joined = join(&sender, &receiver);
+
+        assert_eq!(undecoded, 1);
+        assert_eq!(joined.repeated, 1);
+        assert_eq!(joined.coarse, 1);
+        // Delays are taken from the sender's full stamp
+        assert_eq!(delays(&joined), [Some(0), None, Some(10000), Some(10000)]);
+        let frames: Vec<_> = joined
+            .rows
+            .iter()
+            .map(|(_, received)| received.map(|(received, _)| received.frame))
+            .collect();
+        assert_eq!(frames, [Some(0), None, Some(2), Some(4)]);
+    }
+
+    #[test]
+    fn exact_matches_take_precedence_over_milliseconds() {
+        let sender = read_sender_log(
+            "sender",
+            "frame,pts,timestamp\n0,0,5000000\n1,0,5000500\n".as_bytes(),
+        )
+        .unwrap();
+        let (receiver, _) = read_receiver_log(
+            "receiver",
+            "frame,pts,timestamp,arrival,latency\n0,0,5000000,5020000,20000\n".as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(delays(&join(&sender, &receiver)), [Some(0), None]);
+    }
+
+    #[test]
+    fn rejects_malformed_logs() {
+        let err = read_receiver_log(
+            "receiver",
+            "frame,pts,timestamp,arrival,latency\n0,0,12,34\n".as_bytes(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.to_string(), "receiver:2: unexpected number of columns");
+
+        let err = read_sender_log("sender", "frame,pts,timestamp\n0,0,soon\n".as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "sender:2: invalid number \"soon\"");
+    }
+}
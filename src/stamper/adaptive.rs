@@ -0,0 +1,460 @@
+// Reed-Solomon code whose cell size and parity follow receiver feedback
+//
+// Each frame carries a header naming its layout, so the reader needs no
+// configuration and follows every change the stamper makes. The stamper
+// picks the layout from decode reports of the measure element: a
+// struggling receiver gets larger cells and more parity, a receiver
+// decoding everything with confidence gets a smaller, less visible code
+// back.
+
+use super::{
+    feedback::Feedback,
+    grid::{CellGrid, BLACK, WHITE},
+    levels::Levels,
+    plane::LumaPlane,
+    reed_solomon::{codeword_len, decode_samples, encode_symbols, symbol_bits},
+    traits::{ReaderConfig, Reading, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Cell size and parity of one step of the adaptive code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Side of a payload cell in pixels
+    pub cell_size: usize,
+    /// Reed-Solomon parity symbols
+    pub parity_symbols: usize,
+}
+
+const fn layout(cell_size: usize, parity_symbols: usize) -> Layout {
+    Layout {
+        cell_size,
+        parity_symbols,
+    }
+}
+
+/// Layouts from the smallest to the most robust, the header carries the
+/// index
+pub const LAYOUTS: [Layout; 8] = [
+    layout(2, 4),
+    layout(2, 8),
+    layout(4, 4),
+    layout(4, 8),
+    layout(4, 12),
+    layout(8, 4),
+    layout(8, 8),
+    layout(8, 12),
+];
+
+/// Layout used until feedback arrives, the `reed-solomon` codec's cells
+pub const DEFAULT_LAYOUT: usize = 3;
+
+/// Side of a header cell in pixels, large enough to survive any layout
+/// the payload is in trouble with
+const HEADER_CELL: usize = 8;
+
+/// Bits of the layout index in the header
+const HEADER_BITS: usize = 3;
+
+/// Header cell pairs closer than this (in nominal levels) are not told
+/// apart
+const HEADER_CONTRAST: i32 = 32;
+
+/// Soft value at which a payload cell counts as fully confident, half
+/// the nominal swing
+const FULL_CONFIDENCE: i32 = (WHITE - BLACK) as i32 / 2;
+
+/// Header row of two cells per layout bit, the first at the bit's level
+/// and the second at the opposite one
+fn header_grid(x: u32, y: u32, width: u32) -> Option<CellGrid> {
+    let grid = CellGrid::fit(x, y, width, HEADER_CELL as u32, HEADER_CELL);
+    (grid.capacity() >= 2 * HEADER_BITS).then_some(grid)
+}
+
+/// Grid below the header holding the codeword of `layout`, or `None` if
+/// the region is too small
+fn payload_grid(x: u32, y: u32, width: u32, height: u32, layout: Layout) -> Option<CellGrid> {
+    let height = height.checked_sub(HEADER_CELL as u32)?;
+    let grid = CellGrid::fit(x, y + HEADER_CELL as u32, width, height, layout.cell_size);
+    (codeword_len(layout.parity_symbols) * 8 <= grid.capacity()).then_some(grid)
+}
+
+/// Whether the layout at `index` fits a region of the given size
+pub fn fits(index: usize, width: u32, height: u32) -> bool {
+    header_grid(0, 0, width).is_some()
+        && payload_grid(0, 0, width, height, LAYOUTS[index]).is_some()
+}
+
+/// Most robust layout such that it and every smaller one fit the
+/// region, `None` if not even the smallest does
+pub fn largest_fitting(width: u32, height: u32) -> Option<usize> {
+    (0..LAYOUTS.len())
+        .take_while(|&index| fits(index, width, height))
+        .last()
+}
+
+fn header_bits(index: usize) -> impl Iterator<Item = bool> {
+    (0..HEADER_BITS)
+        .rev()
+        .map(move |bit| (index >> bit) & 1 == 1)
+        .flat_map(|bit| [bit, !bit])
+}
+
+/// Adaptive stamper
+///
+/// - A header row of 8x8 cells names the layout, each of its 3 bits is
+///   sent as a pair of opposite cells so it reads at any contrast
+/// - Below it, the timestamp is sent as in the `reed-solomon` codec with
+///   the cell size and parity of the layout
+/// - The layout comes from the configuration, the stamper element
+///   updates it from receiver feedback; it is shrunk to fit the region
+pub struct AdaptiveStamper;
+
+impl Default for AdaptiveStamper {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampStamper for AdaptiveStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        let index = largest_fitting(config.width, config.height)
+            .ok_or(FlowError::NotSupported)?
+            .min(config.tuning.adaptive_layout);
+        let layout = LAYOUTS[index];
+        let header =
+            header_grid(config.x, config.y, config.width).ok_or(FlowError::NotSupported)?;
+        let payload = payload_grid(config.x, config.y, config.width, config.height, layout)
+            .ok_or(FlowError::NotSupported)?;
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let symbols = encode_symbols(timestamp_usecs, layout.parity_symbols, config.interleave);
+
+        let luma = LumaPlane::new(&**frame)?.scaled(config.scale);
+        let data = &mut frame.planes_data_mut();
+        let levels = config.fill.levels();
+        header.stamp(&luma, data, header_bits(index), levels);
+        payload.stamp(&luma, data, symbol_bits(&symbols), levels);
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "adaptive"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reed-Solomon stamper with a self-described layout sized by receiver feedback"
+    }
+}
+
+/// Adaptive reader
+///
+/// Reads the layout from the header, the header cells also give the
+/// decision levels of the payload. Reports the mean distance of the
+/// payload cells from the decision level as confidence.
+pub struct AdaptiveReader;
+
+impl Default for AdaptiveReader {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl AdaptiveReader {
+    fn decode(
+        &self,
+        frame: &VideoFrameRef<&BufferRef>,
+        config: &ReaderConfig,
+    ) -> Result<Option<(u64, f64)>, FlowError> {
+        let Some(header) = header_grid(config.x, config.y, config.width) else {
+            return Ok(None);
+        };
+
+        let luma = LumaPlane::new(frame)?
+            .scaled(config.scale)
+            .oriented(config.orientation);
+        let data = &frame.planes_data();
+        let Some(cells) = header.sample(&luma, data, 2 * HEADER_BITS) else {
+            return Ok(None);
+        };
+
+        // Compare each pair with the palette's polarity
+        let palette = config.fill.levels();
+        let mut index = 0;
+        let mut known = Vec::with_capacity(cells.len());
+        for pair in cells.chunks(2) {
+            let difference = palette.soft(pair[0] as u32) - palette.soft(pair[1] as u32);
+            if difference.abs() < HEADER_CONTRAST {
+                return Ok(None);
+            }
+            let bit = difference > 0;
+            index = (index << 1) | bit as usize;
+            known.push((pair[0] as u32, bit == (palette.white > palette.black)));
+            known.push((pair[1] as u32, bit != (palette.white > palette.black)));
+        }
+
+        let layout = LAYOUTS[index];
+        let Some(payload) = payload_grid(config.x, config.y, config.width, config.height, layout)
+        else {
+            return Ok(None);
+        };
+        let count = codeword_len(layout.parity_symbols) * 8;
+        let Some(samples) = payload.sample(&luma, data, count) else {
+            return Ok(None);
+        };
+
+        // Header cells were stamped at the palette levels, their
+        // brightness order tells which one was high
+        let estimated = Levels::estimate(known).map(|levels| {
+            if palette.white > palette.black {
+                levels
+            } else {
+                Levels {
+                    black: levels.white,
+                    white: levels.black,
+                }
+            }
+        });
+        let levels = config.tuning.levels(estimated, palette);
+
+        let Some(timestamp) =
+            decode_samples(&samples, layout.parity_symbols, config.interleave, levels)
+        else {
+            return Ok(None);
+        };
+
+        let confidence = samples
+            .iter()
+            .map(|&mean| levels.soft(mean as u32).abs().min(FULL_CONFIDENCE) as f64)
+            .sum::<f64>()
+            / (samples.len() as f64 * FULL_CONFIDENCE as f64);
+        if config
+            .tuning
+            .min_confidence
+            .is_some_and(|min_confidence| confidence < min_confidence as f64)
+        {
+            return Ok(None);
+        }
+
+        Ok(Some((timestamp, confidence)))
+    }
+}
+
+impl TimestampReader for AdaptiveReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        Ok(self.decode(frame, config)?.map(|(timestamp, _)| timestamp))
+    }
+
+    fn read_with_precision(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<Reading>, FlowError> {
+        Ok(self
+            .decode(frame, config)?
+            .map(|(timestamp, confidence)| Reading {
+                timestamp,
+                reduced_precision: false,
+                confidence: Some(confidence),
+            }))
+    }
+
+    fn name(&self) -> &'static str {
+        "adaptive"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reed-Solomon reader following the layout named in the code"
+    }
+}
+
+/// Success rate below which the code grows one step
+const GROW_BELOW: f64 = 0.95;
+
+/// Success rate and confidence above which a report counts towards
+/// shrinking the code
+const SHRINK_SUCCESS: f64 = 0.99;
+const SHRINK_CONFIDENCE: f64 = 0.6;
+
+/// Consecutive good reports before the code shrinks one step
+const SHRINK_REPORTS: u32 = 3;
+
+/// Picks the layout of the adaptive stamper from feedback reports.
+///
+/// Grows the code as soon as a report shows failures, shrinks it only
+/// after several clean reports, so it settles on the smallest layout
+/// that decodes reliably instead of oscillating around it. The report
+/// following a change is skipped, it still covers frames stamped with
+/// the previous layout.
+#[derive(Debug)]
+pub struct LayoutController {
+    layout: usize,
+    good_reports: u32,
+    settling: bool,
+}
+
+impl Default for LayoutController {
+    fn default() -> Self {
+        Self {
+            layout: DEFAULT_LAYOUT,
+            good_reports: 0,
+            settling: false,
+        }
+    }
+}
+
+impl LayoutController {
+    /// Current layout index
+    pub fn layout(&self) -> usize {
+        self.layout
+    }
+
+    /// Account for a report, `largest` is the most robust layout the
+    /// region fits. Returns the new layout index if it changed.
+    pub fn report(&mut self, feedback: &Feedback, largest: usize) -> Option<usize> {
+        if std::mem::take(&mut self.settling) {
+            return None;
+        }
+
+        let target = if feedback.success_rate < GROW_BELOW {
+            self.good_reports = 0;
+            self.layout + 1
+        } else if feedback.success_rate >= SHRINK_SUCCESS
+            && feedback
+                .confidence
+                .is_none_or(|confidence| confidence >= SHRINK_CONFIDENCE)
+        {
+            self.good_reports += 1;
+            if self.good_reports < SHRINK_REPORTS {
+                return None;
+            }
+            self.good_reports = 0;
+            self.layout.saturating_sub(1)
+        } else {
+            self.good_reports = 0;
+            return None;
+        };
+
+        let target = target.min(largest);
+        if target == self.layout {
+            return None;
+        }
+
+        self.layout = target;
+        self.settling = true;
+        Some(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feedback(success_rate: f64, confidence: Option<f64>) -> Feedback {
+        Feedback {
+            frames: 30,
+            success_rate,
+            confidence,
+        }
+    }
+
+    const LARGEST: usize = LAYOUTS.len() - 1;
+
+    #[test]
+    fn grows_below_the_success_threshold() {
+        let mut controller = LayoutController::default();
+        assert_eq!(
+            controller.report(&feedback(GROW_BELOW, None), LARGEST),
+            None
+        );
+        assert_eq!(
+            controller.report(&feedback(0.9, Some(0.9)), LARGEST),
+            Some(DEFAULT_LAYOUT + 1)
+        );
+        assert_eq!(controller.layout(), DEFAULT_LAYOUT + 1);
+    }
+
+    #[test]
+    fn skips_the_report_after_a_change() {
+        let mut controller = LayoutController::default();
+        let failing = feedback(0.5, None);
+        assert_eq!(
+            controller.report(&failing, LARGEST),
+            Some(DEFAULT_LAYOUT + 1)
+        );
+        assert_eq!(controller.report(&failing, LARGEST), None);
+        assert_eq!(controller.layout(), DEFAULT_LAYOUT + 1);
+        assert_eq!(
+            controller.report(&failing, LARGEST),
+            Some(DEFAULT_LAYOUT + 2)
+        );
+    }
+
+    #[test]
+    fn shrinks_after_consecutive_clean_reports() {
+        let mut controller = LayoutController::default();
+        let clean = feedback(1.0, Some(0.9));
+        for _ in 1..SHRINK_REPORTS {
+            assert_eq!(controller.report(&clean, LARGEST), None);
+        }
+        assert_eq!(controller.report(&clean, LARGEST), Some(DEFAULT_LAYOUT - 1));
+
+        // Settling, then the count starts over
+        assert_eq!(controller.report(&clean, LARGEST), None);
+        for _ in 1..SHRINK_REPORTS {
+            assert_eq!(controller.report(&clean, LARGEST), None);
+        }
+        assert_eq!(controller.report(&clean, LARGEST), Some(DEFAULT_LAYOUT - 2));
+    }
+
+    #[test]
+    fn doubtful_reports_restart_the_shrink_count() {
+        let mut controller = LayoutController::default();
+        let clean = feedback(1.0, None);
+        for doubtful in [
+            feedback(SHRINK_SUCCESS - 0.01, None),
+            feedback(1.0, Some(SHRINK_CONFIDENCE - 0.1)),
+        ] {
+            for _ in 1..SHRINK_REPORTS {
+                assert_eq!(controller.report(&clean, LARGEST), None);
+            }
+            assert_eq!(controller.report(&doubtful, LARGEST), None);
+        }
+        assert_eq!(controller.layout(), DEFAULT_LAYOUT);
+    }
+
+    #[test]
+    fn stays_within_the_layouts_that_fit() {
+        let mut controller = LayoutController::default();
+        let failing = feedback(0.0, None);
+        assert_eq!(controller.report(&failing, DEFAULT_LAYOUT), None);
+        assert_eq!(controller.layout(), DEFAULT_LAYOUT);
+
+        // The region shrank below the current layout
+        assert_eq!(controller.report(&failing, 1), Some(1));
+
+        let mut controller = LayoutController::default();
+        let clean = feedback(1.0, None);
+        // Each step takes its clean reports plus the one skipped after it
+        for _ in 0..DEFAULT_LAYOUT as u32 * (SHRINK_REPORTS + 1) {
+            controller.report(&clean, LARGEST);
+        }
+        assert_eq!(controller.layout(), 0);
+        for _ in 0..2 * SHRINK_REPORTS {
+            assert_eq!(controller.report(&clean, LARGEST), None);
+        }
+    }
+}
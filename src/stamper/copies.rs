@@ -0,0 +1,327 @@
+// Redundant copies of the code region
+//
+// The stamper writes the code once and copies its pixels to further
+// locations, so every copy carries the same payload whatever the
+// codec. The reader decodes all copies and votes, so an overlay on one
+// copy does not lose the frame.
+
+use super::{
+    placement::{Anchor, Region},
+    traits::Reading,
+};
+use glib::prelude::*;
+use gst::{BufferRef, FlowError};
+use gst_video::{prelude::*, VideoFrameRef};
+
+/// One copy per anchor at most
+pub const MAX_COPIES: u32 = 9;
+
+/// Anchors of automatic copies, corners first and the opposite corner
+/// before the adjacent ones, so copies are spread over the frame
+const AUTO_ANCHORS: [Anchor; MAX_COPIES as usize] = [
+    Anchor::TopLeft,
+    Anchor::BottomRight,
+    Anchor::TopRight,
+    Anchor::BottomLeft,
+    Anchor::Center,
+    Anchor::TopCenter,
+    Anchor::BottomCenter,
+    Anchor::CenterLeft,
+    Anchor::CenterRight,
+];
+
+/// Parse a comma-separated list of anchor nicks, e.g.
+/// `"top-right,bottom-left"`
+pub fn parse_anchors(list: &str) -> Result<Vec<Anchor>, String> {
+    let class = glib::EnumClass::with_type(Anchor::static_type()).unwrap();
+
+    list.split(',')
+        .map(str::trim)
+        .filter(|nick| !nick.is_empty())
+        .map(|nick| {
+            class
+                .to_value_by_nick(nick)
+                .and_then(|value| value.get().ok())
+                .ok_or_else(|| format!("unknown anchor {:?}", nick))
+        })
+        .collect()
+}
+
+/// Anchors of `count` copies, the primary one first, then the
+/// configured ones and automatic ones for the rest. Each anchor is used
+/// once.
+pub fn copy_anchors(primary: Anchor, count: u32, configured: &[Anchor]) -> Vec<Anchor> {
+    let mut anchors = vec![primary];
+
+    for &anchor in configured.iter().chain(&AUTO_ANCHORS) {
+        if anchors.len() >= count as usize {
+            break;
+        }
+        if !anchors.contains(&anchor) {
+            anchors.push(anchor);
+        }
+    }
+
+    anchors
+}
+
+/// Chroma grid of the most subsampled formats the elements accept,
+/// 4x4 for YUV9
+const CHROMA_GRID: u32 = 4;
+
+/// Move `value` by less than the grid onto the grid phase of `phase`,
+/// down unless that would leave the frame
+fn align_to(value: u32, phase: u32) -> u32 {
+    let offset = (value + CHROMA_GRID - phase % CHROMA_GRID) % CHROMA_GRID;
+    if value >= offset {
+        value - offset
+    } else {
+        value + CHROMA_GRID - offset
+    }
+}
+
+fn overlaps(a: &Region, b: &Region) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+/// Arrange the resolved regions, the primary one first, for copying.
+///
+/// Copies are moved onto the chroma grid phase of the primary region,
+/// so `copy_rect` moves whole chroma samples and lands the code where
+/// the reader looks for it. Copies overlapping an earlier region or one
+/// of the `reserved` ones, e.g. the content hash, would overwrite its
+/// code and are dropped. Returns the regions kept and the copies
+/// dropped.
+pub fn arrange(regions: &[Region], reserved: &[Region]) -> (Vec<Region>, Vec<Region>) {
+    let Some((primary, copies)) = regions.split_first() else {
+        return (vec![], vec![]);
+    };
+
+    let mut kept = vec![*primary];
+    let mut dropped = vec![];
+    for copy in copies {
+        let copy = Region {
+            x: align_to(copy.x, primary.x),
+            y: align_to(copy.y, primary.y),
+            ..*copy
+        };
+        if kept
+            .iter()
+            .chain(reserved)
+            .any(|region| overlaps(region, &copy))
+        {
+            dropped.push(copy);
+        } else {
+            kept.push(copy);
+        }
+    }
+    (kept, dropped)
+}
+
+/// Copy the frame rectangle `(x, y, width, height)` to `(to_x, to_y)`
+/// on all planes, clipped to the frame.
+///
+/// Positions are rounded down to the chroma subsampling so copies keep
+/// their colour, which moves a copy unless it is on the grid phase of
+/// the source, see [`arrange`].
+pub fn copy_rect(
+    frame: &mut VideoFrameRef<&mut BufferRef>,
+    (x, y, width, height): (usize, usize, usize, usize),
+    (to_x, to_y): (usize, usize),
+) -> Result<(), FlowError> {
+    let fmt = frame.format_info();
+    let components = 0..fmt.n_components() as usize;
+
+    let align_x = components
+        .clone()
+        .map(|comp| 1 << fmt.w_sub()[comp])
+        .max()
+        .unwrap_or(1);
+    let align_y = components
+        .clone()
+        .map(|comp| 1 << fmt.h_sub()[comp])
+        .max()
+        .unwrap_or(1);
+    // Grow the rectangle by what rounding takes off its origin
+    let (width, height) = (width + x % align_x, height + y % align_y);
+    let (x, to_x) = (x - x % align_x, to_x - to_x % align_x);
+    let (y, to_y) = (y - y % align_y, to_y - to_y % align_y);
+
+    let frame_width = frame.width() as usize;
+    let frame_height = frame.height() as usize;
+    let width = width
+        .min(frame_width.saturating_sub(x))
+        .min(frame_width.saturating_sub(to_x));
+    let height = height
+        .min(frame_height.saturating_sub(y))
+        .min(frame_height.saturating_sub(to_y));
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    for plane in 0..fmt.n_planes() {
+        // The first component on the plane gives its sampling, packed
+        // formats interleave the others within its pixel stride
+        let Some(comp) = components.clone().find(|&comp| fmt.plane()[comp] == plane) else {
+            continue;
+        };
+        if fmt.depth()[comp] != 8 {
+            return Err(FlowError::NotSupported);
+        }
+
+        let (w_sub, h_sub) = (fmt.w_sub()[comp], fmt.h_sub()[comp]);
+        let pixel_stride = fmt.pixel_stride()[comp] as usize;
+        let stride = frame.plane_stride()[plane as usize] as usize;
+
+        let start = |value: usize, sub: u32| value >> sub;
+        let end = |value: usize, sub: u32| (value + (1 << sub) - 1) >> sub;
+        let len = (end(x + width, w_sub) - start(x, w_sub)) * pixel_stride;
+        let rows = end(y + height, h_sub) - start(y, h_sub);
+
+        let data = frame.plane_data_mut(plane).unwrap();
+        for row in 0..rows {
+            let src = (start(y, h_sub) + row) * stride + start(x, w_sub) * pixel_stride;
+            let dst = (start(to_y, h_sub) + row) * stride + start(to_x, w_sub) * pixel_stride;
+            if src.max(dst) + len <= data.len() {
+                data.copy_within(src..src + len, dst);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reading agreed on by most copies.
+///
+/// Full precision readings win ties over reduced precision ones, then
+/// the earlier copy wins, the primary one first.
+pub fn vote(readings: &[Option<Reading>]) -> Option<Reading> {
+    let votes = |timestamp: u64| {
+        readings
+            .iter()
+            .flatten()
+            .filter(|reading| reading.timestamp == timestamp)
+            .count()
+    };
+
+    readings
+        .iter()
+        .enumerate()
+        .filter_map(|(index, reading)| reading.map(|reading| (index, reading)))
+        .max_by_key(|&(index, reading)| {
+            (
+                votes(reading.timestamp),
+                !reading.reduced_precision,
+                std::cmp::Reverse(index),
+            )
+        })
+        .map(|(_, reading)| reading)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing;
+    use gst_video::VideoFormat;
+
+    fn region(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn drops_copies_over_earlier_regions() {
+        let (kept, dropped) = arrange(
+            &[
+                region(0, 0, 64, 64),
+                region(32, 32, 64, 64),
+                region(100, 0, 64, 64),
+                region(140, 0, 64, 64),
+                region(0, 100, 64, 64),
+            ],
+            &[],
+        );
+        assert_eq!(
+            kept,
+            [
+                region(0, 0, 64, 64),
+                region(100, 0, 64, 64),
+                region(0, 100, 64, 64)
+            ]
+        );
+        assert_eq!(dropped, [region(32, 32, 64, 64), region(140, 0, 64, 64)]);
+    }
+
+    #[test]
+    fn drops_copies_over_reserved_regions() {
+        let hash = region(0, 64, 48, 24);
+        let (kept, dropped) = arrange(
+            &[
+                region(0, 0, 64, 64),
+                region(0, 80, 64, 64),
+                region(100, 0, 64, 64),
+            ],
+            &[hash],
+        );
+        assert_eq!(kept, [region(0, 0, 64, 64), region(100, 0, 64, 64)]);
+        assert_eq!(dropped, [region(0, 80, 64, 64)]);
+    }
+
+    #[test]
+    fn moves_copies_onto_the_grid_phase_of_the_primary() {
+        let (kept, _) = arrange(
+            &[
+                region(5, 3, 16, 16),
+                region(102, 3, 16, 16),
+                region(0, 40, 16, 16),
+            ],
+            &[],
+        );
+        assert_eq!(
+            kept,
+            [
+                region(5, 3, 16, 16),
+                region(101, 3, 16, 16),
+                region(1, 39, 16, 16)
+            ]
+        );
+    }
+
+    #[test]
+    fn copies_land_where_they_are_read() {
+        let (info, mut buffer) = testing::frame(VideoFormat::I420, 128, 64, 128);
+        let mut frame =
+            VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), &info).unwrap();
+        let stride = frame.plane_stride()[0] as usize;
+        let luma = |x: usize, y: usize| (x * 7 + y * 13) as u8;
+
+        let primary = region(5, 3, 16, 16);
+        let data = frame.plane_data_mut(0).unwrap();
+        for y in 0..16 {
+            for x in 0..16 {
+                data[(3 + y) * stride + 5 + x] = luma(x, y);
+            }
+        }
+
+        let (kept, _) = arrange(&[primary, region(70, 30, 16, 16)], &[]);
+        let copy = kept[1];
+        copy_rect(
+            &mut frame,
+            (5, 3, 16, 16),
+            (copy.x as usize, copy.y as usize),
+        )
+        .unwrap();
+
+        let data = frame.plane_data(0).unwrap();
+        for y in 0..16 {
+            for x in 0..16 {
+                let at = (copy.y as usize + y) * stride + copy.x as usize + x;
+                assert_eq!(data[at], luma(x, y), "{},{}", x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// Automatic codec detection for the measure element
+
+use super::{
+    create_reader,
+    traits::{ReaderConfig, Reading},
+    StamperType, TimestampReader,
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Codecs probed in `auto` mode, in order of preference.
+///
+/// `Original` comes last because it has no markers or checksum and
+/// decodes any picture into some value; it is only accepted through
+/// the plausibility check. `Optical` is not probed: it searches the
+/// whole frame, too costly to run alongside every other codec.
+const PROBE_ORDER: [StamperType; 11] = [
+    StamperType::Optimized,
+    StamperType::FastRobust,
+    StamperType::ReedSolomon,
+    StamperType::Differential,
+    StamperType::Pam4,
+    StamperType::Multichannel,
+    StamperType::Hierarchical,
+    StamperType::Temporal,
+    StamperType::Adaptive,
+    StamperType::Watermark,
+    StamperType::Original,
+];
+
+/// Consecutive valid decodes required before locking onto a codec
+const LOCK_FRAMES: u32 = 3;
+
+/// Consecutive failed decodes after which the detector re-probes
+const REPROBE_FRAMES: u32 = 30;
+
+/// Largest latency accepted as a plausible decode (10 seconds)
+const MAX_PLAUSIBLE_LATENCY_USECS: u64 = 10_000_000;
+
+/// Largest amount a stamp may be ahead of the local clock, clocks
+/// synchronised over NTP or PTP still differ by up to milliseconds
+const MAX_CLOCK_SKEW_USECS: u64 = 1_000_000;
+
+/// Largest spacing of consecutive decodes counted towards a lock, one
+/// frame at 1 fps
+const MAX_FRAME_INTERVAL_USECS: u64 = 1_000_000;
+
+/// Events raised when the detector changes state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectorEvent {
+    /// A codec decoded reliably and is now used exclusively
+    Locked(StamperType),
+    /// The locked codec failed for a sustained period, probing again
+    Lost(StamperType),
+}
+
+/// Outcome of reading one frame through the detector
+#[derive(Debug, Default)]
+pub struct Detection {
+    pub reading: Option<Reading>,
+    pub event: Option<DetectorEvent>,
+    /// Readers that returned an error on this frame, e.g. on a format
+    /// their codec does not support; counted as misses
+    pub errors: Vec<(StamperType, FlowError)>,
+}
+
+enum State {
+    Probing {
+        hits: [u32; PROBE_ORDER.len()],
+        /// Timestamp of each candidate's last counted decode, valid
+        /// while it has hits
+        last: [u64; PROBE_ORDER.len()],
+    },
+    Locked {
+        index: usize,
+        failures: u32,
+    },
+}
+
+impl State {
+    fn probing() -> Self {
+        State::Probing {
+            hits: [0; PROBE_ORDER.len()],
+            last: [0; PROBE_ORDER.len()],
+        }
+    }
+}
+
+/// Picks the reader matching the incoming stamps.
+///
+/// While probing, every candidate reader is tried on each frame and
+/// its result is validated against the local clock. Once a reader
+/// yields `LOCK_FRAMES` valid timestamps in a row, each following the
+/// previous one within a frame interval, the detector locks onto it.
+/// After `REPROBE_FRAMES` consecutive failures it goes back to
+/// probing.
+///
+/// A reader error only concerns its own codec, e.g. a format it does
+/// not support, so it counts as a miss and is reported alongside the
+/// result instead of failing the frame.
+pub struct CodecDetector {
+    readers: Vec<Box<dyn TimestampReader>>,
+    state: State,
+}
+
+impl Default for CodecDetector {
+    fn default() -> Self {
+        Self {
+            readers: PROBE_ORDER.iter().map(|&ty| create_reader(ty)).collect(),
+            state: State::probing(),
+        }
+    }
+}
+
+impl CodecDetector {
+    /// Forget the locked codec and start probing again
+    pub fn reset(&mut self) {
+        self.state = State::probing();
+    }
+
+    pub fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Detection {
+        let now_usecs = clock.time().unwrap().useconds();
+        let mut errors = Vec::new();
+
+        match &mut self.state {
+            State::Probing { hits, last } => {
+                let mut decoded = [None; PROBE_ORDER.len()];
+
+                for (index, reader) in self.readers.iter_mut().enumerate() {
+                    let reading = match reader.read_with_precision(frame, clock, config) {
+                        Ok(reading) => reading,
+                        Err(err) => {
+                            errors.push((PROBE_ORDER[index], err));
+                            None
+                        }
+                    };
+
+                    match reading {
+                        Some(reading) if is_plausible(reading.timestamp, now_usecs) => {
+                            hits[index] =
+                                if hits[index] > 0 && follows(last[index], reading.timestamp) {
+                                    hits[index] + 1
+                                } else {
+                                    1
+                                };
+                            last[index] = reading.timestamp;
+                            decoded[index] = Some(reading);
+                        }
+                        _ => hits[index] = 0,
+                    }
+                }
+
+                let Some(index) = hits.iter().position(|&count| count >= LOCK_FRAMES) else {
+                    return Detection {
+                        reading: decoded.into_iter().flatten().next(),
+                        event: None,
+                        errors,
+                    };
+                };
+
+                self.state = State::Locked { index, failures: 0 };
+
+                Detection {
+                    reading: decoded[index],
+                    event: Some(DetectorEvent::Locked(PROBE_ORDER[index])),
+                    errors,
+                }
+            }
+            State::Locked { index, failures } => {
+                let ty = PROBE_ORDER[*index];
+                let reading =
+                    match self.readers[*index].read_with_precision(frame, clock, config) {
+                        Ok(reading) => reading,
+                        Err(err) => {
+                            errors.push((ty, err));
+                            None
+                        }
+                    }
+                    .filter(|reading| is_plausible(reading.timestamp, now_usecs));
+
+                if reading.is_some() {
+                    *failures = 0;
+                    return Detection {
+                        reading,
+                        event: None,
+                        errors,
+                    };
+                }
+
+                *failures += 1;
+                if *failures < REPROBE_FRAMES {
+                    return Detection {
+                        errors,
+                        ..Detection::default()
+                    };
+                }
+
+                self.reset();
+                Detection {
+                    reading: None,
+                    event: Some(DetectorEvent::Lost(ty)),
+                    errors,
+                }
+            }
+        }
+    }
+
+    /// Read with the locked codec without counting towards detection,
+    /// e.g. further copies of the code in a frame already read.
+    /// Nothing is read while probing.
+    pub fn read_locked(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Detection {
+        let State::Locked { index, .. } = self.state else {
+            return Detection::default();
+        };
+
+        let now_usecs = clock.time().unwrap().useconds();
+        match self.readers[index].read_with_precision(frame, clock, config) {
+            Ok(reading) => Detection {
+                reading: reading.filter(|reading| is_plausible(reading.timestamp, now_usecs)),
+                ..Detection::default()
+            },
+            Err(err) => Detection {
+                errors: vec![(PROBE_ORDER[index], err)],
+                ..Detection::default()
+            },
+        }
+    }
+}
+
+/// A decoded timestamp is plausible if it is at most
+/// `MAX_CLOCK_SKEW_USECS` in the future and not older than
+/// `MAX_PLAUSIBLE_LATENCY_USECS`.
+fn is_plausible(stamped_usecs: u64, now_usecs: u64) -> bool {
+    (now_usecs.saturating_sub(MAX_PLAUSIBLE_LATENCY_USECS)
+        ..=now_usecs.saturating_add(MAX_CLOCK_SKEW_USECS))
+        .contains(&stamped_usecs)
+}
+
+/// Whether a decode continues the sequence of the previous one: not
+/// going backwards and at most a frame interval later. Repeated frames
+/// carry the same stamp.
+fn follows(last_usecs: u64, stamped_usecs: u64) -> bool {
+    stamped_usecs
+        .checked_sub(last_usecs)
+        .is_some_and(|spacing| spacing <= MAX_FRAME_INTERVAL_USECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::{fast_robust::FastRobustStamper, testing, traits::StamperConfig};
+    use gst::SystemClock;
+    use gst_video::VideoFormat;
+
+    #[test]
+    fn accepts_stamps_slightly_ahead_of_the_clock() {
+        let now = 100_000_000;
+        assert!(is_plausible(now - MAX_PLAUSIBLE_LATENCY_USECS, now));
+        assert!(is_plausible(now + 500, now));
+        assert!(is_plausible(now + MAX_CLOCK_SKEW_USECS, now));
+        assert!(!is_plausible(now + MAX_CLOCK_SKEW_USECS + 1, now));
+        assert!(!is_plausible(now - MAX_PLAUSIBLE_LATENCY_USECS - 1, now));
+    }
+
+    #[test]
+    fn locks_only_on_a_sequence_of_frames() {
+        let start = 100_000_000;
+        assert!(follows(start, start));
+        assert!(follows(start, start + 33_333));
+        assert!(!follows(start, start - 1));
+        assert!(!follows(start, start + MAX_FRAME_INTERVAL_USECS + 1));
+    }
+
+    #[test]
+    fn reader_errors_count_as_misses() {
+        // `original` does not read gray frames, `fast-robust` does
+        gst::init().unwrap();
+        let config = StamperConfig::default();
+        let reader_config = ReaderConfig::default();
+        let clock = SystemClock::obtain();
+        let mut detector = CodecDetector::default();
+
+        for frame_index in 1..=LOCK_FRAMES {
+            let mut frame = testing::frame(VideoFormat::Gray8, 64, 64, 128);
+            let stamped = testing::stamp(&mut FastRobustStamper, &mut frame, &config);
+            let (info, buffer) = &frame;
+            let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).unwrap();
+            let detection = detector.read(&frame, &clock, &reader_config);
+
+            assert_eq!(
+                detection.reading.map(|reading| reading.timestamp),
+                Some(stamped)
+            );
+            assert!(detection
+                .errors
+                .iter()
+                .any(|(stamper_type, _)| *stamper_type == StamperType::Original));
+            let expected = (frame_index == LOCK_FRAMES)
+                .then_some(DetectorEvent::Locked(StamperType::FastRobust));
+            assert_eq!(detection.event, expected);
+        }
+    }
+}
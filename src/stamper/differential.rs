@@ -0,0 +1,189 @@
+// Differential (Manchester) cell encoding, independent of absolute levels
+//
+// Every bit is a pair of adjacent cells, bright then dark for a one and
+// dark then bright for a zero. The reader only compares both halves, so
+// brightness, contrast or gamma changes that keep the order of luma
+// values cannot flip a bit. The payload is the Reed-Solomon codeword of
+// the `reed-solomon` codec.
+
+use super::{
+    grid::CellGrid,
+    plane::LumaPlane,
+    reed_solomon::{codeword_len, decode_symbols, encode_symbols, symbol_bits},
+    traits::{ReaderConfig, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Pixels per cell in each direction, two cells per bit
+const CELL_SIZE: usize = 4;
+
+/// Grid holding the codeword, or `None` if it does not fit the region
+fn code_grid(x: u32, y: u32, width: u32, height: u32, parity: usize) -> Option<CellGrid> {
+    let grid = CellGrid::fit(x, y, width, height, CELL_SIZE);
+    (codeword_len(parity) * 16 <= grid.capacity()).then_some(grid)
+}
+
+/// Whether the code with `parity` parity symbols fits a region of the
+/// given size
+pub fn fits(width: u32, height: u32, parity: usize) -> bool {
+    code_grid(0, 0, width, height, parity).is_some()
+}
+
+/// Differential stamper
+///
+/// - Sends the Reed-Solomon codeword of the 64-bit timestamp
+/// - Each bit is a pair of 4x4 pixel cells of opposite luma
+/// - 6 parity symbols need 224 cells, 64x56 pixels
+pub struct DifferentialStamper;
+
+impl Default for DifferentialStamper {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampStamper for DifferentialStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let grid = code_grid(config.x, config.y, config.width, config.height, parity)
+            .ok_or(FlowError::NotSupported)?;
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let symbols = encode_symbols(timestamp_usecs, parity, config.interleave);
+
+        let luma = LumaPlane::new(&**frame)?.scaled(config.scale);
+        let data = &mut frame.planes_data_mut();
+        let cells = symbol_bits(&symbols).flat_map(|bit| [bit, !bit]);
+        grid.stamp(&luma, data, cells, config.fill.levels());
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "differential"
+    }
+
+    fn description(&self) -> &'static str {
+        "Differential cell pair stamper independent of brightness and gamma"
+    }
+}
+
+/// Differential reader
+pub struct DifferentialReader;
+
+impl Default for DifferentialReader {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampReader for DifferentialReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let Some(grid) = code_grid(config.x, config.y, config.width, config.height, parity) else {
+            return Ok(None);
+        };
+
+        let luma = LumaPlane::new(frame)?
+            .scaled(config.scale)
+            .oriented(config.orientation);
+        let data = &frame.planes_data();
+        let Some(samples) = grid.sample(&luma, data, codeword_len(parity) * 16) else {
+            return Ok(None);
+        };
+
+        // Signed difference of each cell pair, the sign is the bit
+        let diffs: Vec<i32> = samples
+            .chunks(2)
+            .map(|pair| pair[0] as i32 - pair[1] as i32)
+            .collect();
+
+        let symbols: Vec<u8> = diffs
+            .chunks(8)
+            .map(|bits| {
+                bits.iter()
+                    .fold(0u8, |byte, &diff| (byte << 1) | (diff > 0) as u8)
+            })
+            .collect();
+
+        // Half the difference is the distance of a single-cell reading
+        // from the decision level, the scale the erasure margin expects
+        let margins: Vec<u8> = diffs
+            .chunks(8)
+            .map(|bits| {
+                bits.iter()
+                    .map(|diff| (diff.unsigned_abs() / 2) as u8)
+                    .min()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        Ok(decode_symbols(
+            &symbols,
+            &margins,
+            parity,
+            config.interleave,
+        ))
+    }
+
+    fn name(&self) -> &'static str {
+        "differential"
+    }
+
+    fn description(&self) -> &'static str {
+        "Differential cell pair reader comparing both halves of each bit"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing::{self, Luma};
+    use gst_video::VideoFormat;
+
+    /// Stamp a 64x64 code, impair its luma and tell whether the reader
+    /// got the value back
+    fn decodes(impair: impl Fn(&mut Luma)) -> bool {
+        let mut frame = testing::frame(VideoFormat::I420, 64, 64, 128);
+        let stamped = testing::stamp(
+            &mut DifferentialStamper,
+            &mut frame,
+            &StamperConfig::default(),
+        );
+        let mut luma = testing::luma(&mut frame);
+        impair(&mut luma);
+        testing::set_luma(&mut frame, &luma);
+
+        testing::read(&mut DifferentialReader, &frame, &ReaderConfig::default()) == Some(stamped)
+    }
+
+    #[test]
+    fn decodes_a_clean_frame() {
+        assert!(decodes(|_| ()));
+    }
+
+    #[test]
+    fn ignores_gain_and_offset() {
+        // Both levels far above any fixed threshold
+        assert!(decodes(|luma| luma.transfer(0.25, 180.0)));
+        // Both below it
+        assert!(decodes(|luma| luma.transfer(0.2, 0.0)));
+    }
+
+    #[test]
+    fn ignores_gamma() {
+        assert!(decodes(|luma| luma.gamma(2.2)));
+        assert!(decodes(|luma| luma.gamma(0.4)));
+    }
+}
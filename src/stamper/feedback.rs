@@ -0,0 +1,261 @@
+// Decode statistics reported by the measure element back to the stamper
+//
+// The measure element summarises how well codes decoded over a window
+// of frames and sends the summary as a serialized `GstStructure` in a
+// UDP datagram. The stamper of the `adaptive` codec sizes its code from
+// these reports. Datagrams are small, self-describing and ignored when
+// lost or malformed, so any sender can stand in for the measure
+// element, e.g. `gst-launch-1.0 ... udpsink` or a test script.
+
+use super::traits::Reading;
+
+/// Name of the structure carried in a feedback datagram
+pub const STRUCTURE_NAME: &str = "tslatency-feedback";
+
+/// Frames summarised by one report
+pub const REPORT_FRAMES: u32 = 30;
+
+/// Decode statistics over a window of frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Feedback {
+    /// Frames in the window
+    pub frames: u32,
+    /// Fraction of those frames a timestamp was decoded from
+    pub success_rate: f64,
+    /// Mean confidence of the decoded frames, `None` if the reader
+    /// does not measure it or nothing decoded
+    pub confidence: Option<f64>,
+}
+
+impl Feedback {
+    /// Serialize as the payload of a datagram
+    pub fn to_datagram(self) -> String {
+        let mut structure = gst::Structure::builder(STRUCTURE_NAME)
+            .field("frames", self.frames)
+            .field("success-rate", self.success_rate)
+            .build();
+        if let Some(confidence) = self.confidence {
+            structure.set("confidence", confidence);
+        }
+        structure.to_string()
+    }
+
+    /// Parse the payload of a datagram, `None` if it is not a report
+    pub fn from_datagram(payload: &[u8]) -> Option<Self> {
+        let structure = std::str::from_utf8(payload)
+            .ok()?
+            .trim_end_matches('\0')
+            .parse::<gst::Structure>()
+            .ok()?;
+        if structure.name() != STRUCTURE_NAME {
+            return None;
+        }
+
+        let frames = structure.get::<u32>("frames").ok()?;
+        let success_rate = structure.get::<f64>("success-rate").ok()?;
+        if frames == 0 || !(0.0..=1.0).contains(&success_rate) {
+            return None;
+        }
+
+        Some(Self {
+            frames,
+            success_rate,
+            confidence: structure
+                .get::<f64>("confidence")
+                .ok()
+                .map(|confidence| confidence.clamp(0.0, 1.0)),
+        })
+    }
+}
+
+/// Accumulates decode results into a report every `REPORT_FRAMES`
+#[derive(Debug, Default)]
+pub struct FeedbackWindow {
+    frames: u32,
+    decoded: u32,
+    confidence_sum: f64,
+    confidence_count: u32,
+}
+
+impl FeedbackWindow {
+    /// Account for the result of one frame, returns the report once the
+    /// window is full
+    pub fn record(&mut self, reading: Option<&Reading>) -> Option<Feedback> {
+        self.frames += 1;
+        if let Some(reading) = reading {
+            self.decoded += 1;
+            if let Some(confidence) = reading.confidence {
+                self.confidence_sum += confidence;
+                self.confidence_count += 1;
+            }
+        }
+
+        if self.frames < REPORT_FRAMES {
+            return None;
+        }
+
+        let feedback = Feedback {
+            frames: self.frames,
+            success_rate: self.decoded as f64 / self.frames as f64,
+            confidence: (self.confidence_count > 0)
+                .then(|| self.confidence_sum / self.confidence_count as f64),
+        };
+        *self = Self::default();
+        Some(feedback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, time::Duration};
+
+    fn reading(confidence: Option<f64>) -> Reading {
+        Reading {
+            timestamp: 0,
+            reduced_precision: false,
+            confidence,
+        }
+    }
+
+    #[test]
+    fn datagram_round_trip() {
+        gst::init().unwrap();
+        for feedback in [
+            Feedback {
+                frames: 30,
+                success_rate: 0.9,
+                confidence: Some(0.75),
+            },
+            Feedback {
+                frames: 1,
+                success_rate: 0.0,
+                confidence: None,
+            },
+        ] {
+            let datagram = feedback.to_datagram();
+            assert_eq!(Feedback::from_datagram(datagram.as_bytes()), Some(feedback));
+
+            // C senders may include the terminating NUL
+            let mut payload = datagram.into_bytes();
+            payload.push(0);
+            assert_eq!(Feedback::from_datagram(&payload), Some(feedback));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_datagrams() {
+        gst::init().unwrap();
+        for payload in [
+            &b""[..],
+            b"\xff\xfe",
+            b"not a structure",
+            b"other-report, frames=(uint)30, success-rate=(double)1",
+            b"tslatency-feedback, success-rate=(double)1",
+            b"tslatency-feedback, frames=(uint)30",
+            b"tslatency-feedback, frames=(string)thirty, success-rate=(double)1",
+            b"tslatency-feedback, frames=(uint)0, success-rate=(double)1",
+        ] {
+            assert_eq!(
+                Feedback::from_datagram(payload),
+                None,
+                "{}",
+                String::from_utf8_lossy(payload)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_success_rates_out_of_range() {
+        gst::init().unwrap();
+        for success_rate in [-0.1, 1.5, f64::NAN] {
+            let datagram = Feedback {
+                frames: 30,
+                success_rate,
+                confidence: None,
+            }
+            .to_datagram();
+            assert_eq!(
+                Feedback::from_datagram(datagram.as_bytes()),
+                None,
+                "{}",
+                success_rate
+            );
+        }
+    }
+
+    #[test]
+    fn clamps_confidence() {
+        gst::init().unwrap();
+        let payload =
+            b"tslatency-feedback, frames=(uint)30, success-rate=(double)1, confidence=(double)2";
+        assert_eq!(
+            Feedback::from_datagram(payload).unwrap().confidence,
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn window_reports_every_report_frames() {
+        let mut window = FeedbackWindow::default();
+        let mut reports = Vec::new();
+        for frame in 0..2 * REPORT_FRAMES {
+            // A third of the frames fail, confidence only on even frames
+            let confidence = (frame % 2 == 0).then_some(0.5 + (frame % 4) as f64 / 8.0);
+            let decoded = (frame % 3 != 0).then(|| reading(confidence));
+            if let Some(report) = window.record(decoded.as_ref()) {
+                reports.push((frame, report));
+            }
+        }
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].0, REPORT_FRAMES - 1);
+        assert_eq!(reports[1].0, 2 * REPORT_FRAMES - 1);
+        for (_, report) in &reports {
+            assert_eq!(report.frames, REPORT_FRAMES);
+            assert!((report.success_rate - 2.0 / 3.0).abs() < 1e-9);
+        }
+        // Decoded even frames alternate between 0.5 and 0.75
+        let confidence = reports[0].1.confidence.unwrap();
+        assert!((confidence - 0.625).abs() < 1e-9, "{}", confidence);
+    }
+
+    #[test]
+    fn window_reports_no_confidence_without_decoded_frames() {
+        let mut window = FeedbackWindow::default();
+        for _ in 1..REPORT_FRAMES {
+            assert_eq!(window.record(None), None);
+        }
+        assert_eq!(
+            window.record(Some(&reading(None))),
+            Some(Feedback {
+                frames: REPORT_FRAMES,
+                success_rate: 1.0 / REPORT_FRAMES as f64,
+                confidence: None,
+            })
+        );
+    }
+
+    #[test]
+    fn datagram_crosses_a_loopback_socket() {
+        gst::init().unwrap();
+        let receiver = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let sender = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+
+        let feedback = Feedback {
+            frames: REPORT_FRAMES,
+            success_rate: 0.5,
+            confidence: Some(0.25),
+        };
+        sender.send(feedback.to_datagram().as_bytes()).unwrap();
+
+        // Same buffer size as the stamper
+        let mut payload = [0u8; 512];
+        let len = receiver.recv(&mut payload).unwrap();
+        assert_eq!(Feedback::from_datagram(&payload[..len]), Some(feedback));
+    }
+}
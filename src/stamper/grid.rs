@@ -0,0 +1,85 @@
+// Square cell grids carrying one code bit per cell
+
+use super::{
+    levels::Levels,
+    plane::{LumaPlane, Planes, PlanesMut},
+};
+
+/// Luma of cells carrying a one bit with the default palette in
+/// limited range video
+pub const WHITE: u8 = 235;
+
+/// Luma of cells carrying a zero bit with the default palette in
+/// limited range video (not pure black, survives compression)
+pub const BLACK: u8 = 20;
+
+/// Square cells laid out row by row from `(x, y)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellGrid {
+    pub x: usize,
+    pub y: usize,
+    pub cell_size: usize,
+    pub cols: usize,
+    pub rows: usize,
+}
+
+impl CellGrid {
+    /// Fit as many cells of `cell_size` pixels as possible into a region
+    pub fn fit(x: u32, y: u32, width: u32, height: u32, cell_size: usize) -> Self {
+        Self {
+            x: x as usize,
+            y: y as usize,
+            cell_size,
+            cols: width as usize / cell_size,
+            rows: height as usize / cell_size,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    /// Top-left pixel of the cell at `index`
+    pub fn cell_origin(&self, index: usize) -> (usize, usize) {
+        (
+            self.x + (index % self.cols) * self.cell_size,
+            self.y + (index / self.cols) * self.cell_size,
+        )
+    }
+
+    /// Paint one cell per bit at the white or black level
+    pub fn stamp(
+        &self,
+        luma: &LumaPlane,
+        data: &mut PlanesMut,
+        bits: impl IntoIterator<Item = bool>,
+        levels: Levels,
+    ) {
+        for (index, bit) in bits.into_iter().enumerate().take(self.capacity()) {
+            let (x, y) = self.cell_origin(index);
+            let value = levels.value(bit);
+            luma.fill_rect(data, x, y, self.cell_size, self.cell_size, value);
+        }
+    }
+
+    /// Mean luma of the central half of the first `count` cells.
+    ///
+    /// Edges are skipped since compression blurs them into the
+    /// neighbouring cells. Returns `None` if a cell lies outside the
+    /// frame.
+    pub fn sample(&self, luma: &LumaPlane, data: &Planes, count: usize) -> Option<Vec<u8>> {
+        if count > self.capacity() {
+            return None;
+        }
+
+        let margin = self.cell_size / 4;
+        let size = (self.cell_size - 2 * margin).max(1);
+
+        (0..count)
+            .map(|index| {
+                let (x, y) = self.cell_origin(index);
+                luma.mean_rect(data, x + margin, y + margin, size, size)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,318 @@
+// Hierarchical coarse and fine code for graceful degradation
+//
+// The coarse layer sends the low 24 bits of the millisecond timestamp
+// in large cells that survive heavy compression. Below it, the fine
+// layer sends the microseconds within that millisecond in small cells.
+// When only the coarse layer decodes, the reader still returns a
+// millisecond-accurate timestamp flagged as reduced precision.
+//
+// The high bits are restored from the reader's clock, so stamper and
+// reader clocks must agree to within half the 24-bit wrap (about 2.3
+// hours); stamps slightly ahead of the reader's clock are fine.
+
+use super::{
+    grid::CellGrid,
+    integrity::crc8,
+    levels::Levels,
+    plane::LumaPlane,
+    traits::{ReaderConfig, Reading, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// 24 bits of milliseconds + 8 CRC bits in an 8x4 grid
+const COARSE_COLS: usize = 8;
+const COARSE_ROWS: usize = 4;
+const COARSE_MS_BITS: u32 = 24;
+
+/// Pixels per fine cell in each direction
+const FINE_CELL_SIZE: usize = 4;
+
+/// 10 bits of microseconds + 8 CRC bits over both layers
+const FINE_BITS: usize = 18;
+const FINE_USEC_BITS: u32 = 10;
+
+struct Layout {
+    coarse: CellGrid,
+    fine: CellGrid,
+}
+
+/// Split the region into a coarse grid spanning its width and a fine
+/// grid below it, or `None` if they do not fit
+fn layout(x: u32, y: u32, width: u32, height: u32) -> Option<Layout> {
+    let coarse_cell = width as usize / COARSE_COLS;
+    if coarse_cell < 2 * FINE_CELL_SIZE {
+        return None;
+    }
+
+    let coarse = CellGrid {
+        x: x as usize,
+        y: y as usize,
+        cell_size: coarse_cell,
+        cols: COARSE_COLS,
+        rows: COARSE_ROWS,
+    };
+
+    let fine_cols = width as usize / FINE_CELL_SIZE;
+    let fine = CellGrid {
+        x: x as usize,
+        y: y as usize + COARSE_ROWS * coarse_cell,
+        cell_size: FINE_CELL_SIZE,
+        cols: fine_cols,
+        rows: FINE_BITS.div_ceil(fine_cols),
+    };
+
+    let used_height = COARSE_ROWS * coarse_cell + fine.rows * FINE_CELL_SIZE;
+    (used_height <= height as usize).then_some(Layout { coarse, fine })
+}
+
+/// Whether both layers fit a region of the given size
+pub fn fits(width: u32, height: u32) -> bool {
+    layout(0, 0, width, height).is_some()
+}
+
+fn coarse_word(ms: u64) -> u64 {
+    let ms = ms & ((1 << COARSE_MS_BITS) - 1);
+    (ms << 8) | crc8(ms) as u64
+}
+
+fn fine_word(ms: u64, usecs: u64) -> u64 {
+    let ms = ms & ((1 << COARSE_MS_BITS) - 1);
+    (usecs << 8) | crc8((ms << FINE_USEC_BITS) | usecs) as u64
+}
+
+/// Restore the high bits of the low milliseconds `ms_low` from the local
+/// clock, picking the candidate nearest to `now_ms`, so stamps up to
+/// half a wrap old or ahead of the clock are both right
+fn restore_ms(ms_low: u64, now_ms: u64) -> Option<u64> {
+    let wrap = 1u64 << COARSE_MS_BITS;
+    let age = now_ms.wrapping_sub(ms_low) & (wrap - 1);
+    if age < wrap / 2 {
+        now_ms.checked_sub(age)
+    } else {
+        now_ms.checked_add(wrap - age)
+    }
+}
+
+fn word_bits(word: u64, count: usize) -> impl Iterator<Item = bool> {
+    (0..count).rev().map(move |pos| (word >> pos) & 1 == 1)
+}
+
+fn bits_word(samples: &[u8], levels: Levels) -> u64 {
+    samples.iter().fold(0u64, |word, &mean| {
+        (word << 1) | levels.bit(mean as u32) as u64
+    })
+}
+
+/// Hierarchical stamper
+///
+/// - Coarse layer: 8x4 cells of a eighth of the region width, 24 bits
+///   of milliseconds and a CRC8
+/// - Fine layer: 4x4 pixel cells below, 10 bits of microseconds within
+///   the millisecond and a CRC8 over both layers
+/// - A 64 pixel wide region needs 64x40 pixels; widen it for larger
+///   coarse cells
+pub struct HierarchicalStamper;
+
+impl Default for HierarchicalStamper {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampStamper for HierarchicalStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        let layout = layout(config.x, config.y, config.width, config.height)
+            .ok_or(FlowError::NotSupported)?;
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let ms = timestamp_usecs / 1000;
+        let usecs = timestamp_usecs % 1000;
+
+        let luma = LumaPlane::new(&**frame)?.scaled(config.scale);
+        let data = &mut frame.planes_data_mut();
+        let levels = config.fill.levels();
+        let coarse_bits = (COARSE_MS_BITS + 8) as usize;
+        layout
+            .coarse
+            .stamp(&luma, data, word_bits(coarse_word(ms), coarse_bits), levels);
+        layout.fine.stamp(
+            &luma,
+            data,
+            word_bits(fine_word(ms, usecs), FINE_BITS),
+            levels,
+        );
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "hierarchical"
+    }
+
+    fn description(&self) -> &'static str {
+        "Coarse millisecond code in large cells plus a fine microsecond code"
+    }
+}
+
+/// Hierarchical reader
+pub struct HierarchicalReader;
+
+impl Default for HierarchicalReader {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampReader for HierarchicalReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        Ok(self
+            .read_with_precision(frame, clock, config)?
+            .map(|reading| reading.timestamp))
+    }
+
+    fn read_with_precision(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<Reading>, FlowError> {
+        let Some(layout) = layout(config.x, config.y, config.width, config.height) else {
+            return Ok(None);
+        };
+
+        let luma = LumaPlane::new(frame)?
+            .scaled(config.scale)
+            .oriented(config.orientation);
+        let data = &frame.planes_data();
+        let levels = config.fill.levels();
+
+        let coarse_bits = (COARSE_MS_BITS + 8) as usize;
+        let Some(samples) = layout.coarse.sample(&luma, data, coarse_bits) else {
+            return Ok(None);
+        };
+        let word = bits_word(&samples, levels);
+        let ms_low = word >> 8;
+        if crc8(ms_low) != word as u8 {
+            return Ok(None);
+        }
+
+        let now_ms = clock.time().unwrap().useconds() / 1000;
+        let Some(ms) = restore_ms(ms_low, now_ms) else {
+            return Ok(None);
+        };
+
+        let usecs = layout
+            .fine
+            .sample(&luma, data, FINE_BITS)
+            .map(|samples| bits_word(&samples, levels))
+            .filter(|&word| fine_word(ms, word >> 8) == word)
+            .map(|word| word >> 8)
+            .filter(|&usecs| usecs < 1000);
+
+        Ok(Some(Reading {
+            timestamp: ms * 1000 + usecs.unwrap_or_default(),
+            reduced_precision: usecs.is_none(),
+            confidence: None,
+        }))
+    }
+
+    fn name(&self) -> &'static str {
+        "hierarchical"
+    }
+
+    fn description(&self) -> &'static str {
+        "Hierarchical reader falling back to millisecond precision"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing::{self, Luma};
+    use gst::SystemClock;
+    use gst_video::VideoFormat;
+
+    /// Stamp a 64x64 code, impair its luma and read it back
+    fn read(impair: impl Fn(&mut Luma)) -> (u64, Option<Reading>) {
+        let mut frame = testing::frame(VideoFormat::I420, 64, 64, 128);
+        let stamped = testing::stamp(
+            &mut HierarchicalStamper,
+            &mut frame,
+            &StamperConfig::default(),
+        );
+        let mut luma = testing::luma(&mut frame);
+        impair(&mut luma);
+        testing::set_luma(&mut frame, &luma);
+
+        let (info, buffer) = &frame;
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).unwrap();
+        let reading = HierarchicalReader
+            .read_with_precision(&frame, &SystemClock::obtain(), &ReaderConfig::default())
+            .unwrap();
+        (stamped, reading)
+    }
+
+    #[test]
+    fn restores_the_high_bits_around_the_clock() {
+        let now_ms = 1_700_000_000_000;
+        let low = |ms: u64| ms & ((1 << COARSE_MS_BITS) - 1);
+        for ms in [
+            now_ms,
+            now_ms - 1,
+            now_ms + 1,
+            now_ms - 3_600_000,
+            now_ms + 5_000,
+        ] {
+            assert_eq!(restore_ms(low(ms), now_ms), Some(ms), "{}", ms);
+        }
+    }
+
+    #[test]
+    fn decodes_both_layers() {
+        let (stamped, reading) = read(|_| ());
+        assert_eq!(
+            reading,
+            Some(Reading {
+                timestamp: stamped,
+                reduced_precision: false,
+                confidence: None,
+            })
+        );
+    }
+
+    #[test]
+    fn falls_back_to_milliseconds_without_the_fine_layer() {
+        // The fine layer is the 8 pixel band below the 8x4 cells of 8
+        // pixels, all white reads as more than 999 microseconds
+        let (stamped, reading) = read(|luma| luma.fill((0, 32, 64, 8), 255.0));
+        assert_eq!(
+            reading,
+            Some(Reading {
+                timestamp: stamped / 1000 * 1000,
+                reduced_precision: true,
+                confidence: None,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_a_corrupted_coarse_layer() {
+        // Flip the first coarse cell, CRC8 catches any single bit
+        let (_, reading) = read(|luma| {
+            let flipped = 255.0 - luma.get(4, 4);
+            luma.fill((0, 0, 8, 8), flipped);
+        });
+        assert_eq!(reading, None);
+    }
+}
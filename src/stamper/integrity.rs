@@ -0,0 +1,247 @@
+// Content hash of a downscaled luma thumbnail for frame integrity checks
+//
+// The stamper hashes the picture and stamps the hash in a second code
+// region next to the timestamp region, below it where the frame has
+// room. The reader recomputes the hash on the received picture and
+// compares both. Both code regions are excluded from the hash, so
+// stamping does not change it.
+//
+// The hash is not part of the timestamp payload: every codec sizes
+// its own payload and checksum for the timestamp alone, while a
+// region of its own carries the hash next to any codec unchanged.
+
+use super::{
+    grid::CellGrid, levels::Levels, orient::Orientation, placement::Region, plane::LumaPlane,
+    scale::Scale,
+};
+use gst::{BufferRef, FlowError};
+use gst_video::VideoFrameRef;
+
+/// The thumbnail is an 8x8 grid of mean luma values
+const THUMBNAIL_SIZE: usize = 8;
+
+/// Only every n-th pixel in each direction is sampled
+const SAMPLE_STEP: usize = 4;
+
+/// Pixels per hash bit in each direction
+const BLOCK_SIZE: usize = 4;
+
+/// 64 hash bits + 8 CRC bits laid out in a 12x6 grid
+const HASH_COLS: usize = 12;
+const HASH_ROWS: usize = 6;
+const HASH_BITS: usize = HASH_COLS * HASH_ROWS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x: x as usize,
+            y: y as usize,
+            width: width as usize,
+            height: height as usize,
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// Region holding the hash next to the timestamp region in a frame of
+/// the given size, all in code pixels: below it, or above, right or
+/// left of it where there is no room below. `None` if it fits nowhere.
+pub fn hash_region(code_region: &Region, frame_width: u32, frame_height: u32) -> Option<Region> {
+    let width = (HASH_COLS * BLOCK_SIZE) as u32;
+    let height = (HASH_ROWS * BLOCK_SIZE) as u32;
+    let Region { x, y, .. } = *code_region;
+
+    [
+        (Some(x), y.checked_add(code_region.height)),
+        (Some(x), y.checked_sub(height)),
+        (x.checked_add(code_region.width), Some(y)),
+        (x.checked_sub(width), Some(y)),
+    ]
+    .into_iter()
+    .filter_map(|(x, y)| {
+        Some(Region {
+            x: x?,
+            y: y?,
+            width,
+            height,
+        })
+    })
+    .find(|region| region.x + width <= frame_width && region.y + height <= frame_height)
+}
+
+fn hash_grid(region: Rect) -> CellGrid {
+    CellGrid {
+        x: region.x,
+        y: region.y,
+        cell_size: BLOCK_SIZE,
+        cols: HASH_COLS,
+        rows: HASH_ROWS,
+    }
+}
+
+/// Compute a 64-bit average hash of the frame's luma thumbnail.
+///
+/// Each bit tells whether a thumbnail cell is brighter than the mean
+/// of all cells. Pixels inside `exclude`, in code pixels of `scale`,
+/// are skipped. The thumbnail is taken of the frame as stamped, before
+/// it went through `orientation`.
+pub fn thumbnail_hash(
+    frame: &VideoFrameRef<&BufferRef>,
+    exclude: &[Rect],
+    scale: Scale,
+    orientation: Orientation,
+) -> Result<u64, FlowError> {
+    let luma = LumaPlane::new(frame)?.scaled(scale).oriented(orientation);
+    let data = &frame.planes_data();
+
+    let mut means = [0u32; THUMBNAIL_SIZE * THUMBNAIL_SIZE];
+
+    for (index, mean) in means.iter_mut().enumerate() {
+        let cx = index % THUMBNAIL_SIZE;
+        let cy = index / THUMBNAIL_SIZE;
+        let x0 = cx * luma.width() / THUMBNAIL_SIZE;
+        let x1 = (cx + 1) * luma.width() / THUMBNAIL_SIZE;
+        let y0 = cy * luma.height() / THUMBNAIL_SIZE;
+        let y1 = (cy + 1) * luma.height() / THUMBNAIL_SIZE;
+
+        let mut sum = 0u32;
+        let mut count = 0u32;
+
+        for y in (y0..y1).step_by(SAMPLE_STEP) {
+            for x in (x0..x1).step_by(SAMPLE_STEP) {
+                if exclude.iter().any(|rect| rect.contains(x, y)) {
+                    continue;
+                }
+                if let Some(value) = luma.get(data, x, y) {
+                    sum += value as u32;
+                    count += 1;
+                }
+            }
+        }
+
+        *mean = sum.checked_div(count).unwrap_or_default();
+    }
+
+    let average = means.iter().sum::<u32>() / means.len() as u32;
+    let hash = means
+        .iter()
+        .fold(0u64, |hash, &mean| (hash << 1) | (mean > average) as u64);
+
+    Ok(hash)
+}
+
+/// Stamp the hash and its CRC8 into `region`
+pub fn stamp_hash(
+    frame: &mut VideoFrameRef<&mut BufferRef>,
+    region: Rect,
+    hash: u64,
+    scale: Scale,
+    levels: Levels,
+) -> Result<(), FlowError> {
+    let luma = LumaPlane::new(&**frame)?.scaled(scale);
+    let data = &mut frame.planes_data_mut();
+    hash_grid(region).stamp(&luma, data, hash_bits(hash), levels);
+
+    Ok(())
+}
+
+/// Read the hash from `region`, or `None` if its CRC8 does not match
+pub fn read_hash(
+    frame: &VideoFrameRef<&BufferRef>,
+    region: Rect,
+    scale: Scale,
+    orientation: Orientation,
+    levels: Levels,
+) -> Result<Option<u64>, FlowError> {
+    let luma = LumaPlane::new(frame)?.scaled(scale).oriented(orientation);
+    let data = &frame.planes_data();
+
+    let Some(samples) = hash_grid(region).sample(&luma, data, HASH_BITS) else {
+        return Ok(None);
+    };
+    let bits = samples.into_iter().fold(0u128, |bits, mean| {
+        (bits << 1) | levels.bit(mean as u32) as u128
+    });
+
+    let hash = (bits >> 8) as u64;
+    let crc = bits as u8;
+
+    Ok((crc8(hash) == crc).then_some(hash))
+}
+
+/// Fraction of matching hash bits, from 0.0 to 1.0
+pub fn similarity(expected: u64, actual: u64) -> f64 {
+    1.0 - (expected ^ actual).count_ones() as f64 / 64.0
+}
+
+fn hash_bits(hash: u64) -> impl Iterator<Item = bool> {
+    let bits = ((hash as u128) << 8) | crc8(hash) as u128;
+    (0..HASH_BITS).rev().map(move |pos| (bits >> pos) & 1 == 1)
+}
+
+/// CRC-8 (polynomial 0x07) of the big-endian bytes of `data`
+pub fn crc8(data: u64) -> u8 {
+    data.to_be_bytes().iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn hash_goes_below_the_code_where_there_is_room() {
+        assert_eq!(
+            hash_region(&region(0, 0, 64, 64), 320, 240),
+            Some(region(0, 64, 48, 24))
+        );
+    }
+
+    #[test]
+    fn hash_stays_inside_the_frame() {
+        // Bottom anchored
+        assert_eq!(
+            hash_region(&region(0, 176, 64, 64), 320, 240),
+            Some(region(0, 152, 48, 24))
+        );
+        // Full height, on the right or left
+        assert_eq!(
+            hash_region(&region(0, 0, 64, 240), 320, 240),
+            Some(region(64, 0, 48, 24))
+        );
+        assert_eq!(
+            hash_region(&region(256, 0, 64, 240), 320, 240),
+            Some(region(208, 0, 48, 24))
+        );
+        assert_eq!(hash_region(&region(0, 0, 320, 240), 320, 240), None);
+    }
+}
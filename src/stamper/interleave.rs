@@ -0,0 +1,196 @@
+// Interleaving of code units across the cell grid
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, VecDeque},
+};
+
+/// Permutation of code units (bits or symbols) onto grid cells.
+///
+/// Without interleaving the units of a codeword sit in adjacent
+/// cells, so one corrupted macroblock can wipe out a whole codeword.
+/// Interleaving spreads them over the grid so that spatially clustered
+/// errors hit many codewords a little instead of one codeword a lot.
+/// Stamper and reader must use the same mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "GstTsLatencyInterleave")]
+pub enum Interleave {
+    /// Units are stamped in codeword order
+    #[default]
+    #[enum_value(name = "None: Codewords in adjacent cells", nick = "none")]
+    None,
+    /// Codewords spread over encoder macroblocks, each macroblock holds
+    /// at most one bit of a codeword. Only the `fast-robust` codec has
+    /// several codewords, the others stamp in codeword order.
+    #[enum_value(
+        name = "Block: One bit per codeword and macroblock (fast-robust)",
+        nick = "block"
+    )]
+    Block,
+    /// Fixed pseudo-random permutation
+    #[enum_value(name = "Random: Fixed pseudo-random permutation", nick = "random")]
+    Random,
+}
+
+/// Seed of the pseudo-random permutation, shared by stamper and reader
+const RANDOM_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Side of the encoder macroblocks the block interleaver spreads
+/// codewords over, in pixels
+const MACROBLOCK: usize = 16;
+
+/// Grid the units are stamped on, cells in row-major order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridShape {
+    /// Cells per row
+    pub columns: usize,
+    /// Distance between cell origins in pixels
+    pub pitch: usize,
+}
+
+impl GridShape {
+    /// Macroblock holding the origin of `cell`, as (row, column),
+    /// counted from a region that starts on a macroblock
+    fn macroblock(&self, cell: usize) -> (usize, usize) {
+        let (row, column) = (cell / self.columns, cell % self.columns);
+        (
+            row * self.pitch / MACROBLOCK,
+            column * self.pitch / MACROBLOCK,
+        )
+    }
+}
+
+impl Interleave {
+    /// Slot of each of the `len` symbols of a single codeword.
+    ///
+    /// Block interleaving needs several codewords to interleave, with
+    /// one it leaves the symbols in order.
+    pub fn permutation(self, len: usize) -> Vec<usize> {
+        match self {
+            Interleave::None | Interleave::Block => (0..len).collect(),
+            Interleave::Random => random_permutation(len),
+        }
+    }
+
+    /// Cell index of each of `len` bits on a grid of `shape`, where
+    /// every `depth` consecutive bits form one codeword.
+    ///
+    /// The block interleaver places the bits of a codeword in different
+    /// macroblocks as long as there are enough of them, so losing one
+    /// macroblock costs each codeword at most one bit.
+    pub fn grid_permutation(self, len: usize, depth: usize, shape: GridShape) -> Vec<usize> {
+        match self {
+            Interleave::None => (0..len).collect(),
+            Interleave::Block => spread_over_macroblocks(len, depth.max(1), shape),
+            Interleave::Random => random_permutation(len),
+        }
+    }
+}
+
+/// Fixed pseudo-random permutation of `len` units
+fn random_permutation(len: usize) -> Vec<usize> {
+    let mut cells: Vec<usize> = (0..len).collect();
+    let mut state = RANDOM_SEED ^ len as u64;
+
+    // Fisher-Yates shuffle driven by xorshift64
+    for i in (1..len).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        cells.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    cells
+}
+
+/// Give the bits of each codeword in turn the first free cell of the
+/// macroblock with the most free cells among those the codeword does
+/// not use yet, so macroblocks fill evenly and the last codewords still
+/// find distinct ones
+fn spread_over_macroblocks(len: usize, depth: usize, shape: GridShape) -> Vec<usize> {
+    let shape = GridShape {
+        columns: shape.columns.max(1),
+        pitch: shape.pitch.max(1),
+    };
+
+    let mut free: BTreeMap<(usize, usize), VecDeque<usize>> = BTreeMap::new();
+    for cell in 0..len {
+        free.entry(shape.macroblock(cell))
+            .or_default()
+            .push_back(cell);
+    }
+
+    let mut cells = Vec::with_capacity(len);
+    for codeword in (0..len).step_by(depth) {
+        let mut used = Vec::with_capacity(depth);
+        for _ in codeword..(codeword + depth).min(len) {
+            let fullest = |allowed: &dyn Fn(&(usize, usize)) -> bool| {
+                free.iter()
+                    .filter(|(macroblock, cells)| !cells.is_empty() && allowed(macroblock))
+                    .max_by_key(|(&macroblock, cells)| (cells.len(), Reverse(macroblock)))
+                    .map(|(&macroblock, _)| macroblock)
+            };
+            // A codeword longer than the macroblocks are many shares one
+            let macroblock = fullest(&|macroblock| !used.contains(macroblock))
+                .or_else(|| fullest(&|_| true))
+                .unwrap();
+            used.push(macroblock);
+            cells.push(free.get_mut(&macroblock).unwrap().pop_front().unwrap());
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid of the `fast-robust` code at its default 4 pixel cells
+    const FAST_ROBUST: GridShape = GridShape {
+        columns: 16,
+        pitch: 4,
+    };
+
+    #[test]
+    fn permutations_are_bijective() {
+        for interleave in [Interleave::None, Interleave::Block, Interleave::Random] {
+            for cells in [
+                interleave.permutation(24),
+                interleave.grid_permutation(120, 7, FAST_ROBUST),
+                interleave.grid_permutation(
+                    50,
+                    7,
+                    GridShape {
+                        columns: 7,
+                        pitch: 6,
+                    },
+                ),
+            ] {
+                let mut sorted = cells.clone();
+                sorted.sort();
+                assert_eq!(sorted, (0..cells.len()).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn block_keeps_codewords_out_of_shared_macroblocks() {
+        let cells = Interleave::Block.grid_permutation(120, 7, FAST_ROBUST);
+        for codeword in cells.chunks(7) {
+            let mut macroblocks: Vec<_> = codeword
+                .iter()
+                .map(|&cell| FAST_ROBUST.macroblock(cell))
+                .collect();
+            macroblocks.sort();
+            macroblocks.dedup();
+            assert_eq!(macroblocks.len(), codeword.len(), "{:?}", codeword);
+        }
+    }
+
+    #[test]
+    fn block_leaves_a_single_codeword_in_order() {
+        assert_eq!(
+            Interleave::Block.permutation(14),
+            (0..14).collect::<Vec<_>>()
+        );
+    }
+}
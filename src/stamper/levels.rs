@@ -0,0 +1,85 @@
+// Black and white levels of the code cells
+
+use super::grid::{BLACK, WHITE};
+
+/// Received levels closer than this are not told apart
+const MIN_CONTRAST: i32 = 32;
+
+/// Luma of the cells carrying zero (black) and one (white) bits.
+///
+/// Stampers write the levels of the palette. Readers place their
+/// decision threshold halfway between the levels they expect, or the
+/// ones cells of known value ended up at after compression and colour
+/// conversion, e.g. 20/235 squeezed to 40/200 by a range conversion.
+/// Soft values are scaled back to the nominal swing, so confidence
+/// checks keep their meaning at any contrast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Levels {
+    pub black: i32,
+    pub white: i32,
+}
+
+impl Levels {
+    /// Estimate the levels from the mean luma of known cells and the
+    /// value each one was stamped with.
+    ///
+    /// Returns `None` unless every white cell is brighter than every
+    /// black cell by at least `MIN_CONTRAST`, which picture content
+    /// where no code was stamped rarely is.
+    pub fn estimate(cells: impl IntoIterator<Item = (u32, bool)>) -> Option<Self> {
+        let (mut white_sum, mut white_count, mut white_min) = (0i32, 0i32, i32::MAX);
+        let (mut black_sum, mut black_count, mut black_max) = (0i32, 0i32, i32::MIN);
+
+        for (mean, white) in cells {
+            let mean = mean as i32;
+            if white {
+                white_sum += mean;
+                white_count += 1;
+                white_min = white_min.min(mean);
+            } else {
+                black_sum += mean;
+                black_count += 1;
+                black_max = black_max.max(mean);
+            }
+        }
+
+        if white_count == 0 || black_count == 0 || white_min - black_max < MIN_CONTRAST {
+            return None;
+        }
+
+        Some(Self {
+            black: black_sum / black_count,
+            white: white_sum / white_count,
+        })
+    }
+
+    /// Luma of a cell carrying `bit`
+    pub fn value(&self, bit: bool) -> u8 {
+        if bit {
+            self.white as u8
+        } else {
+            self.black as u8
+        }
+    }
+
+    /// Decision level between black and white
+    pub fn threshold(&self) -> i32 {
+        (self.black + self.white + 1) / 2
+    }
+
+    /// Signed distance of a cell mean from the threshold, the sign is
+    /// the bit. Scaled so the levels land where the nominal ones would,
+    /// a palette with a darker high colour flips the sign.
+    pub fn soft(&self, mean: u32) -> i32 {
+        let swing = self.white - self.black;
+        if swing == 0 {
+            return 0;
+        }
+        (mean as i32 - self.threshold()) * (WHITE - BLACK) as i32 / swing
+    }
+
+    /// Bit carried by a cell of the given mean luma
+    pub fn bit(&self, mean: u32) -> bool {
+        self.soft(mean) > 0
+    }
+}
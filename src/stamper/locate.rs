@@ -0,0 +1,255 @@
+// Automatic code localisation for the measure element
+
+use super::{orient::Orientation, plane::LumaPlane, scale::Scale};
+use gst::{BufferRef, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Consecutive failed decodes after which the cached position is
+/// dropped and the frame searched again
+const SEARCH_AFTER_FAILURES: u32 = 10;
+
+/// Frames between two searches while the code is not found, a full
+/// frame search is too costly to run on every frame
+const SEARCH_INTERVAL: u32 = 15;
+
+/// Luma gap required between the darkest white and the brightest black
+/// marker cell
+const MIN_MARKER_CONTRAST: i32 = 48;
+
+/// Step of the coarse search in pixels, a quarter of a cell keeps the
+/// sampled cell centres inside their cells
+const COARSE_STEP_DIVISOR: usize = 4;
+
+/// Layout of the `optimized` code as seen by the locator: bits in
+/// row-major order on a grid of square cells, with a 16-bit start
+/// marker at bit 0 and a 16-bit end marker at bit 80
+#[derive(Debug, Clone, Copy)]
+pub struct MarkerLayout {
+    pub cell_size: usize,
+    pub grid_width: usize,
+    pub grid_height: usize,
+    pub start_marker: u16,
+    pub end_marker: u16,
+}
+
+impl Default for MarkerLayout {
+    fn default() -> Self {
+        Self {
+            cell_size: 8,
+            grid_width: 12,
+            grid_height: 8,
+            start_marker: 0xA5A5,
+            end_marker: 0x5A5A,
+        }
+    }
+}
+
+impl MarkerLayout {
+    /// Bit offsets and expected values of all marker bits
+    pub fn marker_bits(&self) -> impl Iterator<Item = (usize, bool)> {
+        [(0, self.start_marker), (80, self.end_marker)]
+            .into_iter()
+            .flat_map(|(offset, marker)| {
+                (0..16).map(move |i| (offset + i, (marker >> (15 - i)) & 1 == 1))
+            })
+    }
+
+    /// Cell offsets and expected values of all marker bits
+    fn marker_cells(&self) -> Vec<(usize, usize, bool)> {
+        self.marker_bits()
+            .map(|(bit, value)| (bit % self.grid_width, bit / self.grid_width, value))
+            .collect()
+    }
+
+    /// Gap between the darkest white and the brightest black marker
+    /// cell of a code at `(x, y)`, `sample` returns the luma of a cell
+    /// from its origin. Stops early once the gap is below the minimum.
+    fn contrast(
+        &self,
+        cells: &[(usize, usize, bool)],
+        x: usize,
+        y: usize,
+        sample: impl Fn(usize, usize) -> Option<i32>,
+    ) -> Option<i32> {
+        let mut darkest_white = u8::MAX as i32;
+        let mut brightest_black = 0;
+
+        for &(col, row, white) in cells {
+            let value = sample(x + col * self.cell_size, y + row * self.cell_size)?;
+            if white {
+                darkest_white = darkest_white.min(value);
+            } else {
+                brightest_black = brightest_black.max(value);
+            }
+
+            if darkest_white - brightest_black < MIN_MARKER_CONTRAST {
+                return None;
+            }
+        }
+
+        Some(darkest_white - brightest_black)
+    }
+
+    /// Origin of the code in the frame, if its markers are found.
+    ///
+    /// The coarse pass samples the centre of each marker cell at every
+    /// quarter cell, the fine pass refines the best match pixel by
+    /// pixel on the mean of the cells' inner area. Positions are code
+    /// pixels of the given scale in the frame as stamped, before it
+    /// went through `orientation`.
+    pub fn search(
+        &self,
+        frame: &VideoFrameRef<&BufferRef>,
+        scale: Scale,
+        orientation: Orientation,
+    ) -> Result<Option<(u32, u32)>, FlowError> {
+        let luma = LumaPlane::new(frame)?.scaled(scale).oriented(orientation);
+        let data = &frame.planes_data();
+
+        let code_width = self.grid_width * self.cell_size;
+        let code_height = self.grid_height * self.cell_size;
+        let (Some(max_x), Some(max_y)) = (
+            luma.width().checked_sub(code_width),
+            luma.height().checked_sub(code_height),
+        ) else {
+            return Ok(None);
+        };
+
+        let cells = self.marker_cells();
+        let half = self.cell_size / 2;
+        let centre = |x: usize, y: usize| luma.get(data, x + half, y + half).map(i32::from);
+
+        let step = (self.cell_size / COARSE_STEP_DIVISOR).max(1);
+        let mut coarse = None;
+        for y in (0..=max_y).step_by(step) {
+            for x in (0..=max_x).step_by(step) {
+                if let Some(contrast) = self.contrast(&cells, x, y, centre) {
+                    if coarse.is_none_or(|(best, _, _)| contrast > best) {
+                        coarse = Some((contrast, x, y));
+                    }
+                }
+            }
+        }
+        let Some((_, coarse_x, coarse_y)) = coarse else {
+            return Ok(None);
+        };
+
+        // Edges blur into the neighbouring cells, only the exact
+        // alignment keeps them out of every inner area
+        let inner = self.cell_size.saturating_sub(2).max(1);
+        let inner_mean = |x: usize, y: usize| {
+            luma.mean_rect(data, x + 1, y + 1, inner, inner)
+                .map(i32::from)
+        };
+
+        let reach = step as isize;
+        let mut best = None;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let (Some(x), Some(y)) = (
+                    coarse_x.checked_add_signed(dx).filter(|&x| x <= max_x),
+                    coarse_y.checked_add_signed(dy).filter(|&y| y <= max_y),
+                ) else {
+                    continue;
+                };
+
+                if let Some(contrast) = self.contrast(&cells, x, y, inner_mean) {
+                    if best.is_none_or(|(best, _, _)| contrast > best) {
+                        best = Some((contrast, x, y));
+                    }
+                }
+            }
+        }
+
+        let (_, x, y) = best.unwrap_or((0, coarse_x, coarse_y));
+        Ok(Some((x as u32, y as u32)))
+    }
+}
+
+/// Events raised when the locator changes state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocatorEvent {
+    /// The code was found at this position
+    Located { x: u32, y: u32 },
+    /// The code stopped decoding at its cached position
+    Lost { x: u32, y: u32 },
+}
+
+/// Outcome of locating the code on one frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Location {
+    /// Position to read the code at, `None` while it is not found
+    pub position: Option<(u32, u32)>,
+    pub event: Option<LocatorEvent>,
+}
+
+/// Finds the code in the frame and keeps its position.
+///
+/// Searches until the code is found, then reads at the cached position
+/// until `SEARCH_AFTER_FAILURES` consecutive frames fail to decode.
+#[derive(Default)]
+pub struct CodeLocator {
+    position: Option<(u32, u32)>,
+    failures: u32,
+    frames_since_search: Option<u32>,
+}
+
+impl CodeLocator {
+    /// Forget the cached position and search on the next frame
+    pub fn reset(&mut self) {
+        self.position = None;
+        self.failures = 0;
+        self.frames_since_search = None;
+    }
+
+    /// Position to read the code at on this frame, searching the frame
+    /// for a code of the given layout if no position is cached
+    pub fn locate(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        layout: &MarkerLayout,
+        scale: Scale,
+        orientation: Orientation,
+    ) -> Result<Location, FlowError> {
+        if self.position.is_some() {
+            return Ok(Location {
+                position: self.position,
+                event: None,
+            });
+        }
+
+        if let Some(frames) = &mut self.frames_since_search {
+            *frames += 1;
+            if *frames < SEARCH_INTERVAL {
+                return Ok(Location::default());
+            }
+        }
+
+        self.frames_since_search = Some(0);
+        self.position = layout.search(frame, scale, orientation)?;
+
+        Ok(Location {
+            position: self.position,
+            event: self.position.map(|(x, y)| LocatorEvent::Located { x, y }),
+        })
+    }
+
+    /// Record whether the code decoded at the located position
+    pub fn report(&mut self, decoded: bool) -> Option<LocatorEvent> {
+        let (x, y) = self.position?;
+
+        if decoded {
+            self.failures = 0;
+            return None;
+        }
+
+        self.failures += 1;
+        if self.failures < SEARCH_AFTER_FAILURES {
+            return None;
+        }
+
+        // Search right away, the code has most likely just moved
+        self.reset();
+        Some(LocatorEvent::Lost { x, y })
+    }
+}
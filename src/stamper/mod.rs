@@ -3,52 +3,120 @@
 pub mod original;
 pub mod optimized;
 pub mod fast_robust;
+pub mod reed_solomon;
+pub mod differential;
+pub mod pam4;
+pub mod multichannel;
+pub mod hierarchical;
+pub mod temporal;
+pub mod optical;
+pub mod watermark;
+pub mod adaptive;
+pub mod detect;
+pub mod feedback;
+pub mod locate;
+pub mod copies;
+pub mod grid;
+pub mod integrity;
+pub mod interleave;
+pub mod levels;
+pub mod orient;
+pub mod palette;
+pub mod placement;
+pub mod plane;
+pub mod scale;
 pub mod traits;
+pub mod tuning;
+#[cfg(test)]
+mod testing;
 
-pub use traits::{TimestampStamper, TimestampReader, StamperType, StamperConfig, ReaderConfig};
+pub use traits::{TimestampStamper, TimestampReader, StamperType, StamperConfig, ReaderConfig, Reading};
 pub use original::{OriginalStamper, OriginalReader};
 pub use optimized::{OptimizedStamper, OptimizedReader};
 pub use fast_robust::{FastRobustStamper, FastRobustReader};
+pub use reed_solomon::{ReedSolomonStamper, ReedSolomonReader};
+pub use differential::{DifferentialStamper, DifferentialReader};
+pub use pam4::{Pam4Stamper, Pam4Reader};
+pub use multichannel::{MultichannelStamper, MultichannelReader};
+pub use hierarchical::{HierarchicalStamper, HierarchicalReader};
+pub use temporal::{TemporalStamper, TemporalReader};
+pub use optical::{OpticalStamper, OpticalReader};
+pub use watermark::{WatermarkStamper, WatermarkReader};
+pub use adaptive::{AdaptiveStamper, AdaptiveReader, LayoutController};
+pub use detect::{CodecDetector, Detection, DetectorEvent};
+pub use feedback::{Feedback, FeedbackWindow};
+pub use locate::{CodeLocator, LocatorEvent, MarkerLayout};
+pub use interleave::Interleave;
+pub use orient::{Orientation, OrientationTracker};
+pub use palette::Palette;
+pub use placement::{Anchor, BlockAlign, Placement, Region};
+pub use scale::Scale;
+pub use tuning::Tuning;
 
 use gst_video::VideoFormatFlags;
 use gst::FlowError;
+use palette::Fill;
 
 /// Factory function to create a stamper based on the selected type
+///
+/// `Auto` has no stamper of its own and falls back to the default type.
 pub fn create_stamper(stamper_type: StamperType) -> Box<dyn TimestampStamper> {
     match stamper_type {
-        StamperType::Original => Box::new(OriginalStamper::default()),
-        StamperType::Optimized => Box::new(OptimizedStamper::default()),
-        StamperType::FastRobust => Box::new(FastRobustStamper::default()),
+        StamperType::Original => Box::new(OriginalStamper),
+        StamperType::Optimized => Box::new(OptimizedStamper),
+        StamperType::FastRobust => Box::new(FastRobustStamper),
+        StamperType::ReedSolomon => Box::new(ReedSolomonStamper),
+        StamperType::Differential => Box::new(DifferentialStamper),
+        StamperType::Pam4 => Box::new(Pam4Stamper),
+        StamperType::Multichannel => Box::new(MultichannelStamper),
+        StamperType::Hierarchical => Box::new(HierarchicalStamper),
+        StamperType::Temporal => Box::new(TemporalStamper::default()),
+        StamperType::Optical => Box::new(OpticalStamper),
+        StamperType::Watermark => Box::new(WatermarkStamper),
+        StamperType::Adaptive => Box::new(AdaptiveStamper),
+        StamperType::Auto => create_stamper(StamperType::default()),
     }
 }
 
 /// Factory function to create a reader based on the selected type
+///
+/// `Auto` is handled by [`CodecDetector`]; here it falls back to the
+/// default type.
 pub fn create_reader(stamper_type: StamperType) -> Box<dyn TimestampReader> {
     match stamper_type {
-        StamperType::Original => Box::new(OriginalReader::default()),
+        StamperType::Original => Box::new(OriginalReader),
         StamperType::Optimized => Box::new(OptimizedReader::default()),
         StamperType::FastRobust => Box::new(FastRobustReader::default()),
+        StamperType::ReedSolomon => Box::new(ReedSolomonReader),
+        StamperType::Differential => Box::new(DifferentialReader),
+        StamperType::Pam4 => Box::new(Pam4Reader),
+        StamperType::Multichannel => Box::new(MultichannelReader),
+        StamperType::Hierarchical => Box::new(HierarchicalReader),
+        StamperType::Temporal => Box::new(TemporalReader::default()),
+        StamperType::Optical => Box::new(OpticalReader::default()),
+        StamperType::Watermark => Box::new(WatermarkReader),
+        StamperType::Adaptive => Box::new(AdaptiveReader),
+        StamperType::Auto => create_reader(StamperType::default()),
     }
 }
 
 /// Helper function to get appropriate fill values for different video formats
+#[allow(dead_code)]
+#[deprecated(note = "use `Fill::full_swing`, or resolve a `Palette`")]
 pub fn get_fill_values(flags: VideoFormatFlags) -> Result<([u8; 3], [u8; 3]), FlowError> {
-    if flags.contains(VideoFormatFlags::RGB) {
-        Ok(([255, 255, 255], [0, 0, 0]))
-    } else if flags.contains(VideoFormatFlags::YUV) {
-        Ok(([255, 128, 128], [0, 128, 128]))
-    } else {
-        Err(FlowError::NotSupported)
-    }
+    Fill::full_swing(flags).map(fill_values)
 }
 
 /// Helper function to get robust fill values (not pure black/white)
+#[allow(dead_code)]
+#[deprecated(note = "use `Fill::gray`, or resolve a `Palette`")]
 pub fn get_robust_fill_values(flags: VideoFormatFlags) -> Result<([u8; 3], [u8; 3]), FlowError> {
-    if flags.contains(VideoFormatFlags::RGB) {
-        Ok(([235, 235, 235], [20, 20, 20]))
-    } else if flags.contains(VideoFormatFlags::YUV) {
-        Ok(([235, 128, 128], [20, 128, 128]))
-    } else {
-        Err(FlowError::NotSupported)
-    }
+    Fill::gray(grid::WHITE, grid::BLACK, flags).map(fill_values)
+}
+
+#[allow(dead_code)]
+fn fill_values(fill: Fill) -> ([u8; 3], [u8; 3]) {
+    let [high @ .., _] = fill.high.components;
+    let [low @ .., _] = fill.low.components;
+    (high, low)
 }
\ No newline at end of file
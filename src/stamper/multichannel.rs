@@ -0,0 +1,230 @@
+// Colour-channel multiplexed encoding for RGB and 4:4:4 streams
+//
+// Each colour component of a cell carries its own bit, tripling the
+// density on formats without chroma subsampling. Subsampled formats
+// fall back to one bit per cell in the luma. The payload is the
+// Reed-Solomon codeword of the `reed-solomon` codec.
+//
+// The number of channels follows from the format, so stamper and
+// reader must see formats of the same kind: RGB or 4:4:4 end to end,
+// e.g. over shared memory or a lossless transport.
+
+use super::{
+    grid::CellGrid,
+    plane::ComponentPlane,
+    reed_solomon::{codeword_len, decode_samples, encode_symbols, symbol_bits},
+    traits::{ReaderConfig, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Pixels per cell in each direction
+const CELL_SIZE: usize = 4;
+
+/// Grid holding `bits` spread over `channels` components, or `None` if
+/// it does not fit the region
+fn code_grid(
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    bits: usize,
+    channels: usize,
+) -> Option<CellGrid> {
+    let grid = CellGrid::fit(x, y, width, height, CELL_SIZE);
+    (bits.div_ceil(channels) <= grid.capacity()).then_some(grid)
+}
+
+/// Whether the code with `parity` parity symbols fits a region of the
+/// given size when spread over `channels` components
+pub fn fits(width: u32, height: u32, parity: usize, channels: usize) -> bool {
+    code_grid(0, 0, width, height, codeword_len(parity) * 8, channels).is_some()
+}
+
+/// Multichannel stamper
+///
+/// - Sends the Reed-Solomon codeword of the 64-bit timestamp
+/// - Each 4x4 pixel cell carries one bit per colour component
+/// - 6 parity symbols need 38 cells on RGB or 4:4:4, 112 otherwise
+pub struct MultichannelStamper;
+
+impl Default for MultichannelStamper {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampStamper for MultichannelStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let channels: Vec<_> = ComponentPlane::independent(&**frame)?
+            .into_iter()
+            .map(|component| component.scaled(config.scale))
+            .collect();
+        let grid = code_grid(
+            config.x,
+            config.y,
+            config.width,
+            config.height,
+            codeword_len(parity) * 8,
+            channels.len(),
+        )
+        .ok_or(FlowError::NotSupported)?;
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let symbols = encode_symbols(timestamp_usecs, parity, config.interleave);
+        let bits: Vec<bool> = symbol_bits(&symbols).collect();
+        let levels = config.fill.levels();
+
+        for (channel, component) in channels.iter().enumerate() {
+            let data = frame.plane_data_mut(component.plane()).unwrap();
+
+            for (index, &bit) in bits
+                .iter()
+                .skip(channel)
+                .step_by(channels.len())
+                .enumerate()
+            {
+                let (x, y) = grid.cell_origin(index);
+                let value = levels.value(bit);
+                component.fill_rect(data, x, y, CELL_SIZE, CELL_SIZE, value);
+            }
+        }
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "multichannel"
+    }
+
+    fn description(&self) -> &'static str {
+        "Colour-channel multiplexed stamper, one bit per component and cell"
+    }
+}
+
+/// Multichannel reader
+pub struct MultichannelReader;
+
+impl Default for MultichannelReader {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampReader for MultichannelReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let bit_count = codeword_len(parity) * 8;
+        let channels: Vec<_> = ComponentPlane::independent(frame)?
+            .into_iter()
+            .map(|component| component.scaled(config.scale).oriented(config.orientation))
+            .collect();
+        let Some(grid) = code_grid(
+            config.x,
+            config.y,
+            config.width,
+            config.height,
+            bit_count,
+            channels.len(),
+        ) else {
+            return Ok(None);
+        };
+
+        // Central half of each cell, edges blur into the neighbours
+        let margin = CELL_SIZE / 4;
+        let size = CELL_SIZE - 2 * margin;
+
+        let mut samples = Vec::with_capacity(bit_count);
+        for bit in 0..bit_count {
+            let component = &channels[bit % channels.len()];
+            let data = frame.plane_data(component.plane()).unwrap();
+            let (x, y) = grid.cell_origin(bit / channels.len());
+
+            let Some(mean) = component.mean_rect(data, x + margin, y + margin, size, size) else {
+                return Ok(None);
+            };
+            samples.push(mean);
+        }
+
+        Ok(decode_samples(
+            &samples,
+            parity,
+            config.interleave,
+            config.fill.levels(),
+        ))
+    }
+
+    fn name(&self) -> &'static str {
+        "multichannel"
+    }
+
+    fn description(&self) -> &'static str {
+        "Colour-channel multiplexed reader, falls back to luma on subsampled formats"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing;
+    use gst::Buffer;
+    use gst_video::{VideoFormat, VideoInfo};
+
+    /// Stamp a 64x64 frame of `format`, returns the frame and whether
+    /// the reader got the value back
+    fn round_trip(format: VideoFormat) -> ((VideoInfo, Buffer), bool) {
+        let mut frame = testing::frame(format, 64, 64, 128);
+        let stamped = testing::stamp(
+            &mut MultichannelStamper,
+            &mut frame,
+            &StamperConfig::default(),
+        );
+        let decoded = testing::read(&mut MultichannelReader, &frame, &ReaderConfig::default());
+        (frame, decoded == Some(stamped))
+    }
+
+    #[test]
+    fn decodes_rgb_and_444() {
+        for format in [VideoFormat::Rgbx, VideoFormat::Gbr, VideoFormat::Y444] {
+            assert!(round_trip(format).1, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn spreads_bits_over_the_components() {
+        // A third of the cells of the luma-only code
+        let cells = codeword_len(6) * 8;
+        assert!(code_grid(0, 0, 28, 24, cells, 3).is_some());
+        assert!(code_grid(0, 0, 28, 24, cells, 1).is_none());
+    }
+
+    #[test]
+    fn falls_back_to_luma_on_subsampled_formats() {
+        let ((info, buffer), decoded) = round_trip(VideoFormat::I420);
+        assert!(decoded);
+
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &info).unwrap();
+        for plane in 1..3 {
+            assert!(
+                frame
+                    .plane_data(plane)
+                    .unwrap()
+                    .iter()
+                    .all(|&value| value == 128),
+                "plane {}",
+                plane
+            );
+        }
+    }
+}
@@ -0,0 +1,730 @@
+// Optical-capture codec for glass-to-glass measurements
+//
+// The code is a square of 25x25 modules in the style of a QR code: three
+// finder patterns in the corners, an alignment pattern towards the
+// fourth corner and a light quiet zone around it. The reader does not
+// rely on the configured region. It scans the frame for the finder
+// patterns, around the code of the previous frame first, locates the
+// alignment pattern, maps module coordinates to pixels with the
+// homography of those four points and samples every module. This copes with a camera pointed at a display:
+// perspective, rotation, blur and exposure changes.
+//
+// The payload is the Reed-Solomon codeword of the `reed-solomon` codec
+// with a fixed, large number of parity symbols, since a camera capture
+// corrupts far more modules than a video encoder.
+
+use super::{
+    grid::{BLACK, WHITE},
+    plane::{LumaPlane, Planes},
+    reed_solomon::{codeword_len, decode_symbols, encode_symbols, symbol_bits},
+    traits::{ReaderConfig, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Modules per side
+const SIZE: usize = 25;
+
+/// Light modules around the code on each side
+const QUIET_ZONE: usize = 2;
+
+/// Side of a finder pattern in modules
+const FINDER_SIZE: usize = 7;
+
+/// Centre of the alignment pattern, in modules from the top-left
+const ALIGNMENT: usize = 18;
+
+/// Parity symbols, 48 symbols fill 384 of the 408 data modules
+const PARITY: usize = 40;
+
+/// Binary margin of a perfectly received module, for erasure marking
+const FULL_MARGIN: f64 = ((WHITE - BLACK) / 2) as f64;
+
+/// Whether a module belongs to a finder, its separator or the alignment
+/// pattern rather than to the data
+fn is_function_module(row: usize, col: usize) -> bool {
+    let near = |v: usize| v <= FINDER_SIZE;
+    let far = |v: usize| v >= SIZE - FINDER_SIZE - 1;
+
+    (near(row) && near(col))
+        || (near(row) && far(col))
+        || (far(row) && near(col))
+        || (row.abs_diff(ALIGNMENT) <= 2 && col.abs_diff(ALIGNMENT) <= 2)
+}
+
+/// Colour of a function module
+fn function_module_dark(row: usize, col: usize) -> bool {
+    if row.abs_diff(ALIGNMENT) <= 2 && col.abs_diff(ALIGNMENT) <= 2 {
+        let ring = row.abs_diff(ALIGNMENT).max(col.abs_diff(ALIGNMENT));
+        return ring != 1;
+    }
+
+    let (fr, fc) = (
+        if row < SIZE / 2 {
+            0
+        } else {
+            SIZE - FINDER_SIZE
+        },
+        if col < SIZE / 2 {
+            0
+        } else {
+            SIZE - FINDER_SIZE
+        },
+    );
+    let (dr, dc) = (row as isize - fr as isize, col as isize - fc as isize);
+    if !(0..FINDER_SIZE as isize).contains(&dr) || !(0..FINDER_SIZE as isize).contains(&dc) {
+        // Separator
+        return false;
+    }
+
+    let ring = (dr - 3).abs().max((dc - 3).abs());
+    ring != 2
+}
+
+/// Data modules in placement order
+fn data_modules() -> impl Iterator<Item = (usize, usize)> {
+    (0..SIZE)
+        .flat_map(|row| (0..SIZE).map(move |col| (row, col)))
+        .filter(|&(row, col)| !is_function_module(row, col))
+}
+
+/// Pixels per module of a code filling the smaller side of the region
+fn module_size(width: u32, height: u32) -> usize {
+    width.min(height) as usize / (SIZE + 2 * QUIET_ZONE)
+}
+
+/// Whether the modules of a code in a region of the given size are
+/// large enough to be found
+pub fn fits(width: u32, height: u32) -> bool {
+    module_size(width, height) as f64 >= MIN_MODULE
+}
+
+/// Optical stamper
+///
+/// - Draws a 25x25 module code with a 2 module quiet zone in the largest
+///   square fitting the region
+/// - 8 data and 40 parity symbols, up to 20 corrupted symbols corrected
+/// - Modules should be at least 8 pixels on screen for camera capture,
+///   e.g. a 232x232 pixel region
+pub struct OpticalStamper;
+
+impl Default for OpticalStamper {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampStamper for OpticalStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        if !fits(config.width, config.height) {
+            return Err(FlowError::NotSupported);
+        }
+        let module = module_size(config.width, config.height);
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let symbols = encode_symbols(timestamp_usecs, PARITY, config.interleave);
+
+        let luma = LumaPlane::new(&**frame)?.scaled(config.scale);
+        let data = &mut frame.planes_data_mut();
+        let levels = config.fill.levels();
+
+        let side = (SIZE + 2 * QUIET_ZONE) * module;
+        luma.fill_rect(
+            data,
+            config.x as usize,
+            config.y as usize,
+            side,
+            side,
+            levels.value(true),
+        );
+
+        let x0 = config.x as usize + QUIET_ZONE * module;
+        let y0 = config.y as usize + QUIET_ZONE * module;
+        let mut fill = |row: usize, col: usize, dark: bool| {
+            let value = levels.value(!dark);
+            luma.fill_rect(
+                data,
+                x0 + col * module,
+                y0 + row * module,
+                module,
+                module,
+                value,
+            );
+        };
+
+        for row in 0..SIZE {
+            for col in 0..SIZE {
+                if is_function_module(row, col) {
+                    fill(row, col, function_module_dark(row, col));
+                }
+            }
+        }
+
+        // Bits beyond the codeword alternate to keep the code balanced
+        let bits = symbol_bits(&symbols).chain((0..).map(|i| i % 2 == 0));
+        for ((row, col), bit) in data_modules().zip(bits) {
+            fill(row, col, !bit);
+        }
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "optical"
+    }
+
+    fn description(&self) -> &'static str {
+        "QR-style stamper with finder patterns for camera capture"
+    }
+}
+
+/// Optical reader
+///
+/// Searches the whole frame, the configured region is ignored. Once a
+/// code was found, the next frame is searched around it first.
+#[derive(Default)]
+pub struct OpticalReader {
+    /// Area around the code found last
+    window: Option<Window>,
+}
+
+impl TimestampReader for OpticalReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        let luma = LumaPlane::new(frame)?.oriented(config.orientation);
+        let data = &frame.planes_data();
+        let image = Image { luma: &luma, data };
+
+        // Where a code was found and what it decoded to
+        let whole = (0, 0, luma.width(), luma.height());
+        let search = |window| {
+            locate(&image, window).map(|code| (code.window(whole), decode(&image, &code, config)))
+        };
+        let found = self
+            .window
+            .and_then(search)
+            .filter(|(_, timestamp)| timestamp.is_some())
+            .or_else(|| search(whole));
+        self.window = found.map(|(window, _)| window);
+
+        Ok(found.and_then(|(_, timestamp)| timestamp))
+    }
+
+    fn name(&self) -> &'static str {
+        "optical"
+    }
+
+    fn description(&self) -> &'static str {
+        "QR-style reader locating and rectifying the code anywhere in the frame"
+    }
+}
+
+struct Image<'a> {
+    luma: &'a LumaPlane,
+    data: &'a Planes<'a>,
+}
+
+impl Image<'_> {
+    fn get(&self, x: usize, y: usize) -> u8 {
+        self.luma.get(self.data, x, y).unwrap_or(WHITE)
+    }
+
+    /// Mean luma of a small square around a sub-pixel position
+    fn sample(&self, x: f64, y: f64, radius: usize) -> Option<f64> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (cx, cy) = (x as usize, y as usize);
+        let mean = self.luma.mean_rect(
+            self.data,
+            cx.saturating_sub(radius),
+            cy.saturating_sub(radius),
+            2 * radius + 1,
+            2 * radius + 1,
+        )?;
+        Some(mean as f64)
+    }
+}
+
+type Point = (f64, f64);
+
+/// Area of the frame as `(x, y, width, height)`
+type Window = (usize, usize, usize, usize);
+
+/// Located code: the module-to-pixel mapping and the module size
+struct Code {
+    homography: [f64; 8],
+    module: f64,
+}
+
+impl Code {
+    /// Pixel position of a point in module coordinates
+    fn map(&self, (u, v): Point) -> Point {
+        let h = &self.homography;
+        let w = h[6] * u + h[7] * v + 1.0;
+        (
+            (h[0] * u + h[1] * v + h[2]) / w,
+            (h[3] * u + h[4] * v + h[5]) / w,
+        )
+    }
+
+    /// Area of `frame` around the code and its quiet zone, grown by half
+    /// the code's size on every side to follow it between frames
+    fn window(&self, frame: Window) -> Window {
+        let (near, far) = (-(QUIET_ZONE as f64), (SIZE + QUIET_ZONE) as f64);
+        let corners = [(near, near), (far, near), (near, far), (far, far)].map(|p| self.map(p));
+        let (mut left, mut top, mut right, mut bottom) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+        for (x, y) in corners {
+            (left, top) = (left.min(x), top.min(y));
+            (right, bottom) = (right.max(x), bottom.max(y));
+        }
+
+        let margin = (right - left).max(bottom - top) / 2.0;
+        let clamp = |value: f64, min: usize, max: usize| (value.max(min as f64) as usize).min(max);
+        let (x0, y0) = (frame.0, frame.1);
+        let (x1, y1) = (frame.0 + frame.2, frame.1 + frame.3);
+        let (left, top) = (clamp(left - margin, x0, x1), clamp(top - margin, y0, y1));
+        let (right, bottom) = (
+            clamp(right + margin, x0, x1),
+            clamp(bottom + margin, y0, y1),
+        );
+        (left, top, right - left, bottom - top)
+    }
+}
+
+/// Modules smaller than this are noise, not a finder pattern
+const MIN_MODULE: f64 = 2.0;
+
+/// Dark pixels need to be this much below the mean of their
+/// surroundings, so that noise in flat areas stays light
+const MIN_LOCAL_CONTRAST: u32 = 8;
+
+/// Dark/light decision of every pixel of a window against the mean of
+/// its surroundings, which follows uneven lighting across the frame.
+/// Positions are relative to the window.
+struct Binary {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    dark: Vec<bool>,
+}
+
+impl Binary {
+    fn new(image: &Image, (x0, y0, width, height): Window) -> Self {
+        let radius = (image.luma.width().max(image.luma.height()) / 40).max(8);
+
+        // Summed-area table with a zero row and column in front
+        let mut sums = vec![0u32; (width + 1) * (height + 1)];
+        for y in 0..height {
+            let mut row = 0u32;
+            for x in 0..width {
+                row += image.get(x0 + x, y0 + y) as u32;
+                sums[(y + 1) * (width + 1) + x + 1] = sums[y * (width + 1) + x + 1] + row;
+            }
+        }
+
+        let mean = |x: usize, y: usize, radius: usize| {
+            let (top, bottom) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+            let (left, right) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let sum = sums[bottom * (width + 1) + right] + sums[top * (width + 1) + left]
+                - sums[top * (width + 1) + right]
+                - sums[bottom * (width + 1) + left];
+            sum / ((bottom - top) * (right - left)) as u32
+        };
+
+        // Pixels are smoothed over 3x3 against sensor noise
+        let dark = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| mean(x, y, 1) + MIN_LOCAL_CONTRAST < mean(x, y, radius))
+            .collect();
+
+        Self {
+            x: x0,
+            y: y0,
+            width,
+            height,
+            dark,
+        }
+    }
+
+    fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+}
+
+/// Runs of equal colour along a line as `(dark, start, length)`
+fn runs(line: impl Iterator<Item = bool>) -> Vec<(bool, usize, usize)> {
+    let mut runs: Vec<(bool, usize, usize)> = vec![];
+    for (pos, dark) in line.enumerate() {
+        match runs.last_mut() {
+            Some((run_dark, _, len)) if *run_dark == dark => *len += 1,
+            _ => runs.push((dark, pos, 1)),
+        }
+    }
+    runs
+}
+
+/// Centre and module size of every 1:1:3:1:1 dark-light-dark-light-dark
+/// run sequence, the cross-section of a finder pattern
+fn finder_crossings(runs: &[(bool, usize, usize)]) -> Vec<(f64, f64)> {
+    runs.windows(5)
+        .filter(|window| window[0].0)
+        .filter_map(|window| {
+            let lens: Vec<f64> = window.iter().map(|run| run.2 as f64).collect();
+            let module = lens.iter().sum::<f64>() / 7.0;
+            let tolerance = module / 2.0;
+
+            let matches = [1.0, 1.0, 3.0, 1.0, 1.0]
+                .iter()
+                .zip(&lens)
+                .all(|(&expected, &len)| (len - expected * module).abs() < expected * tolerance);
+
+            (matches && module >= MIN_MODULE).then(|| (window[2].1 as f64 + lens[2] / 2.0, module))
+        })
+        .collect()
+}
+
+/// Find the crossing closest to `pos` along the part of a line within
+/// `reach` of it, `is_dark` maps line positions to pixels
+fn cross_check(
+    len: usize,
+    pos: f64,
+    reach: f64,
+    is_dark: impl Fn(usize) -> bool,
+) -> Option<(f64, f64)> {
+    let start = (pos - reach).max(0.0) as usize;
+    let end = ((pos + reach) as usize).min(len);
+
+    finder_crossings(&runs((start..end).map(is_dark)))
+        .into_iter()
+        .map(|(centre, module)| (centre + start as f64, module))
+        .min_by(|a, b| (a.0 - pos).abs().total_cmp(&(b.0 - pos).abs()))
+        .filter(|&(centre, module)| (centre - pos).abs() < module * 1.5)
+}
+
+/// Centres and module sizes of the finder patterns in the frame
+/// window of `binary`
+fn find_finders(binary: &Binary) -> Vec<(Point, f64)> {
+    let (width, height) = (binary.width, binary.height);
+
+    // Clusters of confirmed centres: sums of x, y, module and the count
+    let mut clusters: Vec<(f64, f64, f64, f64)> = vec![];
+
+    for y in (0..height).step_by(2) {
+        let row = runs((0..width).map(|x| binary.is_dark(x, y)));
+        for (x, module) in finder_crossings(&row) {
+            // A finder spans 7 modules, allow for perspective and rotation
+            let reach = module * 8.0;
+            let Some((cy, v_module)) =
+                cross_check(height, y as f64, reach, |cy| binary.is_dark(x as usize, cy))
+            else {
+                continue;
+            };
+            let Some((cx, h_module)) =
+                cross_check(width, x, reach, |cx| binary.is_dark(cx, cy as usize))
+            else {
+                continue;
+            };
+            let module = (v_module + h_module) / 2.0;
+
+            match clusters.iter_mut().find(|c| {
+                let (mx, my) = (c.0 / c.3, c.1 / c.3);
+                (mx - cx).abs() < module * 2.0 && (my - cy).abs() < module * 2.0
+            }) {
+                Some(cluster) => {
+                    cluster.0 += cx;
+                    cluster.1 += cy;
+                    cluster.2 += module;
+                    cluster.3 += 1.0;
+                }
+                None => clusters.push((cx, cy, module, 1.0)),
+            }
+        }
+    }
+
+    clusters.sort_by(|a, b| b.3.total_cmp(&a.3));
+    clusters
+        .into_iter()
+        .filter(|c| c.3 >= 2.0)
+        .take(8)
+        .map(|c| {
+            let centre = (binary.x as f64 + c.0 / c.3, binary.y as f64 + c.1 / c.3);
+            (centre, c.2 / c.3)
+        })
+        .collect()
+}
+
+/// Pick the three finders forming the most right-angled isosceles
+/// triangle, returned as top-left, top-right and bottom-left
+fn pick_finders(finders: &[(Point, f64)]) -> Option<[(Point, f64); 3]> {
+    let mut best: Option<(f64, [(Point, f64); 3])> = None;
+
+    for i in 0..finders.len() {
+        for j in 0..finders.len() {
+            for k in j + 1..finders.len() {
+                if i == j || i == k {
+                    continue;
+                }
+                let (a, b, c) = (finders[i], finders[j], finders[k]);
+
+                let modules = [a.1, b.1, c.1];
+                let max = modules.iter().copied().fold(f64::MIN, f64::max);
+                let min = modules.iter().copied().fold(f64::MAX, f64::min);
+                if max > min * 1.5 {
+                    continue;
+                }
+
+                let ab = (b.0 .0 - a.0 .0, b.0 .1 - a.0 .1);
+                let ac = (c.0 .0 - a.0 .0, c.0 .1 - a.0 .1);
+                let (len_ab, len_ac) = (ab.0.hypot(ab.1), ac.0.hypot(ac.1));
+                if len_ab < a.1 * 10.0 || len_ac < a.1 * 10.0 {
+                    continue;
+                }
+
+                let cos = (ab.0 * ac.0 + ab.1 * ac.1) / (len_ab * len_ac);
+                let score = cos.abs() + (len_ab / len_ac - 1.0).abs();
+                if score > 0.5 || best.is_some_and(|(best, _)| best <= score) {
+                    continue;
+                }
+
+                // Top-right follows top-left clockwise in image coordinates
+                let cross = ab.0 * ac.1 - ab.1 * ac.0;
+                let triple = if cross > 0.0 { [a, b, c] } else { [a, c, b] };
+                best = Some((score, triple));
+            }
+        }
+    }
+
+    best.map(|(_, triple)| triple)
+}
+
+/// Homography mapping four module coordinates onto pixel positions
+fn homography(from: [Point; 4], to: [Point; 4]) -> Option<[f64; 8]> {
+    let mut m = [[0f64; 9]; 8];
+    for (i, (&(u, v), &(x, y))) in from.iter().zip(&to).enumerate() {
+        m[2 * i] = [u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x];
+        m[2 * i + 1] = [0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y];
+    }
+
+    // Gaussian elimination with partial pivoting
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))?;
+        if m[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        m.swap(col, pivot);
+
+        for row in 0..8 {
+            if row != col {
+                let factor = m[row][col] / m[col][col];
+                let pivot_row = m[col];
+                for (value, pivot_value) in m[row].iter_mut().zip(pivot_row).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+
+    let mut h = [0f64; 8];
+    for (i, value) in h.iter_mut().enumerate() {
+        *value = m[i][8] / m[i][i];
+    }
+    Some(h)
+}
+
+/// Find the code in a window of the frame
+fn locate(image: &Image, window: Window) -> Option<Code> {
+    let [tl, tr, bl] = pick_finders(&find_finders(&Binary::new(image, window)))?;
+    let module = (tl.1 + tr.1 + bl.1) / 3.0;
+
+    // Finder centres are 18 modules apart, the alignment pattern is 15
+    // modules right of and below the top-left centre
+    let span = (SIZE - FINDER_SIZE) as f64;
+    let u = ((tr.0 .0 - tl.0 .0) / span, (tr.0 .1 - tl.0 .1) / span);
+    let v = ((bl.0 .0 - tl.0 .0) / span, (bl.0 .1 - tl.0 .1) / span);
+    let offset = (ALIGNMENT as f64 + 0.5) - FINDER_SIZE as f64 / 2.0;
+    let predicted = (
+        tl.0 .0 + (u.0 + v.0) * offset,
+        tl.0 .1 + (u.1 + v.1) * offset,
+    );
+
+    // Perspective moves the alignment pattern, search around the
+    // affine prediction for the best match of its 5x5 modules, the
+    // closest one among equally good matches
+    let radius = (module / 4.0) as usize;
+    let score = |(x, y): Point| -> Option<f64> {
+        let mut score = 0.0;
+        for dv in -2i32..=2 {
+            for du in -2i32..=2 {
+                let (du_f, dv_f) = (du as f64, dv as f64);
+                let value = image.sample(
+                    x + u.0 * du_f + v.0 * dv_f,
+                    y + u.1 * du_f + v.1 * dv_f,
+                    radius,
+                )?;
+                let dark = du.abs().max(dv.abs()) != 1;
+                score += if dark { -value } else { value };
+            }
+        }
+        Some(score)
+    };
+
+    let reach = (module * 4.0) as isize;
+    let step = ((module / 4.0) as isize).max(1) as usize;
+    let alignment = (-reach..=reach)
+        .step_by(step)
+        .flat_map(|dy| (-reach..=reach).step_by(step).map(move |dx| (dx, dy)))
+        .filter_map(|(dx, dy)| {
+            let p = (predicted.0 + dx as f64, predicted.1 + dy as f64);
+            score(p).map(|s| (s, -(dx * dx + dy * dy), p))
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+        .map_or(predicted, |(_, _, p)| p);
+
+    let centre = FINDER_SIZE as f64 / 2.0;
+    let far = SIZE as f64 - centre;
+    let homography = homography(
+        [
+            (centre, centre),
+            (far, centre),
+            (centre, far),
+            (ALIGNMENT as f64 + 0.5, ALIGNMENT as f64 + 0.5),
+        ],
+        [tl.0, tr.0, bl.0, alignment],
+    )?;
+
+    Some(Code { homography, module })
+}
+
+/// Sample the modules of a located code and decode the payload
+fn decode(image: &Image, code: &Code, config: &ReaderConfig) -> Option<u64> {
+    let radius = (code.module / 4.0) as usize;
+    let at = |row: usize, col: usize| {
+        let (x, y) = code.map((col as f64 + 0.5, row as f64 + 0.5));
+        image.sample(x, y, radius)
+    };
+
+    // Decision level from the dark finder centres and their light rings
+    let corners = [(3, 3), (3, SIZE - 4), (SIZE - 4, 3)];
+    let dark = corners
+        .iter()
+        .map(|&(r, c)| at(r, c))
+        .sum::<Option<f64>>()?
+        / 3.0;
+    let light = corners
+        .iter()
+        .map(|&(r, c)| at(r - 2, c))
+        .sum::<Option<f64>>()?
+        / 3.0;
+    if light - dark < 16.0 {
+        return None;
+    }
+    let threshold = (dark + light) / 2.0;
+    let half_contrast = (light - dark) / 2.0;
+
+    let count = codeword_len(PARITY) * 8;
+    let mut symbols = vec![0u8; codeword_len(PARITY)];
+    let mut margins = vec![u8::MAX; codeword_len(PARITY)];
+
+    for (index, (row, col)) in data_modules().take(count).enumerate() {
+        let value = at(row, col)?;
+        let bit = value > threshold;
+        symbols[index / 8] = (symbols[index / 8] << 1) | bit as u8;
+
+        let margin = ((value - threshold).abs() / half_contrast * FULL_MARGIN).min(255.0);
+        margins[index / 8] = margins[index / 8].min(margin as u8);
+    }
+
+    decode_symbols(&symbols, &margins, PARITY, config.interleave)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing::{self, Luma};
+    use gst_video::VideoFormat;
+
+    /// Stamp a 480x320 gray frame with the code in a 232x232 region at
+    /// `(x, y)`, 8 pixel modules, and pass its luma through `capture`
+    fn captured(
+        x: u32,
+        y: u32,
+        capture: impl Fn(&mut Luma),
+    ) -> ((gst_video::VideoInfo, gst::Buffer), u64) {
+        let config = StamperConfig {
+            x,
+            y,
+            width: 232,
+            height: 232,
+            ..Default::default()
+        };
+        let mut frame = testing::frame(VideoFormat::Gray8, 480, 320, 128);
+        let stamped = testing::stamp(&mut OpticalStamper, &mut frame, &config);
+        let mut luma = testing::luma(&mut frame);
+        capture(&mut luma);
+        testing::set_luma(&mut frame, &luma);
+        (frame, stamped)
+    }
+
+    /// Camera looking at the display from the side: rotated, scaled
+    /// down and foreshortened towards the right
+    fn perspective(luma: &mut Luma) {
+        let (cx, cy) = (luma.width as f64 / 2.0, luma.height as f64 / 2.0);
+        let (sin, cos) = 0.2f64.sin_cos();
+        luma.warp(
+            |x, y| {
+                let (dx, dy) = (x - cx, y - cy);
+                let w = 1.0 - 0.0012 * dx;
+                let (u, v) = (dx / (0.8 * w), dy / (0.8 * w));
+                (cx + cos * u - sin * v, cy + sin * u + cos * v)
+            },
+            128.0,
+        );
+    }
+
+    #[test]
+    fn reads_a_warped_blurred_noisy_capture() {
+        let (frame, stamped) = captured(124, 44, |luma| {
+            perspective(luma);
+            luma.blur(1);
+            luma.add_noise(12.0, 7);
+        });
+
+        let mut reader = OpticalReader::default();
+        let read = testing::read(&mut reader, &frame, &ReaderConfig::default());
+        assert_eq!(read, Some(stamped));
+    }
+
+    #[test]
+    fn follows_the_code_between_frames() {
+        let mut reader = OpticalReader::default();
+        let config = ReaderConfig::default();
+
+        let (frame, stamped) = captured(20, 20, |_| ());
+        assert_eq!(testing::read(&mut reader, &frame, &config), Some(stamped));
+        let (x, y, width, height) = reader.window.unwrap();
+        assert!(x < 20 && y < 20 && width < 480 && height <= 320);
+
+        // Moved within the window
+        let (frame, stamped) = captured(60, 40, |_| ());
+        assert_eq!(testing::read(&mut reader, &frame, &config), Some(stamped));
+
+        // Moved out of it, the whole frame is searched again
+        let (frame, stamped) = captured(240, 80, |_| ());
+        assert_eq!(testing::read(&mut reader, &frame, &config), Some(stamped));
+
+        let (frame, _) = captured(0, 0, |luma| luma.fill((0, 0, 480, 320), 128.0));
+        assert_eq!(testing::read(&mut reader, &frame, &config), None);
+        assert_eq!(reader.window, None);
+    }
+}
@@ -1,39 +1,33 @@
 // Optimized timestamp stamper implementation with error correction
 
+use super::levels::Levels;
+use super::locate::MarkerLayout;
 use super::traits::{TimestampStamper, TimestampReader, StamperConfig, ReaderConfig};
 use gst_video::{VideoFrameRef, VideoFormatFlags, VideoFormat, prelude::*};
 use gst::{BufferRef, Clock, FlowError, prelude::*};
 
 /// Optimized stamper with larger cells and error correction
-pub struct OptimizedStamper {
-    cell_size: usize,
-    grid_width: usize,
-    grid_height: usize,
-    start_marker: u16,
-    end_marker: u16,
-}
+///
+/// Cell size, grid and markers come from `config.tuning.layout`, by
+/// default 8x8 pixel cells on a 12x8 grid between the markers 0xA5A5
+/// and 0x5A5A.
+pub struct OptimizedStamper;
 
 impl Default for OptimizedStamper {
     fn default() -> Self {
-        Self {
-            cell_size: 8,      // 8x8 pixels per bit
-            grid_width: 12,    // 12 cells wide
-            grid_height: 8,    // 8 cells high  
-            start_marker: 0xA5A5,  // Start pattern
-            end_marker: 0x5A5A,    // End pattern
-        }
+        Self
     }
 }
 
 impl TimestampStamper for OptimizedStamper {
     fn stamp(
-        &self,
+        &mut self,
         frame: &mut VideoFrameRef<&mut BufferRef>,
         clock: &Clock,
         config: &StamperConfig,
-    ) -> Result<(), FlowError> {
+    ) -> Result<u64, FlowError> {
         let timestamp_usecs = clock.time().unwrap().useconds();
-        let encoded = self.encode_with_redundancy(timestamp_usecs);
+        let encoded = self.encode_with_redundancy(timestamp_usecs, &config.tuning.layout);
         
         let format = frame.format();
         
@@ -43,7 +37,8 @@ impl TimestampStamper for OptimizedStamper {
             self.stamp_generic(frame, &encoded, config)?;
         }
         
-        Ok(())
+        // Only the lower 48 bits are encoded
+        Ok(timestamp_usecs & 0xFFFF_FFFF_FFFF)
     }
     
     fn name(&self) -> &'static str {
@@ -56,12 +51,12 @@ impl TimestampStamper for OptimizedStamper {
 }
 
 impl OptimizedStamper {
-    fn encode_with_redundancy(&self, timestamp_usecs: u64) -> Vec<u8> {
+    fn encode_with_redundancy(&self, timestamp_usecs: u64, layout: &MarkerLayout) -> Vec<u8> {
         let mut encoded = Vec::with_capacity(16);
         
         // Add start marker (2 bytes)
-        encoded.push((self.start_marker >> 8) as u8);
-        encoded.push(self.start_marker as u8);
+        encoded.push((layout.start_marker >> 8) as u8);
+        encoded.push(layout.start_marker as u8);
         
         // Encode 48-bit timestamp (6 bytes) - enough for ~8 years
         let ts48 = timestamp_usecs & 0xFFFF_FFFF_FFFF;
@@ -78,8 +73,8 @@ impl OptimizedStamper {
         encoded.push(crc as u8);
         
         // Add end marker (2 bytes)
-        encoded.push((self.end_marker >> 8) as u8);
-        encoded.push(self.end_marker as u8);
+        encoded.push((layout.end_marker >> 8) as u8);
+        encoded.push(layout.end_marker as u8);
         
         encoded
     }
@@ -93,11 +88,11 @@ impl OptimizedStamper {
         let stride = frame.plane_stride()[0] as usize;
         let plane_data = frame.plane_data_mut(0).unwrap();
         
-        // Use gray levels that survive compression better
-        const WHITE: u8 = 235;  // Not pure white
-        const BLACK: u8 = 20;   // Not pure black
-        const GRAY: u8 = 128;   // Mid-gray for transitions
+        // Palette levels, with edges blended towards mid-gray
+        let levels = config.fill.levels();
+        let gray = levels.threshold() as u8;
         
+        let layout = &config.tuning.layout;
         let x_offset = config.x as usize;
         let y_offset = config.y as usize;
         
@@ -108,24 +103,24 @@ impl OptimizedStamper {
                 let bit = (byte >> (7 - bit_pos)) & 1 == 1;
                 
                 // Calculate cell position
-                let cell_x = bit_index % self.grid_width;
-                let cell_y = bit_index / self.grid_width;
+                let cell_x = bit_index % layout.grid_width;
+                let cell_y = bit_index / layout.grid_width;
                 
-                if cell_y >= self.grid_height {
+                if cell_y >= layout.grid_height {
                     break;
                 }
                 
-                let x_start = x_offset + cell_x * self.cell_size;
-                let y_start = y_offset + cell_y * self.cell_size;
+                let x_start = x_offset + cell_x * layout.cell_size;
+                let y_start = y_offset + cell_y * layout.cell_size;
+                let cell = config.scale.rect(x_start, y_start, layout.cell_size, layout.cell_size);
                 
                 // Stamp with gradient edges for better compression survival
                 self.stamp_cell_with_gradient(
                     plane_data,
                     stride,
-                    x_start,
-                    y_start,
-                    if bit { WHITE } else { BLACK },
-                    GRAY,
+                    cell,
+                    levels.value(bit),
+                    gray,
                 );
                 
                 bit_index += 1;
@@ -135,20 +130,19 @@ impl OptimizedStamper {
         Ok(())
     }
     
+    /// Fill the frame rectangle `(x, y, width, height)` of one cell
     fn stamp_cell_with_gradient(
         &self,
         data: &mut [u8],
         stride: usize,
-        x: usize,
-        y: usize,
+        (x, y, width, height): (usize, usize, usize, usize),
         center_value: u8,
         edge_value: u8,
     ) {
-        let size = self.cell_size;
         let edge_width = 1; // 1-pixel gradient edge
         
-        for dy in 0..size {
-            for dx in 0..size {
+        for dy in 0..height {
+            for dx in 0..width {
                 let idx = (y + dy) * stride + (x + dx);
                 
                 if idx >= data.len() {
@@ -156,8 +150,8 @@ impl OptimizedStamper {
                 }
                 
                 // Apply gradient at edges
-                let value = if dy < edge_width || dy >= size - edge_width ||
-                              dx < edge_width || dx >= size - edge_width {
+                let value = if dy < edge_width || dy + edge_width >= height ||
+                              dx < edge_width || dx + edge_width >= width {
                     // Blend edge with center
                     ((center_value as u16 + edge_value as u16) / 2) as u8
                 } else {
@@ -178,19 +172,17 @@ impl OptimizedStamper {
         let fmt = frame.format_info();
         let flags = fmt.flags();
         
-        let (white, black) = if flags.contains(VideoFormatFlags::RGB) {
-            ([235, 235, 235], [20, 20, 20])
-        } else if flags.contains(VideoFormatFlags::YUV) {
-            ([235, 128, 128], [20, 128, 128])
-        } else {
+        if !flags.intersects(VideoFormatFlags::RGB | VideoFormatFlags::YUV) {
             return Err(FlowError::NotSupported);
-        };
+        }
+        let levels = config.fill.levels();
         
         // Simplified generic implementation
         // In production, this would handle multiple planes properly
         let stride = frame.plane_stride()[0] as usize;
         let plane_data = frame.plane_data_mut(0).unwrap();
         
+        let layout = &config.tuning.layout;
         let x_offset = config.x as usize;
         let y_offset = config.y as usize;
         
@@ -199,23 +191,23 @@ impl OptimizedStamper {
         for byte in encoded {
             for bit_pos in 0..8 {
                 let bit = (byte >> (7 - bit_pos)) & 1 == 1;
-                let cell_x = bit_index % self.grid_width;
-                let cell_y = bit_index / self.grid_width;
+                let cell_x = bit_index % layout.grid_width;
+                let cell_y = bit_index / layout.grid_width;
                 
-                if cell_y >= self.grid_height {
+                if cell_y >= layout.grid_height {
                     break;
                 }
                 
-                let x_start = x_offset + cell_x * self.cell_size;
-                let y_start = y_offset + cell_y * self.cell_size;
+                let x_start = x_offset + cell_x * layout.cell_size;
+                let y_start = y_offset + cell_y * layout.cell_size;
+                let cell = config.scale.rect(x_start, y_start, layout.cell_size, layout.cell_size);
                 
                 self.stamp_cell_with_gradient(
                     plane_data,
                     stride,
-                    x_start,
-                    y_start,
-                    if bit { white[0] } else { black[0] },
-                    128,
+                    cell,
+                    levels.value(bit),
+                    levels.threshold() as u8,
                 );
                 
                 bit_index += 1;
@@ -244,25 +236,25 @@ impl OptimizedStamper {
 }
 
 /// Optimized reader with error detection
+///
+/// The marker cells double as pilots: their known values give the
+/// black and white levels of each frame, and the decision threshold
+/// lies halfway between them.
 pub struct OptimizedReader {
-    cell_size: usize,
-    grid_width: usize,
-    grid_height: usize,
-    start_marker: u16,
-    end_marker: u16,
-    threshold: u8,
+    /// Confidence limit unless `config.tuning` sets one
     min_confidence: f32,
 }
 
+/// Start marker, 48-bit timestamp, CRC16 and end marker
+pub const CODE_BITS: usize = 96;
+
+/// Erased cells tried in both states when the CRC fails, each one
+/// doubles the chance of accepting a corrupted code
+const MAX_ERASURE_FLIPS: usize = 4;
+
 impl Default for OptimizedReader {
     fn default() -> Self {
         Self {
-            cell_size: 8,
-            grid_width: 12,
-            grid_height: 8,
-            start_marker: 0xA5A5,
-            end_marker: 0x5A5A,
-            threshold: 128,
             min_confidence: 0.6,
         }
     }
@@ -270,21 +262,23 @@ impl Default for OptimizedReader {
 
 impl TimestampReader for OptimizedReader {
     fn read(
-        &self,
+        &mut self,
         frame: &VideoFrameRef<&BufferRef>,
         _clock: &Clock,
         config: &ReaderConfig,
     ) -> Result<Option<u64>, FlowError> {
         let format = frame.format();
         
-        let decoded = if format == VideoFormat::I420 {
+        let means = if format == VideoFormat::I420 {
             self.read_i420_fast(frame, config)?
         } else {
             self.read_generic(frame, config)?
         };
+        let soft = self.soft_values(&means, config);
+        let min_confidence = config.tuning.min_confidence.unwrap_or(self.min_confidence);
         
         // Verify and extract timestamp
-        Ok(self.verify_and_extract(&decoded))
+        Ok(self.verify_and_extract(&soft, &config.tuning.layout, min_confidence))
     }
     
     fn name(&self) -> &'static str {
@@ -297,67 +291,55 @@ impl TimestampReader for OptimizedReader {
 }
 
 impl OptimizedReader {
+    /// Mean luma of each code cell, `None` for cells outside the frame
     fn read_i420_fast(
         &self,
         frame: &VideoFrameRef<&BufferRef>,
         config: &ReaderConfig,
-    ) -> Result<Vec<u8>, FlowError> {
+    ) -> Result<Vec<Option<u32>>, FlowError> {
         let stride = frame.plane_stride()[0] as usize;
         let plane_data = frame.plane_data(0).unwrap();
         
+        let layout = &config.tuning.layout;
         let x_offset = config.x as usize;
         let y_offset = config.y as usize;
         
-        let mut decoded = Vec::with_capacity(12);
-        let mut bit_buffer = 0u8;
-        let mut bit_count = 0;
+        let mut means = Vec::with_capacity(CODE_BITS);
         
-        for cell_y in 0..self.grid_height {
-            for cell_x in 0..self.grid_width {
-                let x_start = x_offset + cell_x * self.cell_size;
-                let y_start = y_offset + cell_y * self.cell_size;
+        for cell_y in 0..layout.grid_height {
+            for cell_x in 0..layout.grid_width {
+                let x_start = x_offset + cell_x * layout.cell_size;
+                let y_start = y_offset + cell_y * layout.cell_size;
+                let cell = config.frame_rect(frame, x_start, y_start, layout.cell_size, layout.cell_size);
                 
-                // Read cell with majority voting
-                let bit = self.read_cell_majority(
+                means.push(self.read_cell_majority(
                     plane_data,
                     stride,
-                    x_start,
-                    y_start,
-                ).unwrap_or(false);
-                
-                bit_buffer = (bit_buffer << 1) | (bit as u8);
-                bit_count += 1;
+                    cell,
+                ));
                 
-                if bit_count == 8 {
-                    decoded.push(bit_buffer);
-                    bit_buffer = 0;
-                    bit_count = 0;
-                    
-                    if decoded.len() >= 12 {
-                        return Ok(decoded);
-                    }
+                if means.len() >= CODE_BITS {
+                    return Ok(means);
                 }
             }
         }
         
-        Ok(decoded)
+        Ok(means)
     }
     
+    /// Mean luma of the frame rectangle `(x, y, width, height)` of one cell
     fn read_cell_majority(
         &self,
         data: &[u8],
         stride: usize,
-        x: usize,
-        y: usize,
-    ) -> Option<bool> {
-        let size = self.cell_size;
-        
+        (x, y, width, height): (usize, usize, usize, usize),
+    ) -> Option<u32> {
         let mut sum = 0u32;
         let mut count = 0u32;
         
         // Sample inner region (avoiding edges affected by compression)
-        for dy in 1..size - 1 {
-            for dx in 1..size - 1 {
+        for dy in 1..height.saturating_sub(1) {
+            for dx in 1..width.saturating_sub(1) {
                 let idx = (y + dy) * stride + (x + dx);
                 
                 if idx < data.len() {
@@ -371,51 +353,98 @@ impl OptimizedReader {
             return None;
         }
         
-        let avg = sum / count;
-        
-        // Calculate confidence
-        let distance_from_threshold = ((avg as i32 - self.threshold as i32).abs() as f32) / 128.0;
-        
-        if distance_from_threshold < (1.0 - self.min_confidence) {
-            return None; // Too close to threshold, unreliable
-        }
-        
-        Some(avg > self.threshold as u32)
+        Some(sum / count)
+    }
+    
+    /// Signed distance of each cell from the threshold set by the
+    /// marker cells, or by the palette when the markers are unreadable,
+    /// unless `config.tuning` fixes it. The sign is the bit, unreadable
+    /// cells carry no information.
+    fn soft_values(&self, means: &[Option<u32>], config: &ReaderConfig) -> Vec<i32> {
+        let pilots = config
+            .tuning
+            .layout
+            .marker_bits()
+            .filter_map(|(bit, value)| Some((means.get(bit).copied()??, value)));
+        let levels = config.tuning.levels(Levels::estimate(pilots), config.fill.levels());
+        
+        means
+            .iter()
+            .map(|mean| mean.map_or(0, |mean| levels.soft(mean)))
+            .collect()
+    }
+    
+    /// Whether a soft value is too close to the threshold to be trusted
+    fn is_erasure(&self, soft: i32, min_confidence: f32) -> bool {
+        let distance_from_threshold = (soft.abs() as f32) / 128.0;
+        distance_from_threshold < (1.0 - min_confidence)
     }
     
     fn read_generic(
         &self,
         frame: &VideoFrameRef<&BufferRef>,
         config: &ReaderConfig,
-    ) -> Result<Vec<u8>, FlowError> {
+    ) -> Result<Vec<Option<u32>>, FlowError> {
         // Simplified - just read from first plane
         self.read_i420_fast(frame, config)
     }
     
-    fn verify_and_extract(&self, data: &[u8]) -> Option<u64> {
-        if data.len() < 12 {
-            return None;
-        }
-        
-        // Check start marker
-        let start = ((data[0] as u16) << 8) | (data[1] as u16);
-        if start != self.start_marker {
+    /// Decode the soft cell values into a timestamp.
+    ///
+    /// Markers must match on all trusted cells. Cells too close to the
+    /// threshold are treated as erasures: if the CRC fails on the hard
+    /// decision, the least reliable erased payload bits are flipped
+    /// until it matches.
+    fn verify_and_extract(
+        &self,
+        soft: &[i32],
+        layout: &MarkerLayout,
+        min_confidence: f32,
+    ) -> Option<u64> {
+        if soft.len() < CODE_BITS {
             return None;
         }
         
-        // Check end marker
-        let end = ((data[10] as u16) << 8) | (data[11] as u16);
-        if end != self.end_marker {
-            return None;
+        // Check markers, erased cells cannot contradict them
+        for (bit, expected) in layout.marker_bits() {
+            let value = soft[bit];
+            if !self.is_erasure(value, min_confidence) && (value > 0) != expected {
+                return None;
+            }
         }
         
-        // Verify CRC
-        let stored_crc = ((data[8] as u16) << 8) | (data[9] as u16);
-        let calculated_crc = self.crc16(&data[2..8]);
+        let mut data: Vec<u8> = soft
+            .chunks(8)
+            .map(|cells| cells.iter().fold(0u8, |byte, &value| (byte << 1) | (value > 0) as u8))
+            .collect();
+        
+        // Erased timestamp and CRC bits, least reliable first
+        let mut erasures: Vec<usize> = (16..80)
+            .filter(|&bit| self.is_erasure(soft[bit], min_confidence))
+            .collect();
+        erasures.sort_by_key(|&bit| soft[bit].abs());
+        erasures.truncate(MAX_ERASURE_FLIPS);
+        
+        // Verify CRC, trying all flip patterns of the erased bits
+        let crc_matches = |data: &[u8]| {
+            let stored_crc = ((data[8] as u16) << 8) | (data[9] as u16);
+            stored_crc == self.crc16(&data[2..8])
+        };
+        let flip = |data: &mut [u8], pattern: u32| {
+            for (i, &bit) in erasures.iter().enumerate() {
+                if pattern & (1 << i) != 0 {
+                    data[bit / 8] ^= 0x80 >> (bit % 8);
+                }
+            }
+        };
         
-        if stored_crc != calculated_crc {
-            return None;
-        }
+        let pattern = (0..1u32 << erasures.len()).find(|&pattern| {
+            flip(&mut data, pattern);
+            let matches = crc_matches(&data);
+            flip(&mut data, pattern);
+            matches
+        })?;
+        flip(&mut data, pattern);
         
         // Extract timestamp
         let timestamp = ((data[2] as u64) << 40) |
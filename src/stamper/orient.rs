@@ -0,0 +1,352 @@
+// Rotated and mirrored frames
+
+/// Transformation applied to the frame between stamper and reader,
+/// named after the matching `videoflip` methods.
+///
+/// Readers map every code position of the stamped frame through it,
+/// so the code decodes from the flipped frame as is. A wrong
+/// orientation puts the markers and checksum bits of the code in the
+/// wrong cells, so only the right one validates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "GstTsLatencyOrientation")]
+pub enum Orientation {
+    #[enum_value(name = "Identity: Frame as stamped", nick = "identity")]
+    Identity,
+    #[enum_value(name = "90R: Rotated 90 degrees clockwise", nick = "90r")]
+    Rotate90,
+    #[enum_value(name = "180: Rotated 180 degrees", nick = "180")]
+    Rotate180,
+    #[enum_value(name = "90L: Rotated 90 degrees counter-clockwise", nick = "90l")]
+    Rotate270,
+    #[enum_value(name = "Horiz: Flipped horizontally", nick = "horiz")]
+    FlipHorizontal,
+    #[enum_value(name = "Vert: Flipped vertically", nick = "vert")]
+    FlipVertical,
+    #[enum_value(
+        name = "UL-LR: Flipped across the upper left/lower right diagonal",
+        nick = "ul-lr"
+    )]
+    Transpose,
+    #[enum_value(
+        name = "UR-LL: Flipped across the upper right/lower left diagonal",
+        nick = "ur-ll"
+    )]
+    AntiTranspose,
+    /// Follow `image-orientation` tags and detect the orientation from
+    /// the frames (reader only)
+    #[default]
+    #[enum_value(name = "Auto: Detect the orientation (reader only)", nick = "auto")]
+    Auto,
+}
+
+impl Orientation {
+    /// All concrete orientations, in order of likelihood
+    pub const ALL: [Orientation; 8] = [
+        Orientation::Identity,
+        Orientation::FlipHorizontal,
+        Orientation::Rotate180,
+        Orientation::FlipVertical,
+        Orientation::Rotate90,
+        Orientation::Rotate270,
+        Orientation::Transpose,
+        Orientation::AntiTranspose,
+    ];
+
+    /// Orientation of frames carrying an `image-orientation` tag.
+    ///
+    /// The tag tells how to turn the frame for display, the frame went
+    /// through the inverse of the `videoflip` method applying it.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "rotate-0" => Some(Orientation::Identity),
+            "rotate-90" => Some(Orientation::Rotate270),
+            "rotate-180" => Some(Orientation::Rotate180),
+            "rotate-270" => Some(Orientation::Rotate90),
+            "flip-rotate-0" => Some(Orientation::FlipHorizontal),
+            "flip-rotate-90" => Some(Orientation::Transpose),
+            "flip-rotate-180" => Some(Orientation::FlipVertical),
+            "flip-rotate-270" => Some(Orientation::AntiTranspose),
+            _ => None,
+        }
+    }
+
+    /// Whether width and height swap
+    pub fn transposes(self) -> bool {
+        matches!(
+            self,
+            Orientation::Rotate90
+                | Orientation::Rotate270
+                | Orientation::Transpose
+                | Orientation::AntiTranspose
+        )
+    }
+
+    /// Size of the stamped frame given the size of the frame read
+    pub fn source_size<T>(self, width: T, height: T) -> (T, T) {
+        if self.transposes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Position in the frame read of pixel `(x, y)` of the stamped
+    /// frame of `width` x `height` pixels, which must hold the pixel
+    pub fn point(self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        let (right, bottom) = (width - 1 - x, height - 1 - y);
+
+        match self {
+            Orientation::Identity | Orientation::Auto => (x, y),
+            Orientation::Rotate90 => (bottom, x),
+            Orientation::Rotate180 => (right, bottom),
+            Orientation::Rotate270 => (y, right),
+            Orientation::FlipHorizontal => (right, y),
+            Orientation::FlipVertical => (x, bottom),
+            Orientation::Transpose => (y, x),
+            Orientation::AntiTranspose => (bottom, right),
+        }
+    }
+
+    /// Rectangle in the frame read covering the part of a rectangle of
+    /// the stamped frame inside that frame
+    pub fn rect(
+        self,
+        (x, y, rect_width, rect_height): (usize, usize, usize, usize),
+        width: usize,
+        height: usize,
+    ) -> (usize, usize, usize, usize) {
+        let right = (x + rect_width).min(width);
+        let bottom = (y + rect_height).min(height);
+        if x >= right || y >= bottom {
+            return (x, y, 0, 0);
+        }
+
+        let (ax, ay) = self.point(x, y, width, height);
+        let (bx, by) = self.point(right - 1, bottom - 1, width, height);
+        (
+            ax.min(bx),
+            ay.min(by),
+            ax.abs_diff(bx) + 1,
+            ay.abs_diff(by) + 1,
+        )
+    }
+}
+
+/// Consecutive failed decodes after which the other orientations are
+/// tried
+const PROBE_AFTER_FAILURES: u32 = 3;
+
+/// Failed decodes between two orientation probes, trying all
+/// orientations is too costly to run on every frame
+const PROBE_INTERVAL: u32 = 15;
+
+/// Keeps the orientation of the incoming frames in `auto` mode.
+///
+/// Starts from the latest `image-orientation` tag, or the identity,
+/// and probes the other orientations while the code fails to decode.
+#[derive(Default)]
+pub struct OrientationTracker {
+    tagged: Option<Orientation>,
+    detected: Option<Orientation>,
+    failures: u32,
+}
+
+impl OrientationTracker {
+    /// Orientation to read the next frame with
+    pub fn current(&self) -> Orientation {
+        self.detected
+            .or(self.tagged)
+            .unwrap_or(Orientation::Identity)
+    }
+
+    /// Forget the detected orientation, keeping the tagged one
+    pub fn reset(&mut self) {
+        self.detected = None;
+        self.failures = 0;
+    }
+
+    /// Follow an `image-orientation` tag from upstream
+    pub fn tag(&mut self, orientation: Orientation) {
+        self.tagged = Some(orientation);
+        self.reset();
+    }
+
+    /// Record whether the code decoded at the current orientation,
+    /// `true` if the other orientations should be probed now
+    pub fn report(&mut self, decoded: bool) -> bool {
+        if decoded {
+            self.failures = 0;
+            return false;
+        }
+
+        self.failures += 1;
+        self.failures >= PROBE_AFTER_FAILURES
+            && (self.failures - PROBE_AFTER_FAILURES).is_multiple_of(PROBE_INTERVAL)
+    }
+
+    /// Orientations to probe, all but the current one
+    pub fn candidates(&self) -> impl Iterator<Item = Orientation> {
+        let current = self.current();
+        Orientation::ALL
+            .into_iter()
+            .filter(move |&orientation| orientation != current)
+    }
+
+    /// Read with `orientation` from now on, after it decoded
+    pub fn detected(&mut self, orientation: Orientation) {
+        self.detected = Some(orientation);
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::{
+        fast_robust::{FastRobustReader, FastRobustStamper},
+        testing,
+        traits::{ReaderConfig, StamperConfig},
+    };
+    use gst_video::VideoFormat;
+
+    /// Orientation undoing `orientation`
+    fn inverse(orientation: Orientation) -> Orientation {
+        match orientation {
+            Orientation::Rotate90 => Orientation::Rotate270,
+            Orientation::Rotate270 => Orientation::Rotate90,
+            other => other,
+        }
+    }
+
+    #[test]
+    fn from_tag_undoes_the_display_transform() {
+        // The tag names the videoflip method turning the frame for display
+        let tags = [
+            ("rotate-0", Orientation::Identity),
+            ("rotate-90", Orientation::Rotate90),
+            ("rotate-180", Orientation::Rotate180),
+            ("rotate-270", Orientation::Rotate270),
+            ("flip-rotate-0", Orientation::FlipHorizontal),
+            ("flip-rotate-90", Orientation::Transpose),
+            ("flip-rotate-180", Orientation::FlipVertical),
+            ("flip-rotate-270", Orientation::AntiTranspose),
+        ];
+        let (width, height) = (6, 4);
+
+        for (tag, display) in tags {
+            let orientation = Orientation::from_tag(tag).unwrap();
+            let (read_width, read_height) = if orientation.transposes() {
+                (height, width)
+            } else {
+                (width, height)
+            };
+            for (x, y) in (0..height).flat_map(|y| (0..width).map(move |x| (x, y))) {
+                let (rx, ry) = orientation.point(x, y, width, height);
+                assert_eq!(
+                    display.point(rx, ry, read_width, read_height),
+                    (x, y),
+                    "{tag}"
+                );
+            }
+        }
+        assert_eq!(Orientation::from_tag("rotate-45"), None);
+    }
+
+    #[test]
+    fn rotate90_turns_clockwise() {
+        // The top left corner goes to the top right
+        assert_eq!(Orientation::Rotate90.point(0, 0, 6, 4), (3, 0));
+        assert_eq!(Orientation::Rotate270.point(0, 0, 6, 4), (0, 5));
+    }
+
+    /// Stamp a frame, put it through `orientation` and read it the way
+    /// `auto` does, returns the orientation detected
+    fn detect(orientation: Orientation) -> Option<Orientation> {
+        let mut frame = testing::frame(VideoFormat::Gray8, 128, 128, 128);
+        let stamped = testing::stamp(
+            &mut FastRobustStamper,
+            &mut frame,
+            &StamperConfig::default(),
+        );
+        let mut luma = testing::luma(&mut frame);
+        let undo = inverse(orientation);
+        luma.warp(
+            |x, y| {
+                let (sx, sy) = undo.point(x as usize, y as usize, 128, 128);
+                (sx as f64, sy as f64)
+            },
+            128.0,
+        );
+        testing::set_luma(&mut frame, &luma);
+
+        let mut reader = FastRobustReader::default();
+        let mut tracker = OrientationTracker::default();
+        let mut read = |orientation| {
+            let config = ReaderConfig {
+                orientation,
+                ..Default::default()
+            };
+            testing::read(&mut reader, &frame, &config) == Some(stamped)
+        };
+        for _ in 0..PROBE_AFTER_FAILURES {
+            let decoded = read(tracker.current());
+            if decoded {
+                return Some(tracker.current());
+            }
+            if tracker.report(decoded) {
+                let found = tracker.candidates().find(|&candidate| read(candidate))?;
+                tracker.detected(found);
+            }
+        }
+        read(tracker.current()).then(|| tracker.current())
+    }
+
+    #[test]
+    fn detects_identity() {
+        assert_eq!(detect(Orientation::Identity), Some(Orientation::Identity));
+    }
+
+    #[test]
+    fn detects_rotate90() {
+        assert_eq!(detect(Orientation::Rotate90), Some(Orientation::Rotate90));
+    }
+
+    #[test]
+    fn detects_rotate180() {
+        assert_eq!(detect(Orientation::Rotate180), Some(Orientation::Rotate180));
+    }
+
+    #[test]
+    fn detects_rotate270() {
+        assert_eq!(detect(Orientation::Rotate270), Some(Orientation::Rotate270));
+    }
+
+    #[test]
+    fn detects_flip_horizontal() {
+        assert_eq!(
+            detect(Orientation::FlipHorizontal),
+            Some(Orientation::FlipHorizontal)
+        );
+    }
+
+    #[test]
+    fn detects_flip_vertical() {
+        assert_eq!(
+            detect(Orientation::FlipVertical),
+            Some(Orientation::FlipVertical)
+        );
+    }
+
+    #[test]
+    fn detects_transpose() {
+        assert_eq!(detect(Orientation::Transpose), Some(Orientation::Transpose));
+    }
+
+    #[test]
+    fn detects_anti_transpose() {
+        assert_eq!(
+            detect(Orientation::AntiTranspose),
+            Some(Orientation::AntiTranspose)
+        );
+    }
+}
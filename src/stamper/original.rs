@@ -1,6 +1,7 @@
 // Original timestamp stamper implementation
 // This is the current implementation extracted from the existing code
 
+use super::palette::Fill;
 use super::traits::{TimestampStamper, TimestampReader, StamperConfig, ReaderConfig};
 use gst_video::{VideoFrameRef, VideoFormatFlags, prelude::*};
 use gst::{BufferRef, Clock, FlowError, prelude::*};
@@ -17,23 +18,29 @@ impl Default for OriginalStamper {
 
 impl TimestampStamper for OriginalStamper {
     fn stamp(
-        &self,
+        &mut self,
         frame: &mut VideoFrameRef<&mut BufferRef>,
         clock: &Clock,
         config: &StamperConfig,
-    ) -> Result<(), FlowError> {
+    ) -> Result<u64, FlowError> {
         let fmt = frame.format_info();
         let flags = fmt.flags();
         
-        let (white_fill, black_fill) = if flags.contains(VideoFormatFlags::RGB) {
-            ([255, 255, 255], [0, 0, 0])
-        } else if flags.contains(VideoFormatFlags::YUV) {
-            ([255, 128, 128], [0, 128, 128])
-        } else {
+        if !flags.intersects(VideoFormatFlags::RGB | VideoFormatFlags::YUV) {
             return Err(FlowError::NotSupported);
+        }
+        
+        // Cells are written in the palette colours themselves, full
+        // swing unless colours are set
+        let fill = if config.fill.custom {
+            config.fill
+        } else {
+            Fill::full_swing(flags)?
         };
+        let white_fill = &fill.high.components[..3];
+        let black_fill = &fill.low.components[..3];
         
-        self.stamp_time_code(frame, clock, config, &white_fill, &black_fill)
+        self.stamp_time_code(frame, clock, config, white_fill, black_fill)
     }
     
     fn name(&self) -> &'static str {
@@ -53,11 +60,13 @@ impl OriginalStamper {
         config: &StamperConfig,
         white_fill: &[u8],
         black_fill: &[u8],
-    ) -> Result<(), FlowError> {
-        let start_x = config.x as usize;
-        let start_y = config.y as usize;
-        let width = config.width as usize;
-        let height = config.height as usize;
+    ) -> Result<u64, FlowError> {
+        let (start_x, start_y, width, height) = config.scale.rect(
+            config.x as usize,
+            config.y as usize,
+            config.width as usize,
+            config.height as usize,
+        );
         
         // Get the current timestamp
         let usecs = clock.time().unwrap().useconds();
@@ -128,7 +137,7 @@ impl OriginalStamper {
             }
         }
         
-        Ok(())
+        Ok(usecs)
     }
 }
 
@@ -143,7 +152,7 @@ impl Default for OriginalReader {
 
 impl TimestampReader for OriginalReader {
     fn read(
-        &self,
+        &mut self,
         frame: &VideoFrameRef<&BufferRef>,
         clock: &Clock,
         config: &ReaderConfig,
@@ -151,15 +160,21 @@ impl TimestampReader for OriginalReader {
         let fmt = frame.format_info();
         let flags = fmt.flags();
         
-        let (white_fill, black_fill) = if flags.contains(VideoFormatFlags::RGB) {
-            ([255, 255, 255], [0, 0, 0])
-        } else if flags.contains(VideoFormatFlags::YUV) {
-            ([255, 128, 128], [0, 128, 128])
-        } else {
+        if !flags.intersects(VideoFormatFlags::RGB | VideoFormatFlags::YUV) {
             return Err(FlowError::NotSupported);
+        }
+        
+        // Cells carry the palette colours themselves, full swing unless
+        // colours are set
+        let fill = if config.fill.custom {
+            config.fill
+        } else {
+            Fill::full_swing(flags)?
         };
+        let white_fill = &fill.high.components[..3];
+        let black_fill = &fill.low.components[..3];
         
-        self.measure_latency_using_time_code(frame, clock, config, &white_fill, &black_fill)
+        self.measure_latency_using_time_code(frame, clock, config, white_fill, black_fill)
     }
     
     fn name(&self) -> &'static str {
@@ -180,10 +195,12 @@ impl OriginalReader {
         white_fill: &[u8],
         black_fill: &[u8],
     ) -> Result<Option<u64>, FlowError> {
-        let start_x = config.x as usize;
-        let start_y = config.y as usize;
-        let crop_width = config.width as usize;
-        let crop_height = config.height as usize;
+        let (start_x, start_y, crop_width, crop_height) = config.scale.rect(
+            config.x as usize,
+            config.y as usize,
+            config.width as usize,
+            config.height as usize,
+        );
         let tolerance = config.tolerance;
         
         let fmt = frame.format_info();
@@ -197,15 +214,22 @@ impl OriginalReader {
         let col0 = start_x;
         let coln = col0 + crop_width;
         
+        // Pixels are visited in the frame as stamped and looked up
+        // where its orientation moved them
+        let (frame_width, frame_height) = config
+            .orientation
+            .source_size(frame.width() as usize, frame.height() as usize);
+        
         let abs_diff = |a: u8, b: u8| a.checked_sub(b).unwrap_or_else(|| b - a);
         let sub_scale = |val: usize, factor: u32| (-((-(val as i64)) >> factor)) as usize;
         
         // The white/black counts per bit in the 8x8 bitmap
-        let counts = iproduct!(row0..rown, col0..coln).fold(
+        let counts = iproduct!(row0..rown.min(frame_height), col0..coln.min(frame_width)).fold(
             [[[0; 2]; 8]; 8],
             |mut counts, (ir, ic)| {
                 let mut white_votes = 0;
                 let mut black_votes = 0;
+                let (fc, fr) = config.orientation.point(ic, ir, frame_width, frame_height);
                 
                 for args in izip!(
                     fmt.plane(),
@@ -234,8 +258,8 @@ impl OriginalReader {
                     let plane_stride = frame.plane_stride()[plane_ix] as usize;
                     let plane_data = frame.plane_data(plane_ix as u32).unwrap();
                     
-                    let pr = sub_scale(ir, h_sub);
-                    let pc = sub_scale(ic, w_sub);
+                    let pr = sub_scale(fr, h_sub);
+                    let pc = sub_scale(fc, w_sub);
                     let offset = pr * plane_stride + pc * pixel_stride as usize + poffset as usize;
                     
                     if offset >= plane_data.len() {
@@ -288,4 +312,50 @@ impl OriginalReader {
         let stamped_usecs: u64 = u64::from_be_bytes(bytes);
         Ok(Some(stamped_usecs))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing;
+    use gst_video::VideoFormat;
+
+    /// Stamp an I420 frame with `fill`, returns the luma values of the
+    /// code and whether it reads back with the same fill
+    fn stamp_with(fill: Fill) -> (Vec<u8>, bool) {
+        let stamper_config = StamperConfig {
+            fill,
+            ..Default::default()
+        };
+        let reader_config = ReaderConfig {
+            fill,
+            ..Default::default()
+        };
+        let mut frame = testing::frame(VideoFormat::I420, 128, 64, 128);
+        let stamped = testing::stamp(&mut OriginalStamper, &mut frame, &stamper_config);
+
+        let luma = testing::luma(&mut frame);
+        let mut levels: Vec<u8> = (0..64)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .map(|(x, y)| luma.get(x, y) as u8)
+            .collect();
+        levels.sort_unstable();
+        levels.dedup();
+        let read = testing::read(&mut OriginalReader, &frame, &reader_config);
+        (levels, read == Some(stamped))
+    }
+
+    #[test]
+    fn stamps_full_swing_cells_by_default() {
+        assert_eq!(stamp_with(Fill::NOMINAL), (vec![0, 255], true));
+    }
+
+    #[test]
+    fn stamps_palette_colours_once_set() {
+        let fill = Fill {
+            custom: true,
+            ..Fill::gray(192, 32, VideoFormatFlags::YUV).unwrap()
+        };
+        assert_eq!(stamp_with(fill), (vec![32, 192], true));
+    }
+}
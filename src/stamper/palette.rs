@@ -0,0 +1,307 @@
+// Colours of the code cells in the negotiated format
+
+use super::{
+    grid::{BLACK, WHITE},
+    levels::Levels,
+    plane::ComponentPlane,
+};
+use glib::translate::IntoGlib;
+use gst::{BufferRef, FlowError};
+use gst_video::{prelude::*, VideoFormatFlags, VideoFrameRef, VideoInfo, VideoTransferFunction};
+
+/// Colour of cells carrying one bits, white (luma 235 in limited range)
+pub const DEFAULT_HIGH: u32 = 0xFFFFFF;
+
+/// Colour of cells carrying zero bits, near black (luma 20 in limited
+/// range), which survives compression better than pure black
+pub const DEFAULT_LOW: u32 = 0x050505;
+
+/// Colours closer than this in luma decode poorly
+const MIN_CONTRAST: i32 = 64;
+
+/// Luma of diffuse white in PQ streams, in nits (ITU-R BT.2408)
+const PQ_REFERENCE_WHITE_NITS: f64 = 203.0;
+
+/// Scene light of diffuse white in HLG streams, 75% of the signal
+/// range (ITU-R BT.2408)
+const HLG_REFERENCE_WHITE: f64 = 0.265;
+
+/// Cell colours as `0xRRGGBB`, in SDR signal values.
+///
+/// Stamper and reader resolve the same palette against the
+/// colorimetry of their own frames, so they agree on the luma levels
+/// even when a converter on the way changes the format, range or
+/// matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub high: u32,
+    pub low: u32,
+    /// Colours set by the user, `original` keeps its full swing cells
+    /// otherwise
+    pub custom: bool,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            high: DEFAULT_HIGH,
+            low: DEFAULT_LOW,
+            custom: false,
+        }
+    }
+}
+
+/// Palette colour as written to a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colour {
+    /// Value of each component in format order (R, G, B, A or Y, U, V,
+    /// A), alpha opaque
+    pub components: [u8; 4],
+    /// Luma of the colour as the readers measure it
+    pub luma: u8,
+}
+
+/// Palette resolved for one format and colorimetry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fill {
+    pub high: Colour,
+    pub low: Colour,
+    /// Resolved from colours set by the user
+    pub custom: bool,
+}
+
+impl Palette {
+    /// Both colours are shades of gray, codecs write those by themselves
+    pub fn is_gray(&self) -> bool {
+        [self.high, self.low].iter().all(|&colour| {
+            let [r, g, b] = rgb(colour);
+            r == g && g == b
+        })
+    }
+
+    /// The high colour is clearly brighter than the low one
+    pub fn is_legible(&self) -> bool {
+        let luma = |colour| {
+            let [r, g, b] = rgb(colour);
+            (r as i32 + 2 * g as i32 + b as i32) / 4
+        };
+        luma(self.high) - luma(self.low) >= MIN_CONTRAST
+    }
+
+    /// Values of both colours in the format and colorimetry of `info`
+    pub fn resolve(&self, info: &VideoInfo) -> Fill {
+        Fill {
+            high: resolve_colour(self.high, info),
+            low: resolve_colour(self.low, info),
+            custom: self.custom,
+        }
+    }
+}
+
+impl Fill {
+    /// Default palette in limited range YUV, until a format is known
+    pub const NOMINAL: Fill = Fill {
+        high: Colour {
+            components: [WHITE, 128, 128, u8::MAX],
+            luma: WHITE,
+        },
+        low: Colour {
+            components: [BLACK, 128, 128, u8::MAX],
+            luma: BLACK,
+        },
+        custom: false,
+    };
+
+    /// Full swing cells, white at 255 and black at 0, which `original`
+    /// stamps and reads unless colours are set, as it did before
+    /// palettes
+    pub fn full_swing(flags: VideoFormatFlags) -> Result<Fill, FlowError> {
+        Fill::gray(u8::MAX, 0, flags)
+    }
+
+    /// Gray cells at the given signal values, whatever the range and
+    /// matrix
+    pub fn gray(high: u8, low: u8, flags: VideoFormatFlags) -> Result<Fill, FlowError> {
+        let colour = |value: u8| {
+            let components = if flags.contains(VideoFormatFlags::RGB) {
+                [value, value, value, u8::MAX]
+            } else if flags.contains(VideoFormatFlags::YUV) {
+                [value, 128, 128, u8::MAX]
+            } else {
+                return Err(FlowError::NotSupported);
+            };
+            Ok(Colour {
+                components,
+                luma: value,
+            })
+        };
+
+        Ok(Fill {
+            high: colour(high)?,
+            low: colour(low)?,
+            custom: false,
+        })
+    }
+
+    /// Luma levels the codecs stamp and decide with
+    pub fn levels(&self) -> Levels {
+        Levels {
+            black: self.low.luma as i32,
+            white: self.high.luma as i32,
+        }
+    }
+
+    /// Give the cells in the frame rectangle `(x, y, width, height)`
+    /// their palette colour.
+    ///
+    /// Codecs write luma only, as gray on RGB formats. Pixels left at
+    /// exactly one of the palette levels get the other components of
+    /// that colour: chroma on YUV formats, the colour itself on RGB
+    /// formats. Other pixels, e.g. the blended edges of `optimized`
+    /// cells, are left alone.
+    pub fn tint(
+        &self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        (x, y, width, height): (usize, usize, usize, usize),
+    ) -> Result<(), FlowError> {
+        let fmt = frame.format_info();
+        let rgb = fmt.flags().contains(VideoFormatFlags::RGB);
+        let colour_components = (fmt.n_components() as usize).min(3);
+        let written: Vec<usize> = if rgb { vec![0, 1, 2] } else { vec![0] };
+        let tinted: Vec<usize> = if rgb {
+            vec![0, 1, 2]
+        } else {
+            (1..colour_components).collect()
+        };
+
+        let planes = (0..colour_components)
+            .map(|comp| ComponentPlane::new(frame, comp))
+            .collect::<Result<Vec<_>, _>>()?;
+        let subsampling =
+            |comp: usize, px: usize, py: usize| (px >> fmt.w_sub()[comp], py >> fmt.h_sub()[comp]);
+
+        let x_end = (x + width).min(frame.width() as usize);
+        let y_end = (y + height).min(frame.height() as usize);
+
+        // Colour of each pixel the codec left at a palette level
+        let mut pixels = vec![];
+        for py in y..y_end {
+            for px in x..x_end {
+                let samples: Option<Vec<u8>> = written
+                    .iter()
+                    .map(|&comp| {
+                        let plane = &planes[comp];
+                        let (sx, sy) = subsampling(comp, px, py);
+                        let data = frame.plane_data(plane.plane()).unwrap();
+                        plane.mean_rect(data, sx, sy, 1, 1)
+                    })
+                    .collect();
+                let Some(samples) = samples else {
+                    continue;
+                };
+
+                let colour = [&self.high, &self.low]
+                    .into_iter()
+                    .find(|colour| samples.iter().all(|&sample| sample == colour.luma));
+                if let Some(colour) = colour {
+                    pixels.push((px, py, *colour));
+                }
+            }
+        }
+
+        for comp in tinted {
+            let plane = &planes[comp];
+            let data = frame.plane_data_mut(plane.plane()).unwrap();
+            for &(px, py, colour) in &pixels {
+                let (sx, sy) = subsampling(comp, px, py);
+                plane.fill_rect(data, sx, sy, 1, 1, colour.components[comp]);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn rgb(colour: u32) -> [u8; 3] {
+    [(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]
+}
+
+/// Components and luma of an `0xRRGGBB` colour in the format and
+/// colorimetry of `info`
+fn resolve_colour(colour: u32, info: &VideoInfo) -> Colour {
+    let fmt = info.format_info();
+    let colorimetry = info.colorimetry();
+    let (offset, scale) = fmt.range_offsets(colorimetry.range());
+
+    let [r, g, b] = rgb(colour).map(|value| signal(value as f64 / 255.0, colorimetry.transfer()));
+    let values = if fmt.flags().contains(VideoFormatFlags::RGB) {
+        [r, g, b]
+    } else {
+        // Formats without a YUV matrix are converted like SD video
+        let (kr, kb) = colorimetry.matrix().kr_kb().unwrap_or((0.299, 0.114));
+        let luma = kr * r + (1.0 - kr - kb) * g + kb * b;
+        [
+            luma,
+            (b - luma) / (2.0 * (1.0 - kb)),
+            (r - luma) / (2.0 * (1.0 - kr)),
+        ]
+    };
+
+    let mut components = [u8::MAX; 4];
+    for (comp, value) in values.into_iter().enumerate() {
+        components[comp] = (offset[comp] as f64 + scale[comp] as f64 * value)
+            .round()
+            .clamp(0.0, 255.0) as u8;
+    }
+
+    let luma = if fmt.flags().contains(VideoFormatFlags::RGB) {
+        let [r, g, b, _] = components.map(|value| value as u32);
+        ((r + 2 * g + b) / 4) as u8
+    } else {
+        components[0]
+    };
+
+    Colour { components, luma }
+}
+
+/// Signal value of an SDR signal value in streams with the given
+/// transfer function.
+///
+/// PQ and HLG streams get the colour at the reference white of their
+/// range instead of peak brightness.
+fn signal(sdr: f64, transfer: VideoTransferFunction) -> f64 {
+    let linear = sdr.powf(2.4);
+
+    match transfer.into_glib() {
+        gst_video::ffi::GST_VIDEO_TRANSFER_SMPTE2084 => {
+            pq(linear * PQ_REFERENCE_WHITE_NITS / 10000.0)
+        }
+        gst_video::ffi::GST_VIDEO_TRANSFER_ARIB_STD_B67 => hlg(linear * HLG_REFERENCE_WHITE),
+        _ => sdr,
+    }
+}
+
+/// PQ inverse EOTF (SMPTE ST 2084) of a luminance relative to 10000 nits
+fn pq(luminance: f64) -> f64 {
+    const M1: f64 = 0.1593017578125;
+    const M2: f64 = 78.84375;
+    const C1: f64 = 0.8359375;
+    const C2: f64 = 18.8515625;
+    const C3: f64 = 18.6875;
+
+    let power = luminance.powf(M1);
+    ((C1 + C2 * power) / (1.0 + C3 * power)).powf(M2)
+}
+
+/// HLG OETF (ARIB STD-B67) of a relative scene light
+fn hlg(scene: f64) -> f64 {
+    const A: f64 = 0.17883277;
+    const B: f64 = 0.28466892;
+    const C: f64 = 0.55991073;
+
+    if scene <= 1.0 / 12.0 {
+        (3.0 * scene).sqrt()
+    } else {
+        A * (12.0 * scene - B).ln() + C
+    }
+}
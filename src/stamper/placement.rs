@@ -0,0 +1,268 @@
+// Placement of the code region relative to the frame
+
+/// Frame corner, edge centre or centre the code region is attached to.
+///
+/// Stamper and reader resolve the same placement against the frame
+/// size, so they agree on the region at any resolution.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "GstTsLatencyAnchor")]
+pub enum Anchor {
+    #[default]
+    #[enum_value(name = "Top-Left: Top left corner", nick = "top-left")]
+    TopLeft,
+    #[enum_value(name = "Top-Center: Centre of the top edge", nick = "top-center")]
+    TopCenter,
+    #[enum_value(name = "Top-Right: Top right corner", nick = "top-right")]
+    TopRight,
+    #[enum_value(name = "Center-Left: Centre of the left edge", nick = "center-left")]
+    CenterLeft,
+    #[enum_value(name = "Center: Centre of the frame", nick = "center")]
+    Center,
+    #[enum_value(name = "Center-Right: Centre of the right edge", nick = "center-right")]
+    CenterRight,
+    #[enum_value(name = "Bottom-Left: Bottom left corner", nick = "bottom-left")]
+    BottomLeft,
+    #[enum_value(
+        name = "Bottom-Center: Centre of the bottom edge",
+        nick = "bottom-center"
+    )]
+    BottomCenter,
+    #[enum_value(name = "Bottom-Right: Bottom right corner", nick = "bottom-right")]
+    BottomRight,
+}
+
+/// Grid the code region and cell pitch are snapped to.
+///
+/// Encoders quantise and predict on blocks (8x8 transforms, 16x16
+/// H.264 macroblocks, up to 64x64 HEVC CTUs) and subsample chroma, so
+/// a cell straddling a block or chroma boundary is blurred by both
+/// neighbours. Snapping is done in code pixels, exact on frames of the
+/// reference size, so stamper and reader agree whatever each side's
+/// format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "GstTsLatencyBlockAlign")]
+pub enum BlockAlign {
+    #[default]
+    #[enum_value(name = "None: Place the code at any pixel", nick = "none")]
+    None,
+    #[enum_value(
+        name = "Chroma: 2x2 chroma grid of 4:2:0 and 4:2:2 formats",
+        nick = "chroma"
+    )]
+    Chroma,
+    #[enum_value(name = "8: 8x8 transform blocks", nick = "8")]
+    Block8,
+    #[enum_value(name = "16: 16x16 H.264 macroblocks", nick = "16")]
+    Block16,
+    #[enum_value(name = "32: 32x32 HEVC coding tree units", nick = "32")]
+    Block32,
+    #[enum_value(name = "64: 64x64 HEVC coding tree units", nick = "64")]
+    Block64,
+}
+
+impl BlockAlign {
+    /// Grid step in pixels, 1 when not aligned
+    pub fn step(self) -> u32 {
+        match self {
+            BlockAlign::None => 1,
+            BlockAlign::Chroma => 2,
+            BlockAlign::Block8 => 8,
+            BlockAlign::Block16 => 16,
+            BlockAlign::Block32 => 32,
+            BlockAlign::Block64 => 64,
+        }
+    }
+
+    /// Cell pitch that tiles the grid: a power of two is either a
+    /// divisor or a multiple of every block step, and at least two
+    /// pixels covers whole chroma samples
+    pub fn pitch(self, pitch: usize) -> usize {
+        match self {
+            BlockAlign::None => pitch,
+            _ => pitch
+                .next_power_of_two()
+                .max(BlockAlign::Chroma.step() as usize),
+        }
+    }
+}
+
+/// Alignment of the region along one axis
+#[derive(Debug, Clone, Copy)]
+enum Align {
+    Start,
+    Centre,
+    End,
+}
+
+impl Anchor {
+    fn align(self) -> (Align, Align) {
+        match self {
+            Anchor::TopLeft => (Align::Start, Align::Start),
+            Anchor::TopCenter => (Align::Centre, Align::Start),
+            Anchor::TopRight => (Align::End, Align::Start),
+            Anchor::CenterLeft => (Align::Start, Align::Centre),
+            Anchor::Center => (Align::Centre, Align::Centre),
+            Anchor::CenterRight => (Align::End, Align::Centre),
+            Anchor::BottomLeft => (Align::Start, Align::End),
+            Anchor::BottomCenter => (Align::Centre, Align::End),
+            Anchor::BottomRight => (Align::End, Align::End),
+        }
+    }
+}
+
+/// Code region in frame (or reference) pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Placement of the code region as configured on the elements.
+///
+/// `x`/`y` and `margin` are measured inwards from the anchored edges
+/// and shift the region right/down on centred axes, where the margin
+/// does not apply. Percentages of the frame size override `width` and
+/// `height` when non-zero. `alignment` snaps the resolved region
+/// onto the block grid, origin down and size down to whole blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub anchor: Anchor,
+    pub margin: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub width_percent: f64,
+    pub height_percent: f64,
+    pub alignment: BlockAlign,
+}
+
+impl Placement {
+    /// Region in a frame of the given size
+    pub fn resolve(&self, frame_width: u32, frame_height: u32) -> Region {
+        let width = percent_of(self.width_percent, frame_width).unwrap_or(self.width);
+        let height = percent_of(self.height_percent, frame_height).unwrap_or(self.height);
+        let (align_x, align_y) = self.anchor.align();
+
+        let step = self.alignment.step();
+        let snap = |value: u32| value / step * step;
+        Region {
+            x: snap(position(align_x, self.x, self.margin, width, frame_width)),
+            y: snap(position(align_y, self.y, self.margin, height, frame_height)),
+            width: snap(width).max(step),
+            height: snap(height).max(step),
+        }
+    }
+}
+
+/// `percent` of `len`, `None` if the percentage is unset (zero)
+fn percent_of(percent: f64, len: u32) -> Option<u32> {
+    (percent > 0.0).then(|| (len as f64 * percent / 100.0).round() as u32)
+}
+
+/// Origin of a span of `size` pixels along an axis of `len` pixels,
+/// kept inside the frame where the anchor allows it
+fn position(align: Align, offset: u32, margin: u32, size: u32, len: u32) -> u32 {
+    match align {
+        Align::Start => margin + offset,
+        Align::Centre => len.saturating_sub(size) / 2 + offset,
+        Align::End => len.saturating_sub(size + margin + offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::{
+        optimized::{OptimizedReader, OptimizedStamper},
+        testing,
+        traits::{ReaderConfig, StamperConfig},
+        tuning::Tuning,
+    };
+    use gst_video::VideoFormat;
+
+    /// Stamp an `optimized` code with `block`-pixel cells half a block
+    /// away from the top left corner, snapped by `alignment`, keep only
+    /// the mean of every `block`x`block` block and tell whether the
+    /// reader got the value back
+    fn survives_quantisation(alignment: BlockAlign, block: usize) -> bool {
+        let mut tuning = Tuning::default();
+        tuning.layout.cell_size = block;
+        let tuning = tuning.aligned(alignment);
+
+        let placement = Placement {
+            anchor: Anchor::TopLeft,
+            margin: 0,
+            x: block as u32 / 2,
+            y: block as u32 / 2,
+            width: (tuning.layout.grid_width * tuning.layout.cell_size) as u32,
+            height: (tuning.layout.grid_height * tuning.layout.cell_size) as u32,
+            width_percent: 0.0,
+            height_percent: 0.0,
+            alignment,
+        };
+        let region = placement.resolve(224, 160);
+        let stamper_config = StamperConfig {
+            x: region.x,
+            y: region.y,
+            width: region.width,
+            height: region.height,
+            tuning,
+            ..Default::default()
+        };
+        let reader_config = ReaderConfig {
+            x: region.x,
+            y: region.y,
+            width: region.width,
+            height: region.height,
+            tuning,
+            ..Default::default()
+        };
+
+        let mut frame = testing::frame(VideoFormat::I420, 224, 160, 128);
+        let stamped = testing::stamp(&mut OptimizedStamper, &mut frame, &stamper_config);
+        let mut luma = testing::luma(&mut frame);
+        luma.average_blocks(block);
+        testing::set_luma(&mut frame, &luma);
+
+        testing::read(&mut OptimizedReader::default(), &frame, &reader_config) == Some(stamped)
+    }
+
+    #[test]
+    fn snaps_the_region_onto_the_block_grid() {
+        let placement = Placement {
+            anchor: Anchor::BottomRight,
+            margin: 5,
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 70,
+            width_percent: 0.0,
+            height_percent: 0.0,
+            alignment: BlockAlign::Block16,
+        };
+        assert_eq!(
+            placement.resolve(1920, 1080),
+            Region {
+                x: 1808,
+                y: 992,
+                width: 96,
+                height: 64,
+            }
+        );
+    }
+
+    #[test]
+    fn aligned_code_survives_block_quantisation() {
+        assert!(survives_quantisation(BlockAlign::Block8, 8));
+        assert!(survives_quantisation(BlockAlign::Block16, 16));
+    }
+
+    #[test]
+    fn unaligned_code_fails_block_quantisation() {
+        assert!(!survives_quantisation(BlockAlign::None, 8));
+        assert!(!survives_quantisation(BlockAlign::None, 16));
+    }
+}
@@ -0,0 +1,428 @@
+// Luma access on 8-bit video frames
+
+use super::{orient::Orientation, scale::Scale};
+use gst::FlowError;
+use gst_video::{prelude::*, VideoFormatFlags};
+
+/// Data of every plane of a frame, as returned by `planes_data()`
+pub type Planes<'a> = [&'a [u8]];
+
+/// Writable data of every plane of a frame, as returned by
+/// `planes_data_mut()`
+pub type PlanesMut<'a> = [&'a mut [u8]];
+
+/// Layout of the luma samples of a frame.
+///
+/// YUV and gray formats use the Y component directly. RGB formats,
+/// packed or planar, read the luma as `(R + 2G + B) / 4` and write gray
+/// pixels.
+///
+/// Coordinates are code pixels, mapped onto the frame through the
+/// plane's scale and orientation (identity unless set with
+/// [`LumaPlane::scaled`] and [`LumaPlane::oriented`]).
+#[derive(Debug, Clone)]
+pub struct LumaPlane {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    scale: Scale,
+    orientation: Orientation,
+}
+
+impl LumaPlane {
+    pub fn new(frame: &impl VideoFrameExt) -> Result<Self, FlowError> {
+        let fmt = frame.format_info();
+
+        let components: &[usize] = if fmt.flags().contains(VideoFormatFlags::RGB) {
+            &[0, 1, 2]
+        } else {
+            &[0]
+        };
+
+        let supported = components.iter().all(|&comp| {
+            fmt.depth()[comp] == 8
+                && fmt.shift()[comp] == 0
+                && fmt.w_sub()[comp] == 0
+                && fmt.h_sub()[comp] == 0
+        });
+        if !supported {
+            return Err(FlowError::NotSupported);
+        }
+
+        Ok(Self {
+            width: frame.width() as usize,
+            height: frame.height() as usize,
+            components: components
+                .iter()
+                .map(|&comp| {
+                    let plane = fmt.plane()[comp] as usize;
+                    Component {
+                        plane,
+                        stride: frame.plane_stride()[plane] as usize,
+                        pixel_stride: fmt.pixel_stride()[comp] as usize,
+                        offset: fmt.poffset()[comp] as usize,
+                    }
+                })
+                .collect(),
+            scale: Scale::IDENTITY,
+            orientation: Orientation::Identity,
+        })
+    }
+
+    /// Address the plane in code pixels of the given scale
+    pub fn scaled(self, scale: Scale) -> Self {
+        Self { scale, ..self }
+    }
+
+    /// Address the plane as it was stamped, before the frame went
+    /// through `orientation`
+    pub fn oriented(self, orientation: Orientation) -> Self {
+        Self {
+            orientation,
+            ..self
+        }
+    }
+
+    /// Width in code pixels
+    pub fn width(&self) -> usize {
+        let (width, _) = self.orientation.source_size(self.width, self.height);
+        self.scale.code_width(width)
+    }
+
+    /// Height in code pixels
+    pub fn height(&self) -> usize {
+        let (_, height) = self.orientation.source_size(self.width, self.height);
+        self.scale.code_height(height)
+    }
+
+    /// Frame rectangle covered by a code rectangle
+    fn frame_rect(
+        &self,
+        (x, y, width, height): (usize, usize, usize, usize),
+    ) -> (usize, usize, usize, usize) {
+        let (frame_width, frame_height) = self.orientation.source_size(self.width, self.height);
+        self.orientation.rect(
+            self.scale.rect(x, y, width, height),
+            frame_width,
+            frame_height,
+        )
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+
+    fn get_pixel(&self, data: &Planes, x: usize, y: usize) -> Option<u8> {
+        if !self.contains(x, y) {
+            return None;
+        }
+        let sample = |component: &Component| component.get(data, x, y);
+
+        match self.components.as_slice() {
+            [luma] => sample(luma),
+            [r, g, b] => {
+                let (r, g, b) = (sample(r)? as u32, sample(g)? as u32, sample(b)? as u32);
+                Some(((r + 2 * g + b) / 4) as u8)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Apply `op` to every luma component of the frame pixels covered
+    /// by a code rectangle, ignoring pixels outside the frame
+    fn update_rect(
+        &self,
+        data: &mut PlanesMut,
+        (x, y, width, height): (usize, usize, usize, usize),
+        op: impl Fn(u8) -> u8,
+    ) {
+        let (x, y, width, height) = self.frame_rect((x, y, width, height));
+
+        for py in y..y + height {
+            for px in x..x + width {
+                if !self.contains(px, py) {
+                    continue;
+                }
+
+                for component in &self.components {
+                    if let Some(sample) = component.get_mut(data, px, py) {
+                        *sample = op(*sample);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Luma value at `(x, y)`, or `None` outside the frame
+    pub fn get(&self, data: &Planes, x: usize, y: usize) -> Option<u8> {
+        let (x, y) = self.scale.point(x, y);
+        let (frame_width, frame_height) = self.orientation.source_size(self.width, self.height);
+        if x >= frame_width || y >= frame_height {
+            return None;
+        }
+
+        let (x, y) = self.orientation.point(x, y, frame_width, frame_height);
+        self.get_pixel(data, x, y)
+    }
+
+    pub fn fill_rect(
+        &self,
+        data: &mut PlanesMut,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        value: u8,
+    ) {
+        self.update_rect(data, (x, y, width, height), |_| value);
+    }
+
+    /// Add `delta` to the luma of a rectangle, saturating at the ends
+    /// of the range. Packed RGB formats keep their colour.
+    pub fn add_rect(
+        &self,
+        data: &mut PlanesMut,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        delta: i32,
+    ) {
+        self.update_rect(data, (x, y, width, height), |value| {
+            (value as i32 + delta).clamp(0, 255) as u8
+        });
+    }
+
+    /// Mean luma over the part of the rectangle inside the frame
+    pub fn mean_rect(
+        &self,
+        data: &Planes,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<u8> {
+        let (x, y, width, height) = self.frame_rect((x, y, width, height));
+        let mut sum = 0u32;
+        let mut count = 0u32;
+
+        for py in y..y + height {
+            for px in x..x + width {
+                if let Some(value) = self.get_pixel(data, px, py) {
+                    sum += value as u32;
+                    count += 1;
+                }
+            }
+        }
+
+        (count > 0).then(|| (sum / count) as u8)
+    }
+}
+
+/// Location of a luma or colour component within the planes of a
+/// frame
+#[derive(Debug, Clone)]
+struct Component {
+    plane: usize,
+    stride: usize,
+    pixel_stride: usize,
+    offset: usize,
+}
+
+impl Component {
+    fn index(&self, x: usize, y: usize) -> usize {
+        y * self.stride + x * self.pixel_stride + self.offset
+    }
+
+    fn get(&self, data: &Planes, x: usize, y: usize) -> Option<u8> {
+        data.get(self.plane)?.get(self.index(x, y)).copied()
+    }
+
+    fn get_mut<'a>(&self, data: &'a mut PlanesMut, x: usize, y: usize) -> Option<&'a mut u8> {
+        data.get_mut(self.plane)?.get_mut(self.index(x, y))
+    }
+}
+
+/// Layout of one colour component of a frame, e.g. R, G, B or Y, U, V
+#[derive(Debug, Clone)]
+pub struct ComponentPlane {
+    plane: u32,
+    width: usize,
+    height: usize,
+    stride: usize,
+    pixel_stride: usize,
+    offset: usize,
+    scale: Scale,
+    orientation: Orientation,
+}
+
+impl ComponentPlane {
+    pub fn new(frame: &impl VideoFrameExt, comp: usize) -> Result<Self, FlowError> {
+        let fmt = frame.format_info();
+
+        if comp >= fmt.n_components() as usize || fmt.depth()[comp] != 8 || fmt.shift()[comp] != 0 {
+            return Err(FlowError::NotSupported);
+        }
+
+        let plane = fmt.plane()[comp];
+        Ok(Self {
+            plane,
+            width: fmt.scale_width(comp as u8, frame.width()) as usize,
+            height: fmt.scale_height(comp as u8, frame.height()) as usize,
+            stride: frame.plane_stride()[plane as usize] as usize,
+            pixel_stride: fmt.pixel_stride()[comp] as usize,
+            offset: fmt.poffset()[comp] as usize,
+            scale: Scale::IDENTITY,
+            orientation: Orientation::Identity,
+        })
+    }
+
+    /// Components that can carry separate bits at full resolution.
+    ///
+    /// All colour components of formats without chroma subsampling,
+    /// otherwise only the first one, the luma. Alpha is never used.
+    pub fn independent(frame: &impl VideoFrameExt) -> Result<Vec<Self>, FlowError> {
+        let fmt = frame.format_info();
+        let colour = (fmt.n_components() as usize).min(3);
+        let subsampled = (0..colour).any(|comp| fmt.w_sub()[comp] != 0 || fmt.h_sub()[comp] != 0);
+        let count = if subsampled { 1 } else { colour };
+
+        (0..count).map(|comp| Self::new(frame, comp)).collect()
+    }
+
+    /// Address the component in code pixels of the given scale
+    pub fn scaled(self, scale: Scale) -> Self {
+        Self { scale, ..self }
+    }
+
+    /// Address the component as it was stamped, before the frame went
+    /// through `orientation`
+    pub fn oriented(self, orientation: Orientation) -> Self {
+        Self {
+            orientation,
+            ..self
+        }
+    }
+
+    /// Frame rectangle covered by a code rectangle
+    fn frame_rect(
+        &self,
+        (x, y, width, height): (usize, usize, usize, usize),
+    ) -> (usize, usize, usize, usize) {
+        let (frame_width, frame_height) = self.orientation.source_size(self.width, self.height);
+        self.orientation.rect(
+            self.scale.rect(x, y, width, height),
+            frame_width,
+            frame_height,
+        )
+    }
+
+    /// Index of the plane holding this component
+    pub fn plane(&self) -> u32 {
+        self.plane
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height)
+            .then(|| y * self.stride + x * self.pixel_stride + self.offset)
+    }
+
+    pub fn fill_rect(
+        &self,
+        data: &mut [u8],
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        value: u8,
+    ) {
+        let (x, y, width, height) = self.frame_rect((x, y, width, height));
+
+        for py in y..y + height {
+            for px in x..x + width {
+                if let Some(sample) = self.index(px, py).and_then(|i| data.get_mut(i)) {
+                    *sample = value;
+                }
+            }
+        }
+    }
+
+    /// Mean over the part of the rectangle inside the frame
+    pub fn mean_rect(
+        &self,
+        data: &[u8],
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<u8> {
+        let (x, y, width, height) = self.frame_rect((x, y, width, height));
+        let mut sum = 0u32;
+        let mut count = 0u32;
+
+        for py in y..y + height {
+            for px in x..x + width {
+                if let Some(&value) = self.index(px, py).and_then(|i| data.get(i)) {
+                    sum += value as u32;
+                    count += 1;
+                }
+            }
+        }
+
+        (count > 0).then(|| (sum / count) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing;
+    use gst_video::{VideoFormat, VideoFrameRef};
+
+    /// Paint a rectangle and read it back, returns the mean inside and
+    /// the sample of every plane at its first pixel
+    fn round_trip(format: VideoFormat) -> (Option<u8>, Vec<u8>) {
+        let (info, mut buffer) = testing::frame(format, 32, 16, 128);
+        let mut frame =
+            VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), &info).unwrap();
+        let luma = LumaPlane::new(&frame).unwrap();
+        luma.fill_rect(&mut frame.planes_data_mut(), 4, 4, 8, 8, 200);
+
+        let data = frame.planes_data();
+        let mean = luma.mean_rect(&data, 4, 4, 8, 8);
+        let samples = (0..frame.n_planes() as usize)
+            .map(|plane| {
+                let stride = frame.plane_stride()[plane] as usize;
+                data[plane][4 * stride + 4 * frame.format_info().pixel_stride()[0] as usize]
+            })
+            .collect();
+        (mean, samples)
+    }
+
+    #[test]
+    fn gray_and_yuv_write_luma_only() {
+        assert_eq!(round_trip(VideoFormat::Gray8), (Some(200), vec![200]));
+        assert_eq!(
+            round_trip(VideoFormat::I420),
+            (Some(200), vec![200, 128, 128])
+        );
+        assert_eq!(
+            round_trip(VideoFormat::Y444),
+            (Some(200), vec![200, 128, 128])
+        );
+    }
+
+    #[test]
+    fn packed_rgb_writes_gray_pixels() {
+        let (mean, _) = round_trip(VideoFormat::Rgbx);
+        assert_eq!(mean, Some(200));
+    }
+
+    #[test]
+    fn planar_rgb_writes_every_plane() {
+        assert_eq!(
+            round_trip(VideoFormat::Gbr),
+            (Some(200), vec![200, 200, 200])
+        );
+    }
+}
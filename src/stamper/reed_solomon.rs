@@ -0,0 +1,596 @@
+// Reed-Solomon timestamp stamper over GF(2^8) with configurable parity
+
+use super::{
+    grid::CellGrid,
+    interleave::Interleave,
+    levels::Levels,
+    plane::LumaPlane,
+    traits::{ReaderConfig, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+use once_cell::sync::Lazy;
+
+/// Pixels per bit in each direction
+const CELL_SIZE: usize = 4;
+
+/// The timestamp is sent as 8 big-endian data symbols
+const DATA_SYMBOLS: usize = 8;
+
+/// GF(2^8) exponent and logarithm tables for the primitive polynomial
+/// x^8 + x^4 + x^3 + x^2 + 1 (0x11d). The exponent table is doubled
+/// so products of two logarithms need no modulo.
+struct GaloisTables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+static GF: Lazy<GaloisTables> = Lazy::new(|| {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+
+    let mut x = 1u16;
+    for (i, value) in exp.iter_mut().take(255).enumerate() {
+        *value = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+    }
+    for i in 255..512 {
+        exp[i] = exp[i - 255];
+    }
+
+    GaloisTables { exp, log }
+});
+
+fn gf_mul(x: u8, y: u8) -> u8 {
+    if x == 0 || y == 0 {
+        return 0;
+    }
+    GF.exp[GF.log[x as usize] as usize + GF.log[y as usize] as usize]
+}
+
+fn gf_div(x: u8, y: u8) -> u8 {
+    if x == 0 {
+        return 0;
+    }
+    GF.exp[(GF.log[x as usize] as usize + 255 - GF.log[y as usize] as usize) % 255]
+}
+
+fn gf_inverse(x: u8) -> u8 {
+    GF.exp[255 - GF.log[x as usize] as usize]
+}
+
+/// `2^power` in GF(2^8), negative powers allowed
+fn gf_pow2(power: isize) -> u8 {
+    GF.exp[power.rem_euclid(255) as usize]
+}
+
+// Polynomials are stored with the highest degree coefficient first.
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| gf_mul(c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut r = vec![0u8; len];
+    for (i, &c) in p.iter().enumerate() {
+        r[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        r[i + len - q.len()] ^= c;
+    }
+    r
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0u8; p.len() + q.len() - 1];
+    for (j, &qc) in q.iter().enumerate() {
+        for (i, &pc) in p.iter().enumerate() {
+            r[i + j] ^= gf_mul(pc, qc);
+        }
+    }
+    r
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p.iter().fold(0, |y, &c| gf_mul(y, x) ^ c)
+}
+
+/// Generator polynomial `(x - 2^0)(x - 2^1)...(x - 2^(parity - 1))`
+fn generator_poly(parity: usize) -> Vec<u8> {
+    (0..parity).fold(vec![1], |g, i| poly_mul(&g, &[1, gf_pow2(i as isize)]))
+}
+
+/// Systematic encoding: the message followed by `parity` symbols
+fn rs_encode(msg: &[u8], parity: usize) -> Vec<u8> {
+    let generator = generator_poly(parity);
+    let mut out = msg.to_vec();
+    out.resize(msg.len() + parity, 0);
+
+    for i in 0..msg.len() {
+        let coef = out[i];
+        if coef != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                out[i + j] ^= gf_mul(g, coef);
+            }
+        }
+    }
+
+    out[..msg.len()].copy_from_slice(msg);
+    out
+}
+
+/// Syndromes, padded with a leading zero
+fn syndromes(codeword: &[u8], parity: usize) -> Vec<u8> {
+    std::iter::once(0)
+        .chain((0..parity).map(|i| poly_eval(codeword, gf_pow2(i as isize))))
+        .collect()
+}
+
+/// Syndromes with the known erasures removed (Forney syndromes)
+fn forney_syndromes(synd: &[u8], erasures: &[usize], len: usize) -> Vec<u8> {
+    let mut fsynd = synd[1..].to_vec();
+    for &pos in erasures {
+        let x = gf_pow2((len - 1 - pos) as isize);
+        for j in 0..fsynd.len() - 1 {
+            fsynd[j] = gf_mul(fsynd[j], x) ^ fsynd[j + 1];
+        }
+    }
+    fsynd
+}
+
+/// Berlekamp-Massey search for the error locator polynomial
+fn error_locator(synd: &[u8], parity: usize, erase_count: usize) -> Option<Vec<u8>> {
+    let mut err_loc = vec![1u8];
+    let mut old_loc = vec![1u8];
+    let synd_shift = synd.len().saturating_sub(parity);
+
+    for i in 0..parity - erase_count {
+        let k = i + synd_shift;
+        let mut delta = synd[k];
+        for j in 1..err_loc.len() {
+            delta ^= gf_mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+        }
+        old_loc.push(0);
+
+        if delta != 0 {
+            if old_loc.len() > err_loc.len() {
+                let new_loc = poly_scale(&old_loc, delta);
+                old_loc = poly_scale(&err_loc, gf_inverse(delta));
+                err_loc = new_loc;
+            }
+            err_loc = poly_add(&err_loc, &poly_scale(&old_loc, delta));
+        }
+    }
+
+    let leading = err_loc.iter().take_while(|&&c| c == 0).count();
+    err_loc.drain(..leading);
+
+    let errors = err_loc.len().checked_sub(1)?;
+    (errors * 2 <= parity - erase_count).then_some(err_loc)
+}
+
+/// Chien search for the roots of the error locator
+fn error_positions(err_loc: &[u8], len: usize) -> Option<Vec<usize>> {
+    let reversed: Vec<u8> = err_loc.iter().rev().copied().collect();
+    let positions: Vec<usize> = (0..len)
+        .filter(|&i| poly_eval(&reversed, gf_pow2(i as isize)) == 0)
+        .map(|i| len - 1 - i)
+        .collect();
+
+    (positions.len() == err_loc.len() - 1).then_some(positions)
+}
+
+/// Forney algorithm computing the error magnitudes at `positions`
+fn correct_errata(codeword: &mut [u8], synd: &[u8], positions: &[usize]) -> Option<()> {
+    let len = codeword.len();
+    let coef_pos: Vec<usize> = positions.iter().map(|&p| len - 1 - p).collect();
+
+    let err_loc = coef_pos.iter().fold(vec![1u8], |loc, &i| {
+        poly_mul(&loc, &poly_add(&[1], &[gf_pow2(i as isize), 0]))
+    });
+
+    // Error evaluator: (S(x) * Λ(x)) mod x^(errata + 1)
+    let synd_rev: Vec<u8> = synd.iter().rev().copied().collect();
+    let product = poly_mul(&synd_rev, &err_loc);
+    let err_eval: Vec<u8> = product[product.len() - err_loc.len()..]
+        .iter()
+        .rev()
+        .copied()
+        .collect();
+
+    let x: Vec<u8> = coef_pos
+        .iter()
+        .map(|&p| gf_pow2(-(255 - p as isize)))
+        .collect();
+
+    for (i, &xi) in x.iter().enumerate() {
+        let xi_inv = gf_inverse(xi);
+
+        let err_loc_prime = x
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1u8, |acc, (_, &xj)| gf_mul(acc, 1 ^ gf_mul(xi_inv, xj)));
+        if err_loc_prime == 0 {
+            return None;
+        }
+
+        let eval_rev: Vec<u8> = err_eval.iter().rev().copied().collect();
+        let y = gf_mul(xi, poly_eval(&eval_rev, xi_inv));
+
+        codeword[positions[i]] ^= gf_div(y, err_loc_prime);
+    }
+
+    Some(())
+}
+
+/// Correct `codeword` in place.
+///
+/// Up to `parity` erasures at known positions and `(parity -
+/// erasures) / 2` errors at unknown positions can be corrected.
+/// Returns the number of corrected symbols, or `None` if the codeword
+/// is beyond repair.
+fn rs_correct(codeword: &mut [u8], parity: usize, erasures: &[usize]) -> Option<usize> {
+    if erasures.len() > parity {
+        return None;
+    }
+    for &pos in erasures {
+        codeword[pos] = 0;
+    }
+
+    let synd = syndromes(codeword, parity);
+    if synd.iter().all(|&s| s == 0) {
+        return Some(0);
+    }
+
+    let fsynd = forney_syndromes(&synd, erasures, codeword.len());
+    let err_loc = error_locator(&fsynd, parity, erasures.len())?;
+    let mut positions = erasures.to_vec();
+    positions.extend(error_positions(&err_loc, codeword.len())?);
+
+    correct_errata(codeword, &synd, &positions)?;
+
+    syndromes(codeword, parity)
+        .iter()
+        .all(|&s| s == 0)
+        .then_some(positions.len())
+}
+
+/// Symbols with a cell closer than this to the threshold may be erased
+const ERASURE_MARGIN: u8 = 48;
+
+/// Number of symbols of a codeword with `parity` parity symbols
+pub fn codeword_len(parity: usize) -> usize {
+    DATA_SYMBOLS + parity
+}
+
+/// Codeword of a timestamp, with its symbols in stamping order.
+///
+/// Interleaving moves whole symbols, a symbol's bits stay in adjacent
+/// cells.
+pub fn encode_symbols(timestamp: u64, parity: usize, interleave: Interleave) -> Vec<u8> {
+    let codeword = rs_encode(&timestamp.to_be_bytes(), parity);
+    let slots = interleave.permutation(codeword.len());
+
+    let mut symbols = vec![0u8; codeword.len()];
+    for (&symbol, &slot) in codeword.iter().zip(&slots) {
+        symbols[slot] = symbol;
+    }
+    symbols
+}
+
+/// Bits of the symbols, most significant first
+pub fn symbol_bits(symbols: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    symbols
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+}
+
+/// Timestamp from symbols in stamping order.
+///
+/// `margins` tells how far the most doubtful cell of each symbol was
+/// from the decision level. If errors-only decoding fails, the least
+/// reliable symbols are retried as erasures.
+pub fn decode_symbols(
+    symbols: &[u8],
+    margins: &[u8],
+    parity: usize,
+    interleave: Interleave,
+) -> Option<u64> {
+    let slots = interleave.permutation(symbols.len());
+    let codeword: Vec<u8> = slots.iter().map(|&slot| symbols[slot]).collect();
+
+    // Least reliable symbol first
+    let mut reliability: Vec<(u8, usize)> = slots
+        .iter()
+        .enumerate()
+        .map(|(symbol, &slot)| (margins[slot], symbol))
+        .collect();
+    reliability.sort();
+
+    let doubtful: Vec<usize> = reliability
+        .iter()
+        .take_while(|&&(margin, _)| margin < ERASURE_MARGIN)
+        .map(|&(_, symbol)| symbol)
+        // Erasing all parity symbols would accept any codeword
+        .take(parity.saturating_sub(2))
+        .collect();
+
+    // Errors only first, then erase more and more doubtful symbols,
+    // each erasure costs one parity symbol instead of two
+    let codeword = (0..=doubtful.len()).find_map(|erased| {
+        let mut candidate = codeword.clone();
+        rs_correct(&mut candidate, parity, &doubtful[..erased]).map(|_| candidate)
+    })?;
+
+    Some(u64::from_be_bytes(
+        codeword[..DATA_SYMBOLS].try_into().unwrap(),
+    ))
+}
+
+/// Timestamp from the mean luma of each cell in stamping order, bits
+/// are told apart by the threshold of `levels`
+pub fn decode_samples(
+    samples: &[u8],
+    parity: usize,
+    interleave: Interleave,
+    levels: Levels,
+) -> Option<u64> {
+    let symbols: Vec<u8> = samples
+        .chunks(8)
+        .map(|cells| {
+            cells.iter().fold(0u8, |byte, &mean| {
+                (byte << 1) | levels.bit(mean as u32) as u8
+            })
+        })
+        .collect();
+    let margins: Vec<u8> = samples
+        .chunks(8)
+        .map(|cells| {
+            cells
+                .iter()
+                .map(|&mean| levels.soft(mean as u32).unsigned_abs().min(255) as u8)
+                .min()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    decode_symbols(&symbols, &margins, parity, interleave)
+}
+
+/// Grid holding the codeword, or `None` if it does not fit the region
+fn code_grid(x: u32, y: u32, width: u32, height: u32, parity: usize) -> Option<CellGrid> {
+    let grid = CellGrid::fit(x, y, width, height, CELL_SIZE);
+    (codeword_len(parity) * 8 <= grid.capacity()).then_some(grid)
+}
+
+/// Whether the code with `parity` parity symbols fits a region of the
+/// given size
+pub fn fits(width: u32, height: u32, parity: usize) -> bool {
+    code_grid(0, 0, width, height, parity).is_some()
+}
+
+/// Reed-Solomon stamper
+///
+/// - Sends the 64-bit timestamp as 8 data symbols over GF(2^8)
+/// - Appends `parity_symbols` parity symbols from the configuration,
+///   correcting up to half as many corrupted symbols (bytes)
+/// - Each bit is a 4x4 pixel cell, 6 parity symbols fit in 64x28 pixels
+/// - Interleaving moves whole symbols, since splitting a symbol's bits
+///   would let one burst corrupt more symbols
+pub struct ReedSolomonStamper;
+
+impl Default for ReedSolomonStamper {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampStamper for ReedSolomonStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let grid = code_grid(config.x, config.y, config.width, config.height, parity)
+            .ok_or(FlowError::NotSupported)?;
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let symbols = encode_symbols(timestamp_usecs, parity, config.interleave);
+
+        let luma = LumaPlane::new(&**frame)?.scaled(config.scale);
+        let data = &mut frame.planes_data_mut();
+        let bits = symbol_bits(&symbols);
+        grid.stamp(&luma, data, bits, config.fill.levels());
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "reed-solomon"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reed-Solomon GF(2^8) stamper with configurable parity symbols"
+    }
+}
+
+/// Reed-Solomon reader
+///
+/// Symbols with a cell close to the threshold are retried as erasures
+/// when errors-only decoding fails.
+pub struct ReedSolomonReader;
+
+impl Default for ReedSolomonReader {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampReader for ReedSolomonReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let Some(grid) = code_grid(config.x, config.y, config.width, config.height, parity) else {
+            return Ok(None);
+        };
+
+        let luma = LumaPlane::new(frame)?
+            .scaled(config.scale)
+            .oriented(config.orientation);
+        let data = &frame.planes_data();
+        let Some(samples) = grid.sample(&luma, data, codeword_len(parity) * 8) else {
+            return Ok(None);
+        };
+
+        Ok(decode_samples(
+            &samples,
+            parity,
+            config.interleave,
+            config.fill.levels(),
+        ))
+    }
+
+    fn name(&self) -> &'static str {
+        "reed-solomon"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reed-Solomon GF(2^8) reader correcting up to half the parity symbols"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::grid::{BLACK, WHITE};
+
+    const TIMESTAMP: u64 = 0x0006_2F1D_83A4_5C07;
+    const INTERLEAVES: [Interleave; 3] = [Interleave::None, Interleave::Block, Interleave::Random];
+
+    /// Stamped symbols with `corrupt` slots flipped, and their margins,
+    /// `margin` for the flipped slots and full ones elsewhere
+    fn received(
+        parity: usize,
+        interleave: Interleave,
+        corrupt: &[usize],
+        margin: u8,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut symbols = encode_symbols(TIMESTAMP, parity, interleave);
+        let mut margins = vec![u8::MAX; symbols.len()];
+        for &slot in corrupt {
+            symbols[slot] ^= 0x5A;
+            margins[slot] = margin;
+        }
+        (symbols, margins)
+    }
+
+    #[test]
+    fn field_inverse() {
+        for x in 1..=255 {
+            assert_eq!(gf_mul(x, gf_inverse(x)), 1, "{}", x);
+            assert_eq!(gf_div(gf_mul(x, 0x53), 0x53), x, "{}", x);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for interleave in INTERLEAVES {
+            for parity in [2, 6, 16] {
+                let (symbols, margins) = received(parity, interleave, &[], 0);
+                assert_eq!(symbols.len(), codeword_len(parity));
+                assert_eq!(
+                    decode_symbols(&symbols, &margins, parity, interleave),
+                    Some(TIMESTAMP),
+                    "{:?} parity {}",
+                    interleave,
+                    parity
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn corrects_half_the_parity_in_errors() {
+        for interleave in INTERLEAVES {
+            let (symbols, margins) = received(6, interleave, &[0, 5, 11], u8::MAX);
+            assert_eq!(
+                decode_symbols(&symbols, &margins, 6, interleave),
+                Some(TIMESTAMP),
+                "{:?}",
+                interleave
+            );
+        }
+    }
+
+    #[test]
+    fn erases_doubtful_symbols() {
+        // Two parity symbols are never erased, they check the result:
+        // four erasures and one error
+        let corrupt = [1, 4, 7, 9, 13];
+        for interleave in INTERLEAVES {
+            let (symbols, margins) = received(6, interleave, &corrupt, ERASURE_MARGIN / 2);
+            assert_eq!(
+                decode_symbols(&symbols, &margins, 6, interleave),
+                Some(TIMESTAMP),
+                "{:?}",
+                interleave
+            );
+
+            // Without margins the same symbols are beyond correction
+            let (symbols, margins) = received(6, interleave, &corrupt, u8::MAX);
+            assert_eq!(
+                decode_symbols(&symbols, &margins, 6, interleave),
+                None,
+                "{:?}",
+                interleave
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_uncorrectable_codewords() {
+        for interleave in INTERLEAVES {
+            let (symbols, margins) = received(6, interleave, &[0, 3, 6, 10], u8::MAX);
+            assert_eq!(
+                decode_symbols(&symbols, &margins, 6, interleave),
+                None,
+                "{:?}",
+                interleave
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_cell_samples() {
+        let levels = Levels {
+            black: BLACK as i32,
+            white: WHITE as i32,
+        };
+        for interleave in INTERLEAVES {
+            let mut samples: Vec<u8> = symbol_bits(&encode_symbols(TIMESTAMP, 6, interleave))
+                .map(|bit| if bit { WHITE } else { BLACK })
+                .collect();
+            // A cell near the decision level erases its symbol
+            samples[3] = 130;
+            samples[20] = 120;
+            assert_eq!(
+                decode_samples(&samples, 6, interleave, levels),
+                Some(TIMESTAMP),
+                "{:?}",
+                interleave
+            );
+        }
+    }
+}
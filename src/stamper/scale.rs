@@ -0,0 +1,98 @@
+// Mapping of code geometry onto frames of another resolution
+
+/// Frame pixels per code pixel in each direction.
+///
+/// Code geometry (region, cells, blocks) is given in pixels of a
+/// reference resolution. A frame scaled to another resolution on the
+/// way carries the same code at a different pitch, which the readers
+/// follow by mapping every code rectangle through the scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Scale {
+    pub const IDENTITY: Self = Self { x: 1.0, y: 1.0 };
+
+    /// Scale from the reference resolution to the frame's, identity if
+    /// the reference is unset (zero)
+    pub fn new(
+        frame_width: u32,
+        frame_height: u32,
+        reference_width: u32,
+        reference_height: u32,
+    ) -> Self {
+        if reference_width == 0 || reference_height == 0 {
+            return Self::IDENTITY;
+        }
+
+        Self {
+            x: frame_width as f64 / reference_width as f64,
+            y: frame_height as f64 / reference_height as f64,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    /// Frame pixel holding the centre of code pixel `(x, y)`
+    pub fn point(&self, x: usize, y: usize) -> (usize, usize) {
+        if self.is_identity() {
+            return (x, y);
+        }
+
+        (
+            ((x as f64 + 0.5) * self.x) as usize,
+            ((y as f64 + 0.5) * self.y) as usize,
+        )
+    }
+
+    /// Frame rectangle covered by a code rectangle, at least one pixel
+    /// in each direction unless the code rectangle is empty
+    pub fn rect(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> (usize, usize, usize, usize) {
+        if self.is_identity() {
+            return (x, y, width, height);
+        }
+
+        let (x, width) = span(x, width, self.x);
+        let (y, height) = span(y, height, self.y);
+        (x, y, width, height)
+    }
+
+    /// Code pixels covering `len` frame pixels in the x direction
+    pub fn code_width(&self, len: usize) -> usize {
+        (len as f64 / self.x) as usize
+    }
+
+    /// Code pixels covering `len` frame pixels in the y direction
+    pub fn code_height(&self, len: usize) -> usize {
+        (len as f64 / self.y) as usize
+    }
+}
+
+/// Start and length of the frame pixels covering code pixels
+/// `start..start + len`
+fn span(start: usize, len: usize, scale: f64) -> (usize, usize) {
+    let begin = (start as f64 * scale).round() as usize;
+    let end = ((start + len) as f64 * scale).round() as usize;
+
+    if len == 0 {
+        (begin, 0)
+    } else {
+        (begin, end.max(begin + 1) - begin)
+    }
+}
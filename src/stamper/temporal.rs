@@ -0,0 +1,313 @@
+// Temporal payload spreading across consecutive frames
+//
+// Every frame carries the low 24 bits of the microsecond timestamp, the
+// frame's phase within a cycle of `CYCLE` frames and one 8-bit chunk of
+// the high bits. The reader collects the chunks of a full cycle to
+// rebuild the high bits and from then on completes every frame's low
+// bits on its own, so the per-frame code is a third of a full 64-bit
+// code.
+
+use super::{
+    grid::CellGrid,
+    integrity::crc8,
+    plane::LumaPlane,
+    traits::{ReaderConfig, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Pixels per bit in each direction
+const CELL_SIZE: usize = 4;
+
+/// Bits of the timestamp sent on every frame, wrapping every ~16.8 s
+const LOW_BITS: u32 = 24;
+
+/// The 40 high bits are sent as five 8-bit chunks, one per frame
+const CYCLE: usize = 5;
+const PHASE_BITS: u32 = 3;
+const CHUNK_BITS: u32 = 8;
+
+/// Low bits + phase + chunk + CRC8
+const FRAME_BITS: usize = (LOW_BITS + PHASE_BITS + CHUNK_BITS + 8) as usize;
+
+/// Grid holding the per-frame field, or `None` if it does not fit
+fn code_grid(x: u32, y: u32, width: u32, height: u32) -> Option<CellGrid> {
+    let grid = CellGrid::fit(x, y, width, height, CELL_SIZE);
+    (FRAME_BITS <= grid.capacity()).then_some(grid)
+}
+
+/// Whether the per-frame field fits a region of the given size
+pub fn fits(width: u32, height: u32) -> bool {
+    code_grid(0, 0, width, height).is_some()
+}
+
+/// Payload of one frame without its CRC
+fn frame_word(timestamp: u64, phase: usize) -> u64 {
+    let low = timestamp & ((1 << LOW_BITS) - 1);
+    let chunk = (timestamp >> (LOW_BITS + phase as u32 * CHUNK_BITS)) & 0xFF;
+    (((low << PHASE_BITS) | phase as u64) << CHUNK_BITS) | chunk
+}
+
+/// Temporal stamper
+///
+/// - 24 low timestamp bits, 3 phase bits, 8 high bits and a CRC8 per
+///   frame: 43 bits of 4x4 pixel cells, 64x12 pixels
+/// - The 40 high bits take a cycle of 5 frames
+#[derive(Default)]
+pub struct TemporalStamper {
+    frame_count: u64,
+}
+
+impl TimestampStamper for TemporalStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        let grid = code_grid(config.x, config.y, config.width, config.height)
+            .ok_or(FlowError::NotSupported)?;
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let phase = (self.frame_count % CYCLE as u64) as usize;
+        self.frame_count += 1;
+
+        let word = frame_word(timestamp_usecs, phase);
+        let word = (word << 8) | crc8(word) as u64;
+
+        let luma = LumaPlane::new(&**frame)?.scaled(config.scale);
+        let data = &mut frame.planes_data_mut();
+        grid.stamp(
+            &luma,
+            data,
+            (0..FRAME_BITS).rev().map(|pos| (word >> pos) & 1 == 1),
+            config.fill.levels(),
+        );
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "temporal"
+    }
+
+    fn description(&self) -> &'static str {
+        "Temporal stamper spreading the high timestamp bits over 5 frames"
+    }
+}
+
+/// Largest advance of the low bits between two decoded frames, a
+/// quarter of their range or ~4.2 s. Larger jumps, forwards or
+/// backwards, are discontinuities of the stream rather than time
+/// passing.
+const MAX_STEP: u64 = 1 << (LOW_BITS - 2);
+
+/// Consecutive unreadable frames after which the reader starts over,
+/// the low bits may have wrapped unseen meanwhile
+const MAX_FAILED_READS: u32 = 2 * CYCLE as u32;
+
+/// Temporal reader
+///
+/// Yields nothing until a full cycle of chunks has been seen. The high
+/// bits are carried over when the low bits wrap and refreshed from the
+/// chunks of the following cycle. The reader starts over after a jump
+/// of the low bits it cannot explain or too many unreadable frames.
+#[derive(Default)]
+pub struct TemporalReader {
+    chunks: [Option<u8>; CYCLE],
+    high: Option<u64>,
+    last_low: Option<u64>,
+    failed_reads: u32,
+}
+
+impl TemporalReader {
+    /// Payload of a frame without its CRC, `None` if it does not read
+    fn decode(
+        frame: &VideoFrameRef<&BufferRef>,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        let Some(grid) = code_grid(config.x, config.y, config.width, config.height) else {
+            return Ok(None);
+        };
+
+        let luma = LumaPlane::new(frame)?
+            .scaled(config.scale)
+            .oriented(config.orientation);
+        let data = &frame.planes_data();
+        let Some(samples) = grid.sample(&luma, data, FRAME_BITS) else {
+            return Ok(None);
+        };
+
+        let levels = config.fill.levels();
+        let bits = samples.iter().fold(0u64, |bits, &mean| {
+            (bits << 1) | levels.bit(mean as u32) as u64
+        });
+        let word = bits >> 8;
+        let phase = (word >> CHUNK_BITS) & ((1 << PHASE_BITS) - 1);
+        Ok((crc8(word) == bits as u8 && phase < CYCLE as u64).then_some(word))
+    }
+
+    /// Forget the chunks and high bits collected so far
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Account for the low bits of a new frame
+    fn advance(&mut self, low: u64) {
+        let Some(last) = self.last_low else {
+            return;
+        };
+
+        if low >= last {
+            if low - last > MAX_STEP {
+                self.reset();
+            }
+        } else if low + (1 << LOW_BITS) - last <= MAX_STEP {
+            // Wrap from the top of the range: the chunks collected so
+            // far belong to the previous high value
+            self.chunks = [None; CYCLE];
+            self.high = self.high.map(|high| high + 1);
+        } else {
+            self.reset();
+        }
+    }
+
+    /// Account for a frame that did not read
+    fn miss(&mut self) {
+        self.failed_reads += 1;
+        if self.failed_reads >= MAX_FAILED_READS {
+            self.reset();
+        }
+    }
+
+    /// Account for the payload of a frame, returns its timestamp once
+    /// the high bits are known
+    fn accept(&mut self, word: u64) -> Option<u64> {
+        self.failed_reads = 0;
+
+        let chunk = (word & 0xFF) as u8;
+        let phase = ((word >> CHUNK_BITS) & ((1 << PHASE_BITS) - 1)) as usize;
+        let low = word >> (CHUNK_BITS + PHASE_BITS);
+
+        self.advance(low);
+        self.last_low = Some(low);
+
+        self.chunks[phase] = Some(chunk);
+        if self.chunks.iter().all(Option::is_some) {
+            let high = self.chunks.iter().rev().fold(0u64, |high, chunk| {
+                (high << CHUNK_BITS) | chunk.unwrap() as u64
+            });
+            self.high = Some(high);
+        }
+
+        self.high.map(|high| (high << LOW_BITS) | low)
+    }
+}
+
+impl TimestampReader for TemporalReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        Ok(match Self::decode(frame, config)? {
+            Some(word) => self.accept(word),
+            None => {
+                self.miss();
+                None
+            }
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "temporal"
+    }
+
+    fn description(&self) -> &'static str {
+        "Temporal reader reassembling the high timestamp bits over 5 frames"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame interval at 30 fps in microseconds
+    const FRAME: u64 = 33_333;
+
+    /// Feed `count` frames from `start` on, returns what was read
+    fn feed(reader: &mut TemporalReader, start: u64, count: usize) -> Vec<Option<u64>> {
+        (0..count)
+            .map(|index| {
+                let timestamp = start + index as u64 * FRAME;
+                reader.accept(frame_word(timestamp, index % CYCLE))
+            })
+            .collect()
+    }
+
+    fn expected(start: u64, count: usize) -> Vec<Option<u64>> {
+        (0..count)
+            .map(|index| Some(start + index as u64 * FRAME))
+            .collect()
+    }
+
+    #[test]
+    fn follows_a_wrap_of_the_low_bits() {
+        let mut reader = TemporalReader::default();
+        // Wraps after the 8th frame
+        let start = (0x1234 << LOW_BITS) + (1 << LOW_BITS) - 8 * FRAME + 1;
+        let read = feed(&mut reader, start, 15);
+
+        assert_eq!(read[..CYCLE - 1], [None; CYCLE - 1]);
+        assert_eq!(read[CYCLE - 1..8], expected(start, 8)[CYCLE - 1..]);
+        // The carried high bits cover the frames until the next cycle
+        assert_eq!(read[8..], expected(start, 15)[8..]);
+    }
+
+    #[test]
+    fn starts_over_after_a_backward_jump() {
+        let mut reader = TemporalReader::default();
+        let start = (0x1234 << LOW_BITS) + (1 << (LOW_BITS - 1));
+        feed(&mut reader, start, 2 * CYCLE);
+
+        // Back by a second in the middle of the range, not a wrap
+        let restart = start - 1_000_000;
+        let read = feed(&mut reader, restart, 2 * CYCLE);
+        assert_eq!(read[..CYCLE - 1], [None; CYCLE - 1]);
+        assert_eq!(read[CYCLE - 1..], expected(restart, 2 * CYCLE)[CYCLE - 1..]);
+    }
+
+    #[test]
+    fn starts_over_after_a_forward_jump() {
+        let mut reader = TemporalReader::default();
+        let start = 0x1234 << LOW_BITS;
+        feed(&mut reader, start, 2 * CYCLE);
+
+        // Ahead by more than a whole range of the low bits
+        let jump = start + (3 << LOW_BITS) / 2;
+        let read = feed(&mut reader, jump, 2 * CYCLE);
+        assert_eq!(read[..CYCLE - 1], [None; CYCLE - 1]);
+        assert_eq!(read[CYCLE - 1..], expected(jump, 2 * CYCLE)[CYCLE - 1..]);
+    }
+
+    #[test]
+    fn starts_over_after_unreadable_frames() {
+        let mut reader = TemporalReader::default();
+        let start = 0x1234 << LOW_BITS;
+        feed(&mut reader, start, 2 * CYCLE);
+
+        for _ in 0..MAX_FAILED_READS - 1 {
+            reader.miss();
+        }
+        assert_eq!(
+            feed(&mut reader, start + 20 * FRAME, 1),
+            [Some(start + 20 * FRAME)]
+        );
+
+        for _ in 0..MAX_FAILED_READS {
+            reader.miss();
+        }
+        assert_eq!(feed(&mut reader, start + 40 * FRAME, 1), [None]);
+    }
+}
@@ -0,0 +1,227 @@
+// Frames and channel impairments shared by the codec tests
+
+use super::traits::{ReaderConfig, StamperConfig, TimestampReader, TimestampStamper};
+use gst::{Buffer, SystemClock};
+use gst_video::{prelude::*, VideoFormat, VideoFrameRef, VideoInfo};
+
+/// Frame of the given format with every byte set to `value`, mid gray
+/// for 128
+pub fn frame(format: VideoFormat, width: u32, height: u32, value: u8) -> (VideoInfo, Buffer) {
+    gst::init().unwrap();
+    let info = VideoInfo::builder(format, width, height).build().unwrap();
+    let mut buffer = Buffer::with_size(info.size()).unwrap();
+    buffer
+        .get_mut()
+        .unwrap()
+        .map_writable()
+        .unwrap()
+        .fill(value);
+    (info, buffer)
+}
+
+/// Stamp a frame with the current system time, returns the stamped value
+pub fn stamp(
+    stamper: &mut dyn TimestampStamper,
+    (info, buffer): &mut (VideoInfo, Buffer),
+    config: &StamperConfig,
+) -> u64 {
+    let mut frame =
+        VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), info).unwrap();
+    stamper
+        .stamp(&mut frame, &SystemClock::obtain(), config)
+        .unwrap()
+}
+
+/// Read a frame, `None` if nothing decoded
+pub fn read(
+    reader: &mut dyn TimestampReader,
+    (info, buffer): &(VideoInfo, Buffer),
+    config: &ReaderConfig,
+) -> Option<u64> {
+    let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).unwrap();
+    reader.read(&frame, &SystemClock::obtain(), config).unwrap()
+}
+
+/// Luma plane of a gray or I420 frame as rows of `width` samples
+pub fn luma((info, buffer): &mut (VideoInfo, Buffer)) -> Luma {
+    assert!(matches!(
+        info.format(),
+        VideoFormat::Gray8 | VideoFormat::I420
+    ));
+    let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).unwrap();
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0).unwrap();
+    let (width, height) = (info.width() as usize, info.height() as usize);
+
+    Luma {
+        width,
+        height,
+        samples: (0..height)
+            .flat_map(|y| data[y * stride..y * stride + width].iter())
+            .map(|&value| value as f64)
+            .collect(),
+    }
+}
+
+/// Write samples back into the luma plane of a gray or I420 frame, rounded and
+/// clamped like an 8-bit decoder output
+pub fn set_luma((info, buffer): &mut (VideoInfo, Buffer), luma: &Luma) {
+    let mut frame =
+        VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), info).unwrap();
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data_mut(0).unwrap();
+
+    for y in 0..luma.height {
+        for x in 0..luma.width {
+            data[y * stride + x] = luma.get(x, y).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Luma samples in floating point, so impairments can be chained
+/// without intermediate rounding
+#[derive(Debug, Clone)]
+pub struct Luma {
+    pub width: usize,
+    pub height: usize,
+    samples: Vec<f64>,
+}
+
+impl Luma {
+    pub fn get(&self, x: usize, y: usize) -> f64 {
+        self.samples[y * self.width + x]
+    }
+
+    fn set(&mut self, x: usize, y: usize, value: f64) {
+        self.samples[y * self.width + x] = value;
+    }
+
+    /// Sample at a sub-pixel position, bilinear, edge pixels repeated
+    pub fn bilinear(&self, x: f64, y: f64) -> f64 {
+        let x = x.clamp(0.0, (self.width - 1) as f64);
+        let y = y.clamp(0.0, (self.height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x1, y0) * fx;
+        let bottom = self.get(x0, y1) * (1.0 - fx) + self.get(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// Replace every `block`x`block` block by its mean, what an encoder
+    /// keeps of a block when it quantises away all AC coefficients
+    pub fn average_blocks(&mut self, block: usize) {
+        for by in (0..self.height).step_by(block) {
+            for bx in (0..self.width).step_by(block) {
+                let (bw, bh) = (block.min(self.width - bx), block.min(self.height - by));
+                let pixels = || (by..by + bh).flat_map(move |y| (bx..bx + bw).map(move |x| (x, y)));
+                let mean = pixels().map(|(x, y)| self.get(x, y)).sum::<f64>() / (bw * bh) as f64;
+                for (x, y) in pixels() {
+                    self.set(x, y, mean);
+                }
+            }
+        }
+    }
+
+    /// Map every sample through `gain * value + offset`, e.g. a range
+    /// conversion or an encoder contrast change
+    pub fn transfer(&mut self, gain: f64, offset: f64) {
+        for value in &mut self.samples {
+            *value = gain * *value + offset;
+        }
+    }
+
+    /// Map every sample through a power law over the 8-bit range, e.g.
+    /// a display or camera gamma applied twice
+    pub fn gamma(&mut self, gamma: f64) {
+        for value in &mut self.samples {
+            *value = 255.0 * (*value / 255.0).clamp(0.0, 1.0).powf(gamma);
+        }
+    }
+
+    /// Add uniform noise of at most `amplitude` levels, reproducible
+    /// from `seed`
+    pub fn add_noise(&mut self, amplitude: f64, seed: u64) {
+        let mut noise = Noise(seed | 1);
+        for value in &mut self.samples {
+            *value += amplitude * noise.next();
+        }
+    }
+
+    /// Shift every `block`x`block` block by its own uniform noise of at
+    /// most `amplitude` levels, reproducible from `seed`: the error
+    /// quantisation leaves on the mean of a block, bounded
+    pub fn add_block_noise(&mut self, amplitude: f64, block: usize, seed: u64) {
+        let mut noise = Noise(seed | 1);
+        for by in (0..self.height).step_by(block) {
+            for bx in (0..self.width).step_by(block) {
+                let offset = amplitude * noise.next();
+                for y in by..(by + block).min(self.height) {
+                    for x in bx..(bx + block).min(self.width) {
+                        self.set(x, y, self.get(x, y) + offset);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Box blur over `(2 * radius + 1)` pixels in each direction
+    pub fn blur(&mut self, radius: usize) {
+        let source = self.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (left, right) = (x.saturating_sub(radius), (x + radius).min(self.width - 1));
+                let (top, bottom) = (y.saturating_sub(radius), (y + radius).min(self.height - 1));
+                let mut sum = 0.0;
+                for sy in top..=bottom {
+                    for sx in left..=right {
+                        sum += source.get(sx, sy);
+                    }
+                }
+                let count = ((right - left + 1) * (bottom - top + 1)) as f64;
+                self.set(x, y, sum / count);
+            }
+        }
+    }
+
+    /// Resample through `map`, which gives the source position of each
+    /// destination pixel; positions outside the source take `background`
+    pub fn warp(&mut self, map: impl Fn(f64, f64) -> (f64, f64), background: f64) {
+        let source = self.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (sx, sy) = map(x as f64, y as f64);
+                let inside = (0.0..=(self.width - 1) as f64).contains(&sx)
+                    && (0.0..=(self.height - 1) as f64).contains(&sy);
+                let value = if inside {
+                    source.bilinear(sx, sy)
+                } else {
+                    background
+                };
+                self.set(x, y, value);
+            }
+        }
+    }
+
+    /// Set a rectangle to `value`, e.g. a macroblock lost to a burst
+    pub fn fill(&mut self, (x, y, width, height): (usize, usize, usize, usize), value: f64) {
+        for py in y..(y + height).min(self.height) {
+            for px in x..(x + width).min(self.width) {
+                self.set(px, py, value);
+            }
+        }
+    }
+}
+
+/// Xorshift generator for reproducible noise in `[-1, 1]`
+struct Noise(u64);
+
+impl Noise {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
@@ -1,10 +1,16 @@
 // Common traits and types for timestamp stampers
 
-use gst_video::VideoFrameRef;
+use gst_video::{prelude::*, VideoFrameRef};
 use gst::{BufferRef, FlowError, Clock};
 use glib::prelude::*;
 
+use super::interleave::Interleave;
+use super::{orient::Orientation, palette::Fill, scale::Scale, tuning::Tuning};
+
 /// Stamper type selection
+///
+/// The numeric values are set in saved pipelines and `gst-launch`
+/// lines, new codecs are appended at the end.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum)]
 #[enum_type(name = "GstTsLatencyStamperType")]
 pub enum StamperType {
@@ -17,6 +23,36 @@ pub enum StamperType {
     /// Fast robust implementation - BCH error correction
     #[enum_value(name = "Fast-Robust: BCH error correction", nick = "fast-robust")]
     FastRobust,
+    /// Detect the codec from the incoming frames (readers only)
+    #[enum_value(name = "Auto: Detect codec from incoming frames", nick = "auto")]
+    Auto,
+    /// Reed-Solomon implementation - configurable parity symbols
+    #[enum_value(name = "Reed-Solomon: GF(2^8) with configurable parity", nick = "reed-solomon")]
+    ReedSolomon,
+    /// Differential implementation - cell pairs compared with each other
+    #[enum_value(name = "Differential: Manchester cell pairs", nick = "differential")]
+    Differential,
+    /// PAM-4 implementation - four luma levels per cell
+    #[enum_value(name = "PAM-4: Four levels with calibration cells", nick = "pam4")]
+    Pam4,
+    /// Multichannel implementation - one bit per colour component
+    #[enum_value(name = "Multichannel: One bit per colour component", nick = "multichannel")]
+    Multichannel,
+    /// Hierarchical implementation - coarse and fine layers
+    #[enum_value(name = "Hierarchical: Coarse milliseconds plus fine layer", nick = "hierarchical")]
+    Hierarchical,
+    /// Temporal implementation - high bits spread over several frames
+    #[enum_value(name = "Temporal: High bits spread over a cycle of frames", nick = "temporal")]
+    Temporal,
+    /// Optical implementation - QR-style code for camera capture
+    #[enum_value(name = "Optical: Finder patterns for camera capture", nick = "optical")]
+    Optical,
+    /// Watermark implementation - low-amplitude spread-spectrum pattern
+    #[enum_value(name = "Watermark: Low-visibility spread-spectrum pattern", nick = "watermark")]
+    Watermark,
+    /// Adaptive implementation - self-described layout following receiver feedback
+    #[enum_value(name = "Adaptive: Self-described layout sized by receiver feedback", nick = "adaptive")]
+    Adaptive,
 }
 
 impl Default for StamperType {
@@ -31,6 +67,16 @@ impl From<i32> for StamperType {
             0 => StamperType::Original,
             1 => StamperType::Optimized,
             2 => StamperType::FastRobust,
+            3 => StamperType::Auto,
+            4 => StamperType::ReedSolomon,
+            5 => StamperType::Differential,
+            6 => StamperType::Pam4,
+            7 => StamperType::Multichannel,
+            8 => StamperType::Hierarchical,
+            9 => StamperType::Temporal,
+            10 => StamperType::Optical,
+            11 => StamperType::Watermark,
+            12 => StamperType::Adaptive,
             _ => StamperType::Optimized,
         }
     }
@@ -42,14 +88,57 @@ impl StamperType {
             StamperType::Original => "original",
             StamperType::Optimized => "optimized",
             StamperType::FastRobust => "fast-robust",
+            StamperType::ReedSolomon => "reed-solomon",
+            StamperType::Differential => "differential",
+            StamperType::Pam4 => "pam4",
+            StamperType::Multichannel => "multichannel",
+            StamperType::Hierarchical => "hierarchical",
+            StamperType::Temporal => "temporal",
+            StamperType::Optical => "optical",
+            StamperType::Watermark => "watermark",
+            StamperType::Adaptive => "adaptive",
+            StamperType::Auto => "auto",
         }
     }
     
+    /// Whether the codec writes its cells as luma levels, which the
+    /// palette colours afterwards. `original` writes the palette colours
+    /// itself, `multichannel` carries bits in the colour components and
+    /// `watermark` only shifts the picture.
+    pub fn writes_luma_levels(&self) -> bool {
+        !matches!(
+            self,
+            StamperType::Original | StamperType::Multichannel | StamperType::Watermark
+        )
+    }
+    
+    /// Whether the code size depends on `parity_symbols`
+    pub fn uses_parity_symbols(&self) -> bool {
+        matches!(
+            self,
+            StamperType::ReedSolomon
+                | StamperType::Differential
+                | StamperType::Pam4
+                | StamperType::Multichannel
+                | StamperType::Watermark
+        )
+    }
+    
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
             "original" => Some(StamperType::Original),
             "optimized" => Some(StamperType::Optimized),
             "fast-robust" | "fastrobust" => Some(StamperType::FastRobust),
+            "reed-solomon" | "reedsolomon" => Some(StamperType::ReedSolomon),
+            "differential" | "manchester" => Some(StamperType::Differential),
+            "pam4" | "pam-4" => Some(StamperType::Pam4),
+            "multichannel" => Some(StamperType::Multichannel),
+            "hierarchical" => Some(StamperType::Hierarchical),
+            "temporal" => Some(StamperType::Temporal),
+            "optical" => Some(StamperType::Optical),
+            "watermark" => Some(StamperType::Watermark),
+            "adaptive" => Some(StamperType::Adaptive),
+            "auto" => Some(StamperType::Auto),
             _ => None,
         }
     }
@@ -62,6 +151,18 @@ pub struct StamperConfig {
     pub y: u32,
     pub width: u32,
     pub height: u32,
+    /// Number of parity symbols for Reed-Solomon codes
+    pub parity_symbols: u32,
+    /// Placement of code bits on the grid for error correcting codecs
+    pub interleave: Interleave,
+    /// Amplitude of the watermark codec in luma levels
+    pub strength: u32,
+    /// Frame pixels per code pixel, all geometry above is in code pixels
+    pub scale: Scale,
+    /// Cell colours of the palette in the frame's format
+    pub fill: Fill,
+    /// Layout and decision parameters of the tunable codecs
+    pub tuning: Tuning,
 }
 
 impl Default for StamperConfig {
@@ -71,6 +172,12 @@ impl Default for StamperConfig {
             y: 0,
             width: 64,
             height: 64,
+            parity_symbols: 6,
+            interleave: Interleave::None,
+            strength: 4,
+            scale: Scale::IDENTITY,
+            fill: Fill::NOMINAL,
+            tuning: Tuning::default(),
         }
     }
 }
@@ -83,6 +190,19 @@ pub struct ReaderConfig {
     pub width: u32,
     pub height: u32,
     pub tolerance: u32,
+    /// Number of parity symbols for Reed-Solomon codes
+    pub parity_symbols: u32,
+    /// Placement of code bits on the grid for error correcting codecs
+    pub interleave: Interleave,
+    /// Frame pixels per code pixel, all geometry above is in code pixels
+    pub scale: Scale,
+    /// Transformation the frame went through since it was stamped, all
+    /// geometry above is in the stamped frame
+    pub orientation: Orientation,
+    /// Cell colours of the palette in the frame's format
+    pub fill: Fill,
+    /// Layout and decision parameters of the tunable codecs
+    pub tuning: Tuning,
 }
 
 impl Default for ReaderConfig {
@@ -93,19 +213,60 @@ impl Default for ReaderConfig {
             width: 64,
             height: 64,
             tolerance: 5,
+            parity_symbols: 6,
+            interleave: Interleave::None,
+            scale: Scale::IDENTITY,
+            orientation: Orientation::Identity,
+            fill: Fill::NOMINAL,
+            tuning: Tuning::default(),
         }
     }
 }
 
+impl ReaderConfig {
+    /// Rectangle of the frame read covering a code rectangle
+    pub fn frame_rect(
+        &self,
+        frame: &impl VideoFrameExt,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> (usize, usize, usize, usize) {
+        let (frame_width, frame_height) = self
+            .orientation
+            .source_size(frame.width() as usize, frame.height() as usize);
+        self.orientation
+            .rect(self.scale.rect(x, y, width, height), frame_width, frame_height)
+    }
+}
+
+/// Timestamp decoded from a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub timestamp: u64,
+    /// Only the coarse layer decoded, the timestamp is rounded down to
+    /// the coarse resolution
+    pub reduced_precision: bool,
+    /// How clearly the cells were told apart, from 0 to 1, for codecs
+    /// that measure it
+    pub confidence: Option<f64>,
+}
+
 /// Trait for timestamp stamper implementations
+///
+/// Stampers see every frame of a stream in order and may keep state
+/// across frames, e.g. to spread a payload over several frames.
 pub trait TimestampStamper: Send + Sync {
     /// Stamp a timestamp onto a video frame
+    ///
+    /// Returns the stamped value as a matching reader decodes it.
     fn stamp(
-        &self,
+        &mut self,
         frame: &mut VideoFrameRef<&mut BufferRef>,
         clock: &Clock,
         config: &StamperConfig,
-    ) -> Result<(), FlowError>;
+    ) -> Result<u64, FlowError>;
     
     /// Get the name of this stamper implementation
     fn name(&self) -> &'static str;
@@ -115,18 +276,67 @@ pub trait TimestampStamper: Send + Sync {
 }
 
 /// Trait for timestamp reader implementations
+///
+/// Readers see every frame of a stream in order and may keep state
+/// across frames, e.g. to reassemble a payload spread over several
+/// frames.
 pub trait TimestampReader: Send + Sync {
     /// Read a timestamp from a video frame
     fn read(
-        &self,
+        &mut self,
         frame: &VideoFrameRef<&BufferRef>,
         clock: &Clock,
         config: &ReaderConfig,
     ) -> Result<Option<u64>, FlowError>;
     
+    /// Read a timestamp and whether it was decoded at full precision
+    ///
+    /// Layered codecs may fall back to a coarser timestamp when their
+    /// fine layer fails. The default reports every timestamp as exact.
+    fn read_with_precision(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<Reading>, FlowError> {
+        Ok(self.read(frame, clock, config)?.map(|timestamp| Reading {
+            timestamp,
+            reduced_precision: false,
+            confidence: None,
+        }))
+    }
+    
     /// Get the name of this reader implementation
     fn name(&self) -> &'static str;
     
     /// Get a description of this reader
     fn description(&self) -> &'static str;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glib::translate::IntoGlib;
+
+    #[test]
+    fn stamper_type_values_are_stable() {
+        for (value, stamper_type) in [
+            (0, StamperType::Original),
+            (1, StamperType::Optimized),
+            (2, StamperType::FastRobust),
+            (3, StamperType::Auto),
+            (4, StamperType::ReedSolomon),
+            (5, StamperType::Differential),
+            (6, StamperType::Pam4),
+            (7, StamperType::Multichannel),
+            (8, StamperType::Hierarchical),
+            (9, StamperType::Temporal),
+            (10, StamperType::Optical),
+            (11, StamperType::Watermark),
+            (12, StamperType::Adaptive),
+        ] {
+            assert_eq!(stamper_type.into_glib(), value);
+            assert_eq!(StamperType::from(value), stamper_type);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+// Layout and decision parameters of the `optimized` and `fast-robust`
+// codecs
+//
+// Both codecs used to hard-code their cell sizes, grids, markers and
+// confidence limits. They now take them from the configuration, so a
+// code can be tuned per encoder profile: larger cells for heavily
+// quantised streams, smaller ones where the corner is small. Stamper
+// and reader must agree on the layout, the decision parameters only
+// concern the reader. The fit check covers every codec, so an element
+// warns when its code cannot be stamped instead of failing on the
+// first frame.
+
+use super::{
+    adaptive, differential, fast_robust, hierarchical, levels::Levels, locate::MarkerLayout,
+    multichannel, optical, optimized, pam4, placement::BlockAlign, reed_solomon, temporal,
+    traits::StamperType, watermark,
+};
+
+/// Codec tuning shared by stamper and reader
+#[derive(Debug, Clone, Copy)]
+pub struct Tuning {
+    /// Cell grid and markers of the `optimized` code
+    pub layout: MarkerLayout,
+    /// Side of a `fast-robust` cell in pixels
+    pub block_size: usize,
+    /// Pixels left unstamped between `fast-robust` cells
+    pub guard_pixels: usize,
+    /// Fixed decision level of the readers, `None` to take it from the
+    /// cells of known value
+    pub threshold: Option<u8>,
+    /// Average confidence below which a reader rejects a code, `None`
+    /// for the reader's own default
+    pub min_confidence: Option<f32>,
+    /// Index of the `adaptive` code's layout, set from receiver
+    /// feedback; readers take it from the code
+    pub adaptive_layout: usize,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            layout: MarkerLayout::default(),
+            block_size: 4,
+            guard_pixels: 0,
+            threshold: None,
+            min_confidence: None,
+            adaptive_layout: adaptive::DEFAULT_LAYOUT,
+        }
+    }
+}
+
+impl Tuning {
+    /// Round the cell pitches up so cells tile the `alignment` grid,
+    /// `fast-robust` keeps its block size and widens the guard band
+    pub fn aligned(mut self, alignment: BlockAlign) -> Self {
+        self.layout.cell_size = alignment.pitch(self.layout.cell_size);
+        let pitch = alignment.pitch(self.block_size + self.guard_pixels);
+        self.guard_pixels = pitch - self.block_size;
+        self
+    }
+
+    /// Check the parameters are consistent on their own
+    pub fn validate(&self) -> Result<(), String> {
+        let layout = &self.layout;
+        if layout.grid_width * layout.grid_height < optimized::CODE_BITS {
+            return Err(format!(
+                "a {}x{} grid holds fewer than the {} cells of the optimized code",
+                layout.grid_width,
+                layout.grid_height,
+                optimized::CODE_BITS
+            ));
+        }
+        if layout.start_marker == layout.end_marker {
+            return Err("start and end markers must differ".into());
+        }
+        // Marker cells give the readers their black and white levels
+        for marker in [layout.start_marker, layout.end_marker] {
+            if marker == 0 || marker == u16::MAX {
+                return Err(format!(
+                    "marker {:#06x} needs both zero and one bits",
+                    marker
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the code of `stamper_type` with `parity_symbols` parity
+    /// symbols fits a region of the given size
+    pub fn check_fit(
+        &self,
+        stamper_type: StamperType,
+        parity_symbols: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), String> {
+        let parity = parity_symbols as usize;
+        let fits = match stamper_type {
+            StamperType::Optimized => {
+                let code_width = self.layout.grid_width * self.layout.cell_size;
+                let code_height = self.layout.grid_height * self.layout.cell_size;
+                if code_width > width as usize || code_height > height as usize {
+                    return Err(format!(
+                        "{}x{} pixel optimized code does not fit the {}x{} region",
+                        code_width, code_height, width, height
+                    ));
+                }
+                return Ok(());
+            }
+            StamperType::FastRobust => {
+                let pitch = self.block_size + self.guard_pixels;
+                let cells = (width as usize / pitch) * (height as usize / pitch);
+                if cells < fast_robust::CODE_CELLS {
+                    return Err(format!(
+                        "{}x{} region holds {} of the {} fast-robust cells of {} pixels",
+                        width,
+                        height,
+                        cells,
+                        fast_robust::CODE_CELLS,
+                        pitch
+                    ));
+                }
+                return Ok(());
+            }
+            StamperType::Adaptive => {
+                if adaptive::largest_fitting(width, height).is_none() {
+                    return Err(format!(
+                        "{}x{} region does not fit the smallest adaptive code",
+                        width, height
+                    ));
+                }
+                return Ok(());
+            }
+            StamperType::ReedSolomon => reed_solomon::fits(width, height, parity),
+            StamperType::Differential => differential::fits(width, height, parity),
+            StamperType::Pam4 => pam4::fits(width, height, parity),
+            // Best case of three channels, formats with chroma
+            // subsampling need three times the cells
+            StamperType::Multichannel => multichannel::fits(width, height, parity, 3),
+            StamperType::Watermark => watermark::fits(width, height, parity),
+            StamperType::Hierarchical => hierarchical::fits(width, height),
+            StamperType::Temporal => temporal::fits(width, height),
+            StamperType::Optical => optical::fits(width, height),
+            StamperType::Original | StamperType::Auto => true,
+        };
+
+        if !fits {
+            let parity = if stamper_type.uses_parity_symbols() {
+                format!(" with {} parity symbols", parity_symbols)
+            } else {
+                String::new()
+            };
+            return Err(format!(
+                "{}x{} region is too small for the {} code{}",
+                width,
+                height,
+                stamper_type.as_str(),
+                parity
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Levels to decide bits with: the fixed threshold if set, else the
+    /// levels `estimated` from cells of known value, else the
+    /// `palette` levels
+    pub fn levels(&self, estimated: Option<Levels>, palette: Levels) -> Levels {
+        let Some(threshold) = self.threshold else {
+            return estimated.unwrap_or(palette);
+        };
+
+        // Keep the palette swing so soft values stay comparable
+        let half_swing = (palette.white - palette.black) / 2;
+        Levels {
+            black: threshold as i32 - half_swing,
+            white: threshold as i32 + half_swing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_code_fits_a_large_region() {
+        let tuning = Tuning::default();
+        for stamper_type in [
+            StamperType::Optimized,
+            StamperType::FastRobust,
+            StamperType::ReedSolomon,
+            StamperType::Differential,
+            StamperType::Pam4,
+            StamperType::Multichannel,
+            StamperType::Hierarchical,
+            StamperType::Temporal,
+            StamperType::Optical,
+            StamperType::Adaptive,
+        ] {
+            assert_eq!(
+                tuning.check_fit(stamper_type, 6, 128, 128),
+                Ok(()),
+                "{:?}",
+                stamper_type
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_parity_beyond_the_region() {
+        let tuning = Tuning::default();
+        // 8 data and 24 parity symbols fill the 256 cells of a 64x64 region
+        assert_eq!(
+            tuning.check_fit(StamperType::ReedSolomon, 24, 64, 64),
+            Ok(())
+        );
+        assert_eq!(
+            tuning.check_fit(StamperType::ReedSolomon, 64, 64, 64),
+            Err(
+                "64x64 region is too small for the reed-solomon code with 64 parity symbols".into()
+            )
+        );
+        assert!(tuning
+            .check_fit(StamperType::ReedSolomon, 64, 128, 128)
+            .is_ok());
+        assert!(tuning
+            .check_fit(StamperType::Differential, 12, 64, 64)
+            .is_err());
+        assert!(tuning.check_fit(StamperType::Watermark, 6, 64, 64).is_err());
+        assert!(tuning
+            .check_fit(StamperType::Watermark, 6, 172, 172)
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_regions_too_small_for_fixed_codes() {
+        let tuning = Tuning::default();
+        assert!(tuning
+            .check_fit(StamperType::Hierarchical, 6, 32, 64)
+            .is_err());
+        assert!(tuning.check_fit(StamperType::Temporal, 6, 64, 8).is_err());
+        assert!(tuning.check_fit(StamperType::Optical, 6, 40, 40).is_err());
+        assert!(tuning.check_fit(StamperType::Adaptive, 6, 32, 32).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+// Low-visibility spread-spectrum watermark
+//
+// Instead of black and white cells, every bit of the Reed-Solomon
+// codeword nudges the luma of many small chips scattered over the
+// region up or down by a few levels, following a fixed pseudo-random
+// sign per chip. The reader removes the picture's own structure with a
+// local high-pass and correlates the residue with the signs of each
+// bit's chips: the picture averages out, the watermark adds up.
+//
+// The pattern is near-invisible at the default strength of 4 levels,
+// but needs a large region (hundreds of chips per bit) and costs more
+// CPU to read than the cell codecs. Busy content and heavy compression
+// call for a larger region or a higher `strength`.
+
+use super::{
+    interleave::Interleave,
+    plane::LumaPlane,
+    reed_solomon::{codeword_len, decode_symbols, encode_symbols, symbol_bits},
+    traits::{ReaderConfig, StamperConfig, TimestampReader, TimestampStamper},
+};
+use gst::{prelude::*, BufferRef, Clock, FlowError};
+use gst_video::VideoFrameRef;
+
+/// Pixels per chip in each direction, large enough to survive chroma
+/// subsampling and mild blur
+const CHIP_SIZE: usize = 4;
+
+/// Fewest chips per bit for the correlation to rise above the picture
+const MIN_CHIPS_PER_BIT: usize = 16;
+
+/// Margin of a bit correlating as well as the median bit, for erasure
+/// marking against the binary cell scale
+const MEDIAN_MARGIN: f64 = 107.0;
+
+/// Chip grid of the region
+struct Chips {
+    cols: usize,
+    rows: usize,
+    /// Bit index and sign of each chip in row-major order
+    chips: Vec<(usize, bool)>,
+}
+
+impl Chips {
+    /// `None` if the region holds too few chips per bit
+    fn new(width: u32, height: u32, bits: usize) -> Option<Self> {
+        let cols = width as usize / CHIP_SIZE;
+        let rows = height as usize / CHIP_SIZE;
+        if cols * rows < bits * MIN_CHIPS_PER_BIT {
+            return None;
+        }
+
+        // Chips go to the bits round-robin in a fixed pseudo-random
+        // order with alternating signs, so every bit gets as many
+        // positive as negative chips spread over the whole region
+        let chips = Interleave::Random
+            .permutation(cols * rows)
+            .into_iter()
+            .map(|slot| (slot % bits, (slot / bits) & 1 == 0))
+            .collect();
+
+        Some(Self { cols, rows, chips })
+    }
+
+    fn origin(&self, index: usize, x: u32, y: u32) -> (usize, usize) {
+        (
+            x as usize + (index % self.cols) * CHIP_SIZE,
+            y as usize + (index / self.cols) * CHIP_SIZE,
+        )
+    }
+}
+
+/// Whether the chips of the code with `parity` parity symbols fit a
+/// region of the given size
+pub fn fits(width: u32, height: u32, parity: usize) -> bool {
+    Chips::new(width, height, codeword_len(parity) * 8).is_some()
+}
+
+/// Watermark stamper
+///
+/// - Sends the Reed-Solomon codeword of the 64-bit timestamp
+/// - Each bit adds `strength` luma levels, positive or negative, to
+///   chips of 4x4 pixels spread over the whole region
+/// - Needs 16 chips per bit, 6 parity symbols take at least 1792 chips
+///   (e.g. 172x172 pixels); hundreds of chips per bit, a region of a
+///   quarter of the frame or more, are recommended for natural content
+pub struct WatermarkStamper;
+
+impl Default for WatermarkStamper {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampStamper for WatermarkStamper {
+    fn stamp(
+        &mut self,
+        frame: &mut VideoFrameRef<&mut BufferRef>,
+        clock: &Clock,
+        config: &StamperConfig,
+    ) -> Result<u64, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let bit_count = codeword_len(parity) * 8;
+        let chips =
+            Chips::new(config.width, config.height, bit_count).ok_or(FlowError::NotSupported)?;
+
+        let timestamp_usecs = clock.time().unwrap().useconds();
+        let symbols = encode_symbols(timestamp_usecs, parity, config.interleave);
+        let bits: Vec<bool> = symbol_bits(&symbols).collect();
+
+        let luma = LumaPlane::new(&**frame)?.scaled(config.scale);
+        let data = &mut frame.planes_data_mut();
+        let strength = config.strength as i32;
+
+        for (index, &(bit, positive)) in chips.chips.iter().enumerate() {
+            let delta = if bits[bit] == positive {
+                strength
+            } else {
+                -strength
+            };
+
+            let (x, y) = chips.origin(index, config.x, config.y);
+            luma.add_rect(data, x, y, CHIP_SIZE, CHIP_SIZE, delta);
+        }
+
+        Ok(timestamp_usecs)
+    }
+
+    fn name(&self) -> &'static str {
+        "watermark"
+    }
+
+    fn description(&self) -> &'static str {
+        "Low-visibility spread-spectrum watermark stamper"
+    }
+}
+
+/// Watermark reader
+pub struct WatermarkReader;
+
+impl Default for WatermarkReader {
+    fn default() -> Self {
+        Self
+    }
+}
+
+impl TimestampReader for WatermarkReader {
+    fn read(
+        &mut self,
+        frame: &VideoFrameRef<&BufferRef>,
+        _clock: &Clock,
+        config: &ReaderConfig,
+    ) -> Result<Option<u64>, FlowError> {
+        let parity = config.parity_symbols as usize;
+        let bit_count = codeword_len(parity) * 8;
+        let Some(chips) = Chips::new(config.width, config.height, bit_count) else {
+            return Ok(None);
+        };
+
+        let luma = LumaPlane::new(frame)?
+            .scaled(config.scale)
+            .oriented(config.orientation);
+        let data = &frame.planes_data();
+
+        let mut means = Vec::with_capacity(chips.chips.len());
+        for index in 0..chips.chips.len() {
+            let (x, y) = chips.origin(index, config.x, config.y);
+            let Some(mean) = luma.mean_rect(data, x, y, CHIP_SIZE, CHIP_SIZE) else {
+                return Ok(None);
+            };
+            means.push(mean as f64);
+        }
+
+        // High-pass: each chip against the mean of its 3x3 neighbourhood
+        let mut correlation = vec![0f64; bit_count];
+        for row in 0..chips.rows {
+            for col in 0..chips.cols {
+                let (mut sum, mut count) = (0.0, 0.0);
+                for r in row.saturating_sub(1)..(row + 2).min(chips.rows) {
+                    for c in col.saturating_sub(1)..(col + 2).min(chips.cols) {
+                        sum += means[r * chips.cols + c];
+                        count += 1.0;
+                    }
+                }
+
+                let index = row * chips.cols + col;
+                let residue = means[index] - sum / count;
+                let (bit, positive) = chips.chips[index];
+                correlation[bit] += if positive { residue } else { -residue };
+            }
+        }
+
+        // Bits correlating much worse than the median are doubtful
+        let mut strengths: Vec<f64> = correlation.iter().map(|c| c.abs()).collect();
+        strengths.sort_by(f64::total_cmp);
+        let median = strengths[strengths.len() / 2];
+        if median <= 0.0 {
+            return Ok(None);
+        }
+
+        let mut symbols = vec![0u8; codeword_len(parity)];
+        let mut margins = vec![u8::MAX; codeword_len(parity)];
+        for (bit, &value) in correlation.iter().enumerate() {
+            symbols[bit / 8] = (symbols[bit / 8] << 1) | (value > 0.0) as u8;
+
+            let margin = (value.abs() / median * MEDIAN_MARGIN).min(255.0);
+            margins[bit / 8] = margins[bit / 8].min(margin as u8);
+        }
+
+        Ok(decode_symbols(
+            &symbols,
+            &margins,
+            parity,
+            config.interleave,
+        ))
+    }
+
+    fn name(&self) -> &'static str {
+        "watermark"
+    }
+
+    fn description(&self) -> &'static str {
+        "Spread-spectrum watermark reader correlating chips over the region"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stamper::testing;
+    use gst_video::VideoFormat;
+
+    /// Stamp a textured 256x256 frame with strength 4, returns whether
+    /// the value decoded and the largest luma change
+    fn stamp_texture(seed: u64) -> (bool, f64) {
+        let mut frame = testing::frame(VideoFormat::I420, 256, 256, 128);
+        let mut picture = testing::luma(&mut frame);
+        picture.add_noise(60.0, seed);
+        picture.blur(1);
+        testing::set_luma(&mut frame, &picture);
+        let picture = testing::luma(&mut frame);
+
+        let stamper_config = StamperConfig {
+            width: 256,
+            height: 256,
+            strength: 4,
+            ..Default::default()
+        };
+        let reader_config = ReaderConfig {
+            width: 256,
+            height: 256,
+            ..Default::default()
+        };
+        let stamped = testing::stamp(&mut WatermarkStamper, &mut frame, &stamper_config);
+        let decoded = testing::read(&mut WatermarkReader, &frame, &reader_config);
+
+        let stamped_picture = testing::luma(&mut frame);
+        let mut change: f64 = 0.0;
+        for y in 0..256 {
+            for x in 0..256 {
+                change = change.max((stamped_picture.get(x, y) - picture.get(x, y)).abs());
+            }
+        }
+        (decoded == Some(stamped), change)
+    }
+
+    #[test]
+    fn decodes_at_low_strength_on_texture() {
+        for seed in 1..4 {
+            let (decoded, change) = stamp_texture(seed);
+            assert!(decoded, "seed {}", seed);
+            assert!(change <= 4.0, "seed {}: {}", seed, change);
+        }
+    }
+}
@@ -1,24 +1,94 @@
-use crate::stamper::{create_reader, ReaderConfig, StamperType, TimestampReader};
+use crate::stamper::{
+    copies::{self, MAX_COPIES},
+    create_reader,
+    integrity::{self, Rect},
+    palette, Anchor, BlockAlign, CodeLocator, CodecDetector, Detection, DetectorEvent,
+    FeedbackWindow, Interleave, LocatorEvent, MarkerLayout, Orientation, OrientationTracker,
+    Palette, Placement, ReaderConfig, Reading, Region, Scale, StamperType, TimestampReader, Tuning,
+};
 use glib::subclass::{prelude::*, types::ObjectSubclass};
 use gst::{
-    error, info,
+    debug, error, info,
     subclass::{prelude::*, ElementMetadata},
-    BufferRef, Clock, FlowError, FlowSuccess, PadDirection, PadPresence, PadTemplate, SystemClock,
+    warning, BufferRef, Clock, ClockTime, FlowError, FlowSuccess, PadDirection, PadPresence,
+    PadTemplate, PeriodicClockId, SystemClock,
 };
 use gst_base::subclass::BaseTransformMode;
 use gst_video::{
     prelude::*,
-    subclass::prelude::{BaseTransformImpl, VideoFilterImpl},
-    VideoCapsBuilder, VideoFilter, VideoFormat, VideoFrameRef,
+    subclass::prelude::{
+        BaseTransformImpl, BaseTransformImplExt, VideoFilterImpl, VideoFilterImplExt,
+    },
+    VideoCapsBuilder, VideoFilter, VideoFormat, VideoFrameRef, VideoInfo,
 };
 use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::UdpSocket,
+    sync::Mutex,
+};
 
 const DEFAULT_X: u32 = 0;
 const DEFAULT_Y: u32 = 0;
 const DEFAULT_WIDTH: u32 = 64;
 const DEFAULT_HEIGHT: u32 = 64;
+const DEFAULT_MARGIN: u32 = 0;
+const DEFAULT_WIDTH_PERCENT: f64 = 0.0;
+const DEFAULT_HEIGHT_PERCENT: f64 = 0.0;
+const DEFAULT_COPIES: u32 = 1;
+const DEFAULT_REFERENCE_WIDTH: u32 = 0;
+const DEFAULT_REFERENCE_HEIGHT: u32 = 0;
+const DEFAULT_PARITY_SYMBOLS: u32 = 6;
 const DEFAULT_TOLERANCE: u32 = 5;
+const DEFAULT_SEARCH: bool = false;
+const DEFAULT_STALL_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_RETIMESTAMP: bool = false;
+const DEFAULT_CONTENT_HASH: bool = false;
+const DEFAULT_HIGH_COLOR: u32 = palette::DEFAULT_HIGH;
+const DEFAULT_LOW_COLOR: u32 = palette::DEFAULT_LOW;
+const DEFAULT_CELL_SIZE: u32 = 8;
+const DEFAULT_GRID_WIDTH: u32 = 12;
+const DEFAULT_GRID_HEIGHT: u32 = 8;
+const DEFAULT_START_MARKER: u32 = 0xA5A5;
+const DEFAULT_END_MARKER: u32 = 0x5A5A;
+const DEFAULT_BLOCK_SIZE: u32 = 4;
+const DEFAULT_GUARD_PIXELS: u32 = 0;
+const DEFAULT_THRESHOLD: u32 = 0;
+const DEFAULT_MIN_CONFIDENCE: f64 = -1.0;
+const DEFAULT_INTEGRITY_THRESHOLD: f64 = 0.9;
+const DEFAULT_FEEDBACK_HOST: &str = "127.0.0.1";
+const DEFAULT_FEEDBACK_PORT: u32 = 0;
+
+/// Backward jump of the decoded timestamp, in frame intervals, taken
+/// as a new stream (restarted stamper, switched sender, clock step)
+/// rather than repeated frames
+const DISCONTINUITY_FRAMES: u64 = 5;
+
+/// Frame interval assumed for variable frame rate caps (25 fps)
+const DEFAULT_FRAME_INTERVAL_USECS: u64 = 40_000;
+
+/// Properties the code region is resolved from
+const PLACEMENT_PROPERTIES: &[&str] = &[
+    "x",
+    "y",
+    "width",
+    "height",
+    "anchor",
+    "margin",
+    "width-percent",
+    "height-percent",
+    "block-align",
+    "reference-width",
+    "reference-height",
+    "copies",
+    "copy-anchors",
+    "content-hash",
+    "stamper-type",
+];
+
+/// Reader configurations of the code copies and what each one read
+type CopyReadings = (Vec<ReaderConfig>, Vec<Option<Reading>>);
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -32,6 +102,11 @@ pub struct TsLatencyMeasure {
     props: Mutex<Properties>,
     clock: Clock,
     reader: Mutex<Box<dyn TimestampReader>>,
+    detector: Mutex<CodecDetector>,
+    locator: Mutex<CodeLocator>,
+    orientation: Mutex<OrientationTracker>,
+    state: Mutex<State>,
+    watchdog: Mutex<Option<PeriodicClockId>>,
 }
 
 #[derive(Clone)]
@@ -40,8 +115,102 @@ struct Properties {
     y: u32,
     width: u32,
     height: u32,
+    anchor: Anchor,
+    margin: u32,
+    width_percent: f64,
+    height_percent: f64,
+    block_align: BlockAlign,
+    copies: u32,
+    copy_anchors: Option<String>,
+    reference_width: u32,
+    reference_height: u32,
     tolerance: u32,
     stamper_type: StamperType,
+    parity_symbols: u32,
+    interleave: Interleave,
+    high_color: u32,
+    low_color: u32,
+    custom_palette: bool,
+    cell_size: u32,
+    grid_width: u32,
+    grid_height: u32,
+    start_marker: u32,
+    end_marker: u32,
+    block_size: u32,
+    guard_pixels: u32,
+    threshold: u32,
+    min_confidence: f64,
+    search: bool,
+    orientation: Orientation,
+    stall_timeout_ms: u64,
+    retimestamp: bool,
+    log_location: Option<String>,
+    content_hash: bool,
+    integrity_threshold: f64,
+    feedback_host: String,
+    feedback_port: u32,
+}
+
+/// Streaming state used to detect freezes and stalls
+#[derive(Default)]
+struct State {
+    /// Latest decoded timestamp
+    last_stamp: Option<u64>,
+    /// Local time the latest decoded timestamp first arrived
+    last_advance_usecs: Option<u64>,
+    /// Local time the latest buffer arrived
+    last_buffer_usecs: Option<u64>,
+    /// Set while the decoded timestamp stops advancing
+    freeze: Option<Freeze>,
+    /// Set while no buffers arrive for longer than the stall timeout
+    stalled: bool,
+    /// Running time minus stamped time in nanoseconds, fixed by the
+    /// first decoded frame when retimestamping
+    retimestamp_offset: Option<i64>,
+    /// Timestamp to apply to the buffer being transformed
+    pending_pts: Option<ClockTime>,
+    /// Measurement log in CSV format:
+    /// `frame,pts,timestamp,arrival,latency`
+    log: Option<BufWriter<File>>,
+    stats: Stats,
+    /// Size of the negotiated frames
+    frame_size: Option<(u32, u32)>,
+    /// Duration of a frame at the negotiated frame rate
+    frame_interval_usecs: Option<u64>,
+    /// Code regions resolved from the placement properties, the
+    /// primary one first and then its copies
+    regions: Vec<Region>,
+    /// Code regions of frames rotated by 90 degrees since they were
+    /// stamped, resolved against the swapped frame size
+    transposed_regions: Vec<Region>,
+    /// Region of the content hash next to the primary code region, when
+    /// enabled and it fits the frame, and the same for rotated frames
+    hash_region: Option<Region>,
+    transposed_hash_region: Option<Region>,
+    /// Socket decode reports are sent to the stamper from, when enabled
+    feedback_socket: Option<UdpSocket>,
+    /// Decode results since the last report
+    feedback_window: FeedbackWindow,
+}
+
+struct Freeze {
+    start_usecs: u64,
+    frames: u64,
+}
+
+#[derive(Default, Clone)]
+struct Stats {
+    frames: u64,
+    decoded: u64,
+    /// Decoded timestamps from only the coarse layer of a layered codec
+    reduced_precision: u64,
+    /// Decoded timestamps per code copy, the primary one first
+    copy_decoded: Vec<u64>,
+    freezes: u64,
+    stalls: u64,
+    integrity_checked: u64,
+    integrity_mismatches: u64,
+    integrity_score_sum: f64,
 }
 
 impl Default for TsLatencyMeasure {
@@ -51,6 +220,11 @@ impl Default for TsLatencyMeasure {
             props: Mutex::new(Properties::default()),
             clock: SystemClock::obtain(),
             reader: Mutex::new(create_reader(stamper_type)),
+            detector: Mutex::new(CodecDetector::default()),
+            locator: Mutex::new(CodeLocator::default()),
+            orientation: Mutex::new(OrientationTracker::default()),
+            state: Mutex::new(State::default()),
+            watchdog: Mutex::new(None),
         }
     }
 }
@@ -62,8 +236,68 @@ impl Default for Properties {
             y: DEFAULT_Y,
             width: DEFAULT_WIDTH,
             height: DEFAULT_HEIGHT,
+            anchor: Anchor::default(),
+            margin: DEFAULT_MARGIN,
+            width_percent: DEFAULT_WIDTH_PERCENT,
+            height_percent: DEFAULT_HEIGHT_PERCENT,
+            block_align: BlockAlign::default(),
+            copies: DEFAULT_COPIES,
+            copy_anchors: None,
+            reference_width: DEFAULT_REFERENCE_WIDTH,
+            reference_height: DEFAULT_REFERENCE_HEIGHT,
             tolerance: DEFAULT_TOLERANCE,
             stamper_type: StamperType::default(),
+            parity_symbols: DEFAULT_PARITY_SYMBOLS,
+            interleave: Interleave::default(),
+            high_color: DEFAULT_HIGH_COLOR,
+            low_color: DEFAULT_LOW_COLOR,
+            custom_palette: false,
+            cell_size: DEFAULT_CELL_SIZE,
+            grid_width: DEFAULT_GRID_WIDTH,
+            grid_height: DEFAULT_GRID_HEIGHT,
+            start_marker: DEFAULT_START_MARKER,
+            end_marker: DEFAULT_END_MARKER,
+            block_size: DEFAULT_BLOCK_SIZE,
+            guard_pixels: DEFAULT_GUARD_PIXELS,
+            threshold: DEFAULT_THRESHOLD,
+            min_confidence: DEFAULT_MIN_CONFIDENCE,
+            search: DEFAULT_SEARCH,
+            orientation: Orientation::default(),
+            stall_timeout_ms: DEFAULT_STALL_TIMEOUT_MS,
+            retimestamp: DEFAULT_RETIMESTAMP,
+            log_location: None,
+            content_hash: DEFAULT_CONTENT_HASH,
+            integrity_threshold: DEFAULT_INTEGRITY_THRESHOLD,
+            feedback_host: DEFAULT_FEEDBACK_HOST.into(),
+            feedback_port: DEFAULT_FEEDBACK_PORT,
+        }
+    }
+}
+
+impl Properties {
+    fn tuning(&self) -> Tuning {
+        Tuning {
+            layout: MarkerLayout {
+                cell_size: self.cell_size as usize,
+                grid_width: self.grid_width as usize,
+                grid_height: self.grid_height as usize,
+                start_marker: self.start_marker as u16,
+                end_marker: self.end_marker as u16,
+            },
+            block_size: self.block_size as usize,
+            guard_pixels: self.guard_pixels as usize,
+            threshold: (self.threshold > 0).then_some(self.threshold as u8),
+            min_confidence: (self.min_confidence >= 0.0).then_some(self.min_confidence as f32),
+            ..Tuning::default()
+        }
+        .aligned(self.block_align)
+    }
+
+    fn palette(&self) -> Palette {
+        Palette {
+            high: self.high_color,
+            low: self.low_color,
+            custom: self.custom_palette,
         }
     }
 }
@@ -103,6 +337,65 @@ impl ObjectImpl for TsLatencyMeasure {
                     .default_value(DEFAULT_HEIGHT as u64)
                     .mutable_playing()
                     .build(),
+                glib::ParamSpecEnum::builder::<Anchor>("anchor")
+                    .nick("Anchor")
+                    .blurb("Frame corner, edge or centre the time code is placed at, x and y are measured from it")
+                    .default_value(Anchor::default())
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("margin")
+                    .nick("Margin")
+                    .blurb("Distance of the time code from the anchored frame edges")
+                    .default_value(DEFAULT_MARGIN)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("width-percent")
+                    .nick("Width Percent")
+                    .blurb("Time code width in percent of the frame width, overrides width (0 = disabled)")
+                    .minimum(0.0)
+                    .maximum(100.0)
+                    .default_value(DEFAULT_WIDTH_PERCENT)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("height-percent")
+                    .nick("Height Percent")
+                    .blurb("Time code height in percent of the frame height, overrides height (0 = disabled)")
+                    .minimum(0.0)
+                    .maximum(100.0)
+                    .default_value(DEFAULT_HEIGHT_PERCENT)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder::<BlockAlign>("block-align")
+                    .nick("Block Align")
+                    .blurb("Grid of encoder blocks or chroma samples the time code region and cells are snapped to, must match on both ends")
+                    .default_value(BlockAlign::default())
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("copies")
+                    .nick("Copies")
+                    .blurb("Number of locations the time code is read from and voted across, as stamped")
+                    .minimum(1)
+                    .maximum(MAX_COPIES)
+                    .default_value(DEFAULT_COPIES)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecString::builder("copy-anchors")
+                    .nick("Copy Anchors")
+                    .blurb("Comma-separated anchors of the extra copies, e.g. \"top-right,bottom-left\", the remaining copies are placed automatically")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("reference-width")
+                    .nick("Reference Width")
+                    .blurb("Frame width the time code geometry is given for, scaled to the actual frame width (0 = disabled)")
+                    .default_value(DEFAULT_REFERENCE_WIDTH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("reference-height")
+                    .nick("Reference Height")
+                    .blurb("Frame height the time code geometry is given for, scaled to the actual frame height (0 = disabled)")
+                    .default_value(DEFAULT_REFERENCE_HEIGHT)
+                    .mutable_playing()
+                    .build(),
                 glib::ParamSpecUInt::builder("tolerance")
                     .nick("tolerance")
                     .blurb("Tolerance for color matching")
@@ -111,10 +404,167 @@ impl ObjectImpl for TsLatencyMeasure {
                     .build(),
                 glib::ParamSpecEnum::builder::<StamperType>("stamper-type")
                     .nick("Stamper Type")
-                    .blurb("Type of timestamp reader to use (must match stamper)")
+                    .blurb("Type of timestamp reader to use (must match stamper, or auto to detect it)")
                     .default_value(StamperType::default())
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecUInt::builder("parity-symbols")
+                    .nick("Parity Symbols")
+                    .blurb("Number of Reed-Solomon parity symbols, corrects up to half as many byte errors")
+                    .minimum(2)
+                    .maximum(64)
+                    .default_value(DEFAULT_PARITY_SYMBOLS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder::<Interleave>("interleave")
+                    .nick("Interleave")
+                    .blurb("Placement of code bits on the grid for error correcting codecs, must match on both ends")
+                    .default_value(Interleave::default())
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("high-color")
+                    .nick("High Color")
+                    .blurb("Color of the cells carrying one bits as 0xRRGGBB, resolved in the negotiated colorimetry, must match on both ends")
+                    .maximum(0xFFFFFF)
+                    .default_value(DEFAULT_HIGH_COLOR)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("low-color")
+                    .nick("Low Color")
+                    .blurb("Color of the cells carrying zero bits as 0xRRGGBB, resolved in the negotiated colorimetry, must match on both ends")
+                    .maximum(0xFFFFFF)
+                    .default_value(DEFAULT_LOW_COLOR)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("cell-size")
+                    .nick("Cell Size")
+                    .blurb("Side of the optimized stamper's cells in pixels, must match on both ends")
+                    .minimum(1)
+                    .maximum(64)
+                    .default_value(DEFAULT_CELL_SIZE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("grid-width")
+                    .nick("Grid Width")
+                    .blurb("Cells per row of the optimized stamper's grid, must match on both ends")
+                    .minimum(1)
+                    .maximum(96)
+                    .default_value(DEFAULT_GRID_WIDTH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("grid-height")
+                    .nick("Grid Height")
+                    .blurb("Rows of the optimized stamper's grid, must match on both ends")
+                    .minimum(1)
+                    .maximum(96)
+                    .default_value(DEFAULT_GRID_HEIGHT)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("start-marker")
+                    .nick("Start Marker")
+                    .blurb("16-bit pattern opening the optimized stamper's code, must match on both ends")
+                    .maximum(0xFFFF)
+                    .default_value(DEFAULT_START_MARKER)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("end-marker")
+                    .nick("End Marker")
+                    .blurb("16-bit pattern closing the optimized stamper's code, must match on both ends")
+                    .maximum(0xFFFF)
+                    .default_value(DEFAULT_END_MARKER)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("block-size")
+                    .nick("Block Size")
+                    .blurb("Side of the fast-robust stamper's cells in pixels, must match on both ends")
+                    .minimum(1)
+                    .maximum(64)
+                    .default_value(DEFAULT_BLOCK_SIZE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("guard-pixels")
+                    .nick("Guard Pixels")
+                    .blurb("Pixels left unstamped between the fast-robust stamper's cells, must match on both ends")
+                    .maximum(32)
+                    .default_value(DEFAULT_GUARD_PIXELS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("threshold")
+                    .nick("Threshold")
+                    .blurb("Fixed luma decision level of the optimized and fast-robust readers (0 = adaptive, from cells of known value)")
+                    .maximum(255)
+                    .default_value(DEFAULT_THRESHOLD)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("min-confidence")
+                    .nick("Min Confidence")
+                    .blurb("Average confidence below which the optimized and fast-robust readers reject a code (-1 = reader default)")
+                    .minimum(-1.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_MIN_CONFIDENCE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("search")
+                    .nick("Search")
+                    .blurb("Search the frame for the optimized stamper's markers instead of reading at x/y, and follow the code when it moves")
+                    .default_value(DEFAULT_SEARCH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder::<Orientation>("orientation")
+                    .nick("Orientation")
+                    .blurb("Rotation or flip the frames went through since they were stamped, auto follows image-orientation tags and detects it")
+                    .default_value(Orientation::default())
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt64::builder("stall-timeout")
+                    .nick("Stall Timeout")
+                    .blurb("Report a stall when no buffers arrive for this many milliseconds (0 = disabled)")
+                    .default_value(DEFAULT_STALL_TIMEOUT_MS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("retimestamp")
+                    .nick("Retimestamp")
+                    .blurb("Rewrite buffer PTS/DTS from the decoded timestamps")
+                    .default_value(DEFAULT_RETIMESTAMP)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("log-location")
+                    .nick("Log Location")
+                    .blurb("File to log decoded values with their PTS and arrival time as CSV")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("content-hash")
+                    .nick("Content Hash")
+                    .blurb("Compare the picture against the hash stamped below the time code")
+                    .default_value(DEFAULT_CONTENT_HASH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("integrity-threshold")
+                    .nick("Integrity Threshold")
+                    .blurb("Report frames whose content hash similarity is below this value")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_INTEGRITY_THRESHOLD)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecString::builder("feedback-host")
+                    .nick("Feedback Host")
+                    .blurb("Host of the stamper decode reports are sent to")
+                    .default_value(Some(DEFAULT_FEEDBACK_HOST))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("feedback-port")
+                    .nick("Feedback Port")
+                    .blurb("UDP port decode reports are sent to for the adaptive stamper (0 = disabled)")
+                    .maximum(u16::MAX as u32)
+                    .default_value(DEFAULT_FEEDBACK_PORT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Structure>("stats")
+                    .nick("Statistics")
+                    .blurb("Frame, decode, freeze and stall counters")
+                    .read_only()
+                    .build(),
             ]
         });
 
@@ -171,6 +621,119 @@ impl ObjectImpl for TsLatencyMeasure {
                 );
                 props.height = height;
             }
+            "anchor" => {
+                let mut props = self.props.lock().unwrap();
+                let anchor = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing anchor from {:?} to {:?}",
+                    props.anchor,
+                    anchor
+                );
+                props.anchor = anchor;
+            }
+            "margin" => {
+                let mut props = self.props.lock().unwrap();
+                let margin = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing margin from {} to {}",
+                    props.margin,
+                    margin
+                );
+                props.margin = margin;
+            }
+            "width-percent" => {
+                let mut props = self.props.lock().unwrap();
+                let width_percent = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing width percent from {} to {}",
+                    props.width_percent,
+                    width_percent
+                );
+                props.width_percent = width_percent;
+            }
+            "height-percent" => {
+                let mut props = self.props.lock().unwrap();
+                let height_percent = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing height percent from {} to {}",
+                    props.height_percent,
+                    height_percent
+                );
+                props.height_percent = height_percent;
+            }
+            "block-align" => {
+                let mut props = self.props.lock().unwrap();
+                let block_align = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing block alignment from {:?} to {:?}",
+                    props.block_align,
+                    block_align
+                );
+                props.block_align = block_align;
+            }
+            "copies" => {
+                let mut props = self.props.lock().unwrap();
+                let copies = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing copies from {} to {}",
+                    props.copies,
+                    copies
+                );
+                props.copies = copies;
+            }
+            "copy-anchors" => {
+                let mut props = self.props.lock().unwrap();
+                let copy_anchors: Option<String> = value.get().expect("type checked upstream");
+                if let Err(err) = copies::parse_anchors(copy_anchors.as_deref().unwrap_or_default())
+                {
+                    warning!(CAT, imp: self, "Ignoring copy anchors: {}", err);
+                    return;
+                }
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing copy anchors from {:?} to {:?}",
+                    props.copy_anchors,
+                    copy_anchors
+                );
+                props.copy_anchors = copy_anchors;
+            }
+            "reference-width" => {
+                let mut props = self.props.lock().unwrap();
+                let reference_width = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing reference width from {} to {}",
+                    props.reference_width,
+                    reference_width
+                );
+                props.reference_width = reference_width;
+            }
+            "reference-height" => {
+                let mut props = self.props.lock().unwrap();
+                let reference_height = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing reference height from {} to {}",
+                    props.reference_height,
+                    reference_height
+                );
+                props.reference_height = reference_height;
+            }
             "tolerance" => {
                 let mut props = self.props.lock().unwrap();
                 let tolerance = value.get().expect("type checked upstream");
@@ -183,6 +746,108 @@ impl ObjectImpl for TsLatencyMeasure {
                 );
                 props.tolerance = tolerance;
             }
+            "parity-symbols" => {
+                let mut props = self.props.lock().unwrap();
+                let parity_symbols = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing parity symbols from {} to {}",
+                    props.parity_symbols,
+                    parity_symbols
+                );
+                props.parity_symbols = parity_symbols;
+                self.check_tuning(&props, &self.state.lock().unwrap());
+            }
+            "interleave" => {
+                let mut props = self.props.lock().unwrap();
+                let interleave = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing interleave from {:?} to {:?}",
+                    props.interleave,
+                    interleave
+                );
+                props.interleave = interleave;
+                self.check_interleave(&props);
+            }
+            "high-color" => {
+                let mut props = self.props.lock().unwrap();
+                let high_color = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing high color from {:#08x} to {:#08x}",
+                    props.high_color,
+                    high_color
+                );
+                props.high_color = high_color;
+                props.custom_palette = true;
+                self.check_palette(&props);
+            }
+            "low-color" => {
+                let mut props = self.props.lock().unwrap();
+                let low_color = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing low color from {:#08x} to {:#08x}",
+                    props.low_color,
+                    low_color
+                );
+                props.low_color = low_color;
+                props.custom_palette = true;
+                self.check_palette(&props);
+            }
+            "cell-size" => {
+                let cell_size = value.get().expect("type checked upstream");
+                self.update_tuning("cell size", |props| &mut props.cell_size, cell_size);
+            }
+            "grid-width" => {
+                let grid_width = value.get().expect("type checked upstream");
+                self.update_tuning("grid width", |props| &mut props.grid_width, grid_width);
+            }
+            "grid-height" => {
+                let grid_height = value.get().expect("type checked upstream");
+                self.update_tuning("grid height", |props| &mut props.grid_height, grid_height);
+            }
+            "start-marker" => {
+                let start_marker = value.get().expect("type checked upstream");
+                self.update_tuning(
+                    "start marker",
+                    |props| &mut props.start_marker,
+                    start_marker,
+                );
+            }
+            "end-marker" => {
+                let end_marker = value.get().expect("type checked upstream");
+                self.update_tuning("end marker", |props| &mut props.end_marker, end_marker);
+            }
+            "block-size" => {
+                let block_size = value.get().expect("type checked upstream");
+                self.update_tuning("block size", |props| &mut props.block_size, block_size);
+            }
+            "guard-pixels" => {
+                let guard_pixels = value.get().expect("type checked upstream");
+                self.update_tuning(
+                    "guard pixels",
+                    |props| &mut props.guard_pixels,
+                    guard_pixels,
+                );
+            }
+            "threshold" => {
+                let threshold = value.get().expect("type checked upstream");
+                self.update_tuning("threshold", |props| &mut props.threshold, threshold);
+            }
+            "min-confidence" => {
+                let min_confidence = value.get().expect("type checked upstream");
+                self.update_tuning(
+                    "min confidence",
+                    |props| &mut props.min_confidence,
+                    min_confidence,
+                );
+            }
             "stamper-type" => {
                 let mut props = self.props.lock().unwrap();
                 let stamper_type = value.get().expect("type checked upstream");
@@ -194,9 +859,132 @@ impl ObjectImpl for TsLatencyMeasure {
                 );
                 props.stamper_type = stamper_type;
                 *self.reader.lock().unwrap() = create_reader(stamper_type);
+                self.detector.lock().unwrap().reset();
+                self.check_tuning(&props, &self.state.lock().unwrap());
+                self.check_interleave(&props);
+                self.check_search(&props);
+            }
+            "search" => {
+                let mut props = self.props.lock().unwrap();
+                let search = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing search from {} to {}",
+                    props.search,
+                    search
+                );
+                props.search = search;
+                self.locator.lock().unwrap().reset();
+                self.check_search(&props);
+            }
+            "orientation" => {
+                let mut props = self.props.lock().unwrap();
+                let orientation = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing orientation from {:?} to {:?}",
+                    props.orientation,
+                    orientation
+                );
+                props.orientation = orientation;
+                self.orientation.lock().unwrap().reset();
+                self.locator.lock().unwrap().reset();
+            }
+            "stall-timeout" => {
+                let mut props = self.props.lock().unwrap();
+                let stall_timeout_ms = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing stall timeout from {} to {} ms",
+                    props.stall_timeout_ms,
+                    stall_timeout_ms
+                );
+                props.stall_timeout_ms = stall_timeout_ms;
+            }
+            "retimestamp" => {
+                let mut props = self.props.lock().unwrap();
+                let retimestamp = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing retimestamp from {} to {}",
+                    props.retimestamp,
+                    retimestamp
+                );
+                props.retimestamp = retimestamp;
+            }
+            "feedback-host" => {
+                let mut props = self.props.lock().unwrap();
+                let feedback_host = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_FEEDBACK_HOST.into());
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing feedback host from {} to {}",
+                    props.feedback_host,
+                    feedback_host
+                );
+                props.feedback_host = feedback_host;
+            }
+            "feedback-port" => {
+                let mut props = self.props.lock().unwrap();
+                let feedback_port = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing feedback port from {} to {}",
+                    props.feedback_port,
+                    feedback_port
+                );
+                props.feedback_port = feedback_port;
+            }
+            "log-location" => {
+                let mut props = self.props.lock().unwrap();
+                let log_location = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing log location from {:?} to {:?}",
+                    props.log_location,
+                    log_location
+                );
+                props.log_location = log_location;
+            }
+            "content-hash" => {
+                let mut props = self.props.lock().unwrap();
+                let content_hash = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing content-hash from {} to {}",
+                    props.content_hash,
+                    content_hash
+                );
+                props.content_hash = content_hash;
+            }
+            "integrity-threshold" => {
+                let mut props = self.props.lock().unwrap();
+                let integrity_threshold = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing integrity threshold from {} to {}",
+                    props.integrity_threshold,
+                    integrity_threshold
+                );
+                props.integrity_threshold = integrity_threshold;
             }
             _ => unimplemented!(),
         }
+
+        if PLACEMENT_PROPERTIES.contains(&pspec.name()) {
+            self.resolve_region();
+        }
     }
 
     fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
@@ -217,6 +1005,42 @@ impl ObjectImpl for TsLatencyMeasure {
                 let props = self.props.lock().unwrap();
                 props.height.to_value()
             }
+            "anchor" => {
+                let props = self.props.lock().unwrap();
+                props.anchor.to_value()
+            }
+            "margin" => {
+                let props = self.props.lock().unwrap();
+                props.margin.to_value()
+            }
+            "width-percent" => {
+                let props = self.props.lock().unwrap();
+                props.width_percent.to_value()
+            }
+            "height-percent" => {
+                let props = self.props.lock().unwrap();
+                props.height_percent.to_value()
+            }
+            "block-align" => {
+                let props = self.props.lock().unwrap();
+                props.block_align.to_value()
+            }
+            "copies" => {
+                let props = self.props.lock().unwrap();
+                props.copies.to_value()
+            }
+            "copy-anchors" => {
+                let props = self.props.lock().unwrap();
+                props.copy_anchors.to_value()
+            }
+            "reference-width" => {
+                let props = self.props.lock().unwrap();
+                props.reference_width.to_value()
+            }
+            "reference-height" => {
+                let props = self.props.lock().unwrap();
+                props.reference_height.to_value()
+            }
             "tolerance" => {
                 let props = self.props.lock().unwrap();
                 props.tolerance.to_value()
@@ -225,16 +1049,131 @@ impl ObjectImpl for TsLatencyMeasure {
                 let props = self.props.lock().unwrap();
                 props.stamper_type.to_value()
             }
-            _ => unimplemented!(),
-        }
-    }
-}
-
-impl GstObjectImpl for TsLatencyMeasure {}
-
-impl ElementImpl for TsLatencyMeasure {
-    fn metadata() -> Option<&'static ElementMetadata> {
-        static ELEMENT_METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            "parity-symbols" => {
+                let props = self.props.lock().unwrap();
+                props.parity_symbols.to_value()
+            }
+            "interleave" => {
+                let props = self.props.lock().unwrap();
+                props.interleave.to_value()
+            }
+            "high-color" => {
+                let props = self.props.lock().unwrap();
+                props.high_color.to_value()
+            }
+            "low-color" => {
+                let props = self.props.lock().unwrap();
+                props.low_color.to_value()
+            }
+            "cell-size" => {
+                let props = self.props.lock().unwrap();
+                props.cell_size.to_value()
+            }
+            "grid-width" => {
+                let props = self.props.lock().unwrap();
+                props.grid_width.to_value()
+            }
+            "grid-height" => {
+                let props = self.props.lock().unwrap();
+                props.grid_height.to_value()
+            }
+            "start-marker" => {
+                let props = self.props.lock().unwrap();
+                props.start_marker.to_value()
+            }
+            "end-marker" => {
+                let props = self.props.lock().unwrap();
+                props.end_marker.to_value()
+            }
+            "block-size" => {
+                let props = self.props.lock().unwrap();
+                props.block_size.to_value()
+            }
+            "guard-pixels" => {
+                let props = self.props.lock().unwrap();
+                props.guard_pixels.to_value()
+            }
+            "threshold" => {
+                let props = self.props.lock().unwrap();
+                props.threshold.to_value()
+            }
+            "min-confidence" => {
+                let props = self.props.lock().unwrap();
+                props.min_confidence.to_value()
+            }
+            "search" => {
+                let props = self.props.lock().unwrap();
+                props.search.to_value()
+            }
+            "orientation" => {
+                let props = self.props.lock().unwrap();
+                props.orientation.to_value()
+            }
+            "stall-timeout" => {
+                let props = self.props.lock().unwrap();
+                props.stall_timeout_ms.to_value()
+            }
+            "retimestamp" => {
+                let props = self.props.lock().unwrap();
+                props.retimestamp.to_value()
+            }
+            "log-location" => {
+                let props = self.props.lock().unwrap();
+                props.log_location.to_value()
+            }
+            "feedback-host" => {
+                let props = self.props.lock().unwrap();
+                props.feedback_host.to_value()
+            }
+            "feedback-port" => {
+                let props = self.props.lock().unwrap();
+                props.feedback_port.to_value()
+            }
+            "content-hash" => {
+                let props = self.props.lock().unwrap();
+                props.content_hash.to_value()
+            }
+            "integrity-threshold" => {
+                let props = self.props.lock().unwrap();
+                props.integrity_threshold.to_value()
+            }
+            "stats" => {
+                let stats = self.state.lock().unwrap().stats.clone();
+                gst::Structure::builder("tslatencymeasure-stats")
+                    .field("frames", stats.frames)
+                    .field("decoded", stats.decoded)
+                    .field("reduced-precision", stats.reduced_precision)
+                    .field("copy-decoded", gst::Array::new(&stats.copy_decoded))
+                    .field(
+                        "copy-success-rate",
+                        gst::Array::new(
+                            stats
+                                .copy_decoded
+                                .iter()
+                                .map(|&decoded| decoded as f64 / stats.frames.max(1) as f64),
+                        ),
+                    )
+                    .field("freezes", stats.freezes)
+                    .field("stalls", stats.stalls)
+                    .field("integrity-checked", stats.integrity_checked)
+                    .field("integrity-mismatches", stats.integrity_mismatches)
+                    .field(
+                        "integrity-mean-score",
+                        stats.integrity_score_sum / stats.integrity_checked.max(1) as f64,
+                    )
+                    .build()
+                    .to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for TsLatencyMeasure {}
+
+impl ElementImpl for TsLatencyMeasure {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
             ElementMetadata::new(
                 "latency measurement using  binary time code",
                 "Filter/Effect/Converter/Video",
@@ -255,7 +1194,7 @@ impl ElementImpl for TsLatencyMeasure {
                 .format_list([
                     Rgbx, Bgrx, Xrgb, Xbgr, Rgba, Bgra, Gbra, Argb, Abgr, Rgb, Bgr, Gbr, I420,
                     Yv12, Yvyu, Vyuy, Uyvy, Yuy2, Ayuv, Y41b, Y42b, Nv12, Nv16, Nv21, Nv24, Nv61,
-                    A420, Yuv9, Yvu9, Iyu1,
+                    A420, Yuv9, Yvu9, Iyu1, Y444, V308, Iyu2,
                 ])
                 .build();
 
@@ -276,28 +1215,250 @@ impl BaseTransformImpl for TsLatencyMeasure {
     const MODE: BaseTransformMode = BaseTransformMode::AlwaysInPlace;
     const PASSTHROUGH_ON_SAME_CAPS: bool = false;
     const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        let props = self.props.lock().unwrap().clone();
+
+        let log = match props.log_location {
+            Some(path) => {
+                let mut log = File::create(&path).map(BufWriter::new).map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::OpenWrite,
+                        ["Could not open measurement log {}: {}", path, err]
+                    )
+                })?;
+                writeln!(log, "frame,pts,timestamp,arrival,latency").map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::Write,
+                        ["Could not write measurement log {}: {}", path, err]
+                    )
+                })?;
+                Some(log)
+            }
+            None => None,
+        };
+
+        let feedback_socket = if props.feedback_port > 0 {
+            let target = (props.feedback_host.as_str(), props.feedback_port as u16);
+            let socket = UdpSocket::bind(("0.0.0.0", 0))
+                .and_then(|socket| socket.connect(target).map(|_| socket))
+                .map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::OpenWrite,
+                        [
+                            "Could not open feedback socket to {}:{}: {}",
+                            target.0,
+                            target.1,
+                            err
+                        ]
+                    )
+                })?;
+            Some(socket)
+        } else {
+            None
+        };
+
+        *self.state.lock().unwrap() = State {
+            log,
+            feedback_socket,
+            ..State::default()
+        };
+        self.locator.lock().unwrap().reset();
+        *self.orientation.lock().unwrap() = OrientationTracker::default();
+
+        let stall_timeout_ms = props.stall_timeout_ms;
+        if stall_timeout_ms == 0 {
+            return Ok(());
+        }
+
+        // Poll a few times per timeout so stalls are reported promptly
+        let interval = ClockTime::from_mseconds(stall_timeout_ms.div_ceil(4));
+        let watchdog = self
+            .clock
+            .new_periodic_id(self.clock.time().unwrap() + interval, interval);
+        let element = self.obj().downgrade();
+        watchdog
+            .wait_async(move |_clock, _time, _id| {
+                if let Some(element) = element.upgrade() {
+                    element.imp().check_stall();
+                }
+            })
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::CoreError::Clock,
+                    ["Failed to schedule stall watchdog: {:?}", err]
+                )
+            })?;
+        *self.watchdog.lock().unwrap() = Some(watchdog);
+
+        Ok(())
+    }
+
+    fn sink_event(&self, event: gst::Event) -> bool {
+        if let gst::EventView::Tag(tag) = event.view() {
+            let orientation = tag
+                .tag()
+                .get::<gst::tags::ImageOrientation>()
+                .and_then(|value| Orientation::from_tag(value.get()));
+            if let Some(orientation) = orientation {
+                info!(
+                    CAT,
+                    imp: self,
+                    "Frames tagged with orientation {:?}",
+                    orientation
+                );
+                self.orientation.lock().unwrap().tag(orientation);
+                self.locator.lock().unwrap().reset();
+            }
+        }
+
+        self.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        let res = self.parent_transform_ip(buf)?;
+
+        // The mapped video frame cannot be retimestamped, so the
+        // timestamp computed while decoding is applied here.
+        if let Some(pts) = self.state.lock().unwrap().pending_pts.take() {
+            buf.set_pts(pts);
+            buf.set_dts(pts);
+        }
+
+        Ok(res)
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        if let Some(watchdog) = self.watchdog.lock().unwrap().take() {
+            watchdog.unschedule();
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(mut log) = state.log.take() {
+            log.flush().map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Write,
+                    ["Could not flush measurement log: {}", err]
+                )
+            })?;
+        }
+
+        Ok(())
+    }
 }
 
 impl VideoFilterImpl for TsLatencyMeasure {
+    fn set_info(
+        &self,
+        incaps: &gst::Caps,
+        in_info: &VideoInfo,
+        outcaps: &gst::Caps,
+        out_info: &VideoInfo,
+    ) -> Result<(), gst::LoggableError> {
+        let fps = in_info.fps();
+        let mut state = self.state.lock().unwrap();
+        state.frame_size = Some((in_info.width(), in_info.height()));
+        state.frame_interval_usecs = (fps.numer() > 0 && fps.denom() > 0)
+            .then(|| 1_000_000 * fps.denom() as u64 / fps.numer() as u64);
+        drop(state);
+        self.resolve_region();
+
+        self.parent_set_info(incaps, in_info, outcaps, out_info)
+    }
+
     fn transform_frame_ip(
         &self,
         frame: &mut VideoFrameRef<&mut BufferRef>,
     ) -> Result<FlowSuccess, FlowError> {
         let props = self.props.lock().unwrap();
-        let config = ReaderConfig {
-            x: props.x,
-            y: props.y,
-            width: props.width,
-            height: props.height,
-            tolerance: props.tolerance,
-        };
+        let orientation = props.orientation;
+        let stamper_type = props.stamper_type;
+        let retimestamp = props.retimestamp;
+        let content_hash = props.content_hash;
+        let integrity_threshold = props.integrity_threshold;
+        // Only the optimized code has markers to search for
+        let search = props.search && stamper_type == StamperType::Optimized;
         drop(props);
 
-        let reader = self.reader.lock().unwrap();
-        match reader.read(frame, &self.clock, &config)? {
-            Some(stamped_usecs) => {
-                let curr_usecs = self.clock.time().unwrap().useconds();
-                let diff_usecs = curr_usecs - stamped_usecs;
+        // Follow tags and detected flips unless the orientation is fixed
+        let auto_orientation = orientation == Orientation::Auto;
+        let orientation = if auto_orientation {
+            self.orientation.lock().unwrap().current()
+        } else {
+            orientation
+        };
+        let mut configs = self.reader_configs(frame, orientation)?;
+
+        // Read at the located position, or at x/y until the code is found
+        if search {
+            let location = self.locator.lock().unwrap().locate(
+                frame,
+                &configs[0].tuning.layout,
+                configs[0].scale,
+                configs[0].orientation,
+            )?;
+            if let Some(event) = location.event {
+                self.post_locator_event(event);
+            }
+            if let Some((x, y)) = location.position {
+                configs[0].x = x;
+                configs[0].y = y;
+            }
+        }
+
+        let mut readings = self.read_copies(frame, &configs, stamper_type)?;
+        let decoded = readings.iter().any(Option::is_some);
+        if auto_orientation && self.orientation.lock().unwrap().report(decoded) {
+            if let Some((probed_configs, probed_readings)) =
+                self.probe_orientation(frame, stamper_type)?
+            {
+                configs = probed_configs;
+                readings = probed_readings;
+            }
+        }
+        self.track_copies(&readings);
+        let reading = copies::vote(&readings);
+        self.send_feedback(reading.as_ref());
+        let stamped_usecs = reading.map(|reading| reading.timestamp);
+        if search {
+            let event = self.locator.lock().unwrap().report(stamped_usecs.is_some());
+            if let Some(event) = event {
+                self.post_locator_event(event);
+            }
+        }
+        let reduced_precision = reading.is_some_and(|reading| reading.reduced_precision);
+
+        let curr_usecs = self.clock.time().unwrap().useconds();
+        // The clocks of stamper and reader may not be synchronized
+        let latency_usecs =
+            stamped_usecs.map(|stamped_usecs| curr_usecs as i64 - stamped_usecs as i64);
+
+        self.log_frame(frame.buffer(), stamped_usecs, curr_usecs, latency_usecs)?;
+        self.track_frame(stamped_usecs, curr_usecs);
+        if reduced_precision {
+            self.state.lock().unwrap().stats.reduced_precision += 1;
+        }
+
+        if content_hash {
+            self.check_integrity(frame, &configs, stamped_usecs, integrity_threshold)?;
+        }
+
+        if retimestamp {
+            if let Some(stamped_usecs) = stamped_usecs {
+                self.retimestamp(frame.buffer(), stamped_usecs);
+            }
+        }
+
+        match latency_usecs {
+            Some(diff_usecs) if reduced_precision => {
+                info!(
+                    CAT,
+                    imp: self,
+                    "Delay {} usecs (reduced precision)",
+                    diff_usecs
+                );
+            }
+            Some(diff_usecs) => {
                 info!(
                     CAT,
                     imp: self,
@@ -317,3 +1478,693 @@ impl VideoFilterImpl for TsLatencyMeasure {
         Ok(FlowSuccess::Ok)
     }
 }
+
+impl TsLatencyMeasure {
+    /// Set a tuning property unless the resulting tuning is inconsistent
+    fn update_tuning<T: Copy + std::fmt::Debug>(
+        &self,
+        name: &str,
+        field: impl Fn(&mut Properties) -> &mut T,
+        value: T,
+    ) {
+        let mut props = self.props.lock().unwrap();
+        let mut updated = props.clone();
+        *field(&mut updated) = value;
+        if let Err(err) = updated.tuning().validate() {
+            warning!(CAT, imp: self, "Ignoring {} {:?}: {}", name, value, err);
+            return;
+        }
+        info!(
+            CAT,
+            imp: self,
+            "Changing {} from {:?} to {:?}",
+            name,
+            field(&mut props),
+            value
+        );
+        *props = updated;
+        self.check_tuning(&props, &self.state.lock().unwrap());
+        self.locator.lock().unwrap().reset();
+    }
+
+    /// Warn if the code does not fit the primary region
+    fn check_tuning(&self, props: &Properties, state: &State) {
+        let Some(region) = state.regions.first() else {
+            return;
+        };
+        if props.content_hash && state.hash_region.is_none() {
+            warning!(
+                CAT,
+                imp: self,
+                "Content hash does not fit next to the time code, frames are not checked"
+            );
+        }
+        if let Err(err) = props.tuning().check_fit(
+            props.stamper_type,
+            props.parity_symbols,
+            region.width,
+            region.height,
+        ) {
+            warning!(CAT, imp: self, "Time code does not fit: {}", err);
+        }
+    }
+
+    /// Warn about block interleaving for codes with a single codeword,
+    /// which it leaves in order
+    fn check_interleave(&self, props: &Properties) {
+        if props.interleave == Interleave::Block
+            && !matches!(
+                props.stamper_type,
+                StamperType::FastRobust | StamperType::Auto
+            )
+        {
+            warning!(
+                CAT,
+                imp: self,
+                "Block interleaving only applies to the fast-robust code, the {} code is stamped in codeword order",
+                props.stamper_type.as_str()
+            );
+        }
+    }
+
+    /// Warn about searching for a code without markers, which keeps
+    /// being read at x/y
+    fn check_search(&self, props: &Properties) {
+        if props.search && props.stamper_type != StamperType::Optimized {
+            warning!(
+                CAT,
+                imp: self,
+                "Search only finds the optimized code, the {} code is read at {},{}",
+                props.stamper_type.as_str(),
+                props.x,
+                props.y
+            );
+        }
+    }
+
+    /// Warn about palettes the codecs cannot tell apart reliably
+    fn check_palette(&self, props: &Properties) {
+        if !props.palette().is_legible() {
+            warning!(
+                CAT,
+                imp: self,
+                "High color {:#08x} is not clearly brighter than low color {:#08x}, codes may not decode",
+                props.high_color,
+                props.low_color
+            );
+        }
+    }
+
+    /// Resolve the code region against the negotiated frame size, in
+    /// reference pixels if a reference resolution is set
+    fn resolve_region(&self) {
+        let props = self.props.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        let Some((width, height)) = state.frame_size else {
+            return;
+        };
+
+        let configured = props
+            .copy_anchors
+            .as_deref()
+            .map(copies::parse_anchors)
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        // Copying the watermark would copy the picture it is spread over
+        let copy_count = if props.stamper_type == StamperType::Watermark && props.copies > 1 {
+            warning!(
+                CAT,
+                imp: self,
+                "The watermark code is spread over the picture and cannot be copied, ignoring copies"
+            );
+            1
+        } else {
+            props.copies
+        };
+        let anchors = copies::copy_anchors(props.anchor, copy_count, &configured);
+        let placement = Placement {
+            anchor: props.anchor,
+            margin: props.margin,
+            x: props.x,
+            y: props.y,
+            width: props.width,
+            height: props.height,
+            width_percent: props.width_percent,
+            height_percent: props.height_percent,
+            alignment: props.block_align,
+        };
+        // Copies dropped by the stamper are not read either
+        let resolve = |width: u32, height: u32| {
+            let scale = Scale::new(width, height, props.reference_width, props.reference_height);
+            let (code_width, code_height) = (
+                scale.code_width(width as usize) as u32,
+                scale.code_height(height as usize) as u32,
+            );
+            let regions: Vec<_> = anchors
+                .iter()
+                .map(|&anchor| {
+                    Placement {
+                        anchor,
+                        ..placement
+                    }
+                    .resolve(code_width, code_height)
+                })
+                .collect();
+            let hash_region = regions
+                .first()
+                .filter(|_| props.content_hash)
+                .and_then(|primary| integrity::hash_region(primary, code_width, code_height));
+            let (regions, dropped) = copies::arrange(&regions, hash_region.as_slice());
+            (regions, dropped, hash_region)
+        };
+        let (regions, dropped, hash_region) = resolve(width, height);
+        for copy in dropped {
+            warning!(
+                CAT,
+                imp: self,
+                "Ignoring the code copy at {},{}, it overlaps another code region or the content hash",
+                copy.x,
+                copy.y
+            );
+        }
+
+        info!(
+            CAT,
+            imp: self,
+            "Time code regions {:?}, content hash region {:?}",
+            regions,
+            hash_region
+        );
+        state.regions = regions;
+        state.hash_region = hash_region;
+        (state.transposed_regions, _, state.transposed_hash_region) = resolve(height, width);
+        self.check_tuning(&props, &state);
+    }
+
+    /// Reader configurations of the code copies, the primary one first,
+    /// for frames that went through `orientation` since they were
+    /// stamped
+    fn reader_configs(
+        &self,
+        frame: &VideoFrameRef<&BufferRef>,
+        orientation: Orientation,
+    ) -> Result<Vec<ReaderConfig>, FlowError> {
+        let state = self.state.lock().unwrap();
+        let regions = if orientation.transposes() {
+            state.transposed_regions.clone()
+        } else {
+            state.regions.clone()
+        };
+        drop(state);
+        if regions.is_empty() {
+            return Err(FlowError::NotNegotiated);
+        }
+
+        let props = self.props.lock().unwrap();
+        let (width, height) = orientation.source_size(frame.width(), frame.height());
+        let scale = Scale::new(width, height, props.reference_width, props.reference_height);
+        let fill = props.palette().resolve(frame.info());
+        let tuning = props.tuning();
+        Ok(regions
+            .into_iter()
+            .map(|region| ReaderConfig {
+                x: region.x,
+                y: region.y,
+                width: region.width,
+                height: region.height,
+                parity_symbols: props.parity_symbols,
+                interleave: props.interleave,
+                tolerance: props.tolerance,
+                scale,
+                orientation,
+                fill,
+                tuning,
+            })
+            .collect())
+    }
+
+    /// Read the code with the configured reader, or the detector in
+    /// `auto` mode
+    fn read_code(
+        &self,
+        frame: &VideoFrameRef<&BufferRef>,
+        config: &ReaderConfig,
+        stamper_type: StamperType,
+    ) -> Result<Option<Reading>, FlowError> {
+        if stamper_type == StamperType::Auto {
+            let detection = self
+                .detector
+                .lock()
+                .unwrap()
+                .read(frame, &self.clock, config);
+            if let Some(event) = detection.event {
+                self.post_detector_event(event);
+            }
+            Ok(self.log_detector_errors(detection))
+        } else {
+            self.reader
+                .lock()
+                .unwrap()
+                .read_with_precision(frame, &self.clock, config)
+        }
+    }
+
+    /// Read every copy of the code, the primary one as in
+    /// [`Self::read_code`] and the others with the same reader.
+    ///
+    /// In `auto` mode only the primary copy counts towards codec
+    /// detection, the others are read once a codec is locked.
+    fn read_copies(
+        &self,
+        frame: &VideoFrameRef<&BufferRef>,
+        configs: &[ReaderConfig],
+        stamper_type: StamperType,
+    ) -> Result<Vec<Option<Reading>>, FlowError> {
+        let Some((primary, others)) = configs.split_first() else {
+            return Ok(vec![]);
+        };
+
+        let mut readings = vec![self.read_code(frame, primary, stamper_type)?];
+        for config in others {
+            let reading = if stamper_type == StamperType::Auto {
+                let detection =
+                    self.detector
+                        .lock()
+                        .unwrap()
+                        .read_locked(frame, &self.clock, config);
+                self.log_detector_errors(detection)
+            } else {
+                self.reader
+                    .lock()
+                    .unwrap()
+                    .read_with_precision(frame, &self.clock, config)?
+            };
+            readings.push(reading);
+        }
+
+        Ok(readings)
+    }
+
+    /// Try the other orientations on a frame the current one fails on,
+    /// switching to the first one where a copy of the code decodes
+    fn probe_orientation(
+        &self,
+        frame: &VideoFrameRef<&BufferRef>,
+        stamper_type: StamperType,
+    ) -> Result<Option<CopyReadings>, FlowError> {
+        let candidates: Vec<_> = self.orientation.lock().unwrap().candidates().collect();
+
+        for orientation in candidates {
+            let configs = self.reader_configs(frame, orientation)?;
+            let readings = self.read_copies(frame, &configs, stamper_type)?;
+            if readings.iter().any(Option::is_some) {
+                info!(
+                    CAT,
+                    imp: self,
+                    "Detected orientation {:?}",
+                    orientation
+                );
+                self.orientation.lock().unwrap().detected(orientation);
+                self.locator.lock().unwrap().reset();
+
+                let structure = gst::Structure::builder("tslatency-orientation-detected")
+                    .field("orientation", orientation)
+                    .build();
+                self.post_element_message(structure);
+
+                return Ok(Some((configs, readings)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Log the readers that failed on a frame in `auto` mode, they only
+    /// count as misses, and return the reading
+    fn log_detector_errors(&self, detection: Detection) -> Option<Reading> {
+        for (stamper_type, err) in detection.errors {
+            debug!(
+                CAT,
+                imp: self,
+                "Stamper type {:?} could not read the frame: {:?}",
+                stamper_type,
+                err
+            );
+        }
+        detection.reading
+    }
+
+    fn post_detector_event(&self, event: DetectorEvent) {
+        let structure = match event {
+            DetectorEvent::Locked(stamper_type) => {
+                info!(
+                    CAT,
+                    imp: self,
+                    "Detected stamper type {:?}",
+                    stamper_type
+                );
+                gst::Structure::builder("tslatency-codec-detected")
+                    .field("stamper-type", stamper_type)
+                    .build()
+            }
+            DetectorEvent::Lost(stamper_type) => {
+                warning!(
+                    CAT,
+                    imp: self,
+                    "Lost stamper type {:?}, probing again",
+                    stamper_type
+                );
+                gst::Structure::builder("tslatency-codec-lost")
+                    .field("stamper-type", stamper_type)
+                    .build()
+            }
+        };
+
+        self.post_element_message(structure);
+    }
+
+    fn post_locator_event(&self, event: LocatorEvent) {
+        let structure = match event {
+            LocatorEvent::Located { x, y } => {
+                info!(CAT, imp: self, "Located time code at {},{}", x, y);
+                gst::Structure::builder("tslatency-code-located")
+                    .field("x", x)
+                    .field("y", y)
+                    .build()
+            }
+            LocatorEvent::Lost { x, y } => {
+                warning!(
+                    CAT,
+                    imp: self,
+                    "Lost time code at {},{}, searching again",
+                    x,
+                    y
+                );
+                gst::Structure::builder("tslatency-code-lost")
+                    .field("x", x)
+                    .field("y", y)
+                    .build()
+            }
+        };
+
+        self.post_element_message(structure);
+    }
+
+    /// Compare the stamped content hash with the received picture
+    fn check_integrity(
+        &self,
+        frame: &VideoFrameRef<&BufferRef>,
+        configs: &[ReaderConfig],
+        stamped_usecs: Option<u64>,
+        threshold: f64,
+    ) -> Result<(), FlowError> {
+        let Some(config) = configs.first() else {
+            return Ok(());
+        };
+        let state = self.state.lock().unwrap();
+        let hash_region = if config.orientation.transposes() {
+            state.transposed_hash_region
+        } else {
+            state.hash_region
+        };
+        drop(state);
+        let Some(hash_region) = hash_region else {
+            return Ok(());
+        };
+        let hash_region = Rect::new(
+            hash_region.x,
+            hash_region.y,
+            hash_region.width,
+            hash_region.height,
+        );
+
+        let Some(expected) = integrity::read_hash(
+            frame,
+            hash_region,
+            config.scale,
+            config.orientation,
+            config.fill.levels(),
+        )?
+        else {
+            info!(CAT, imp: self, "Failed to read content hash from frame");
+            return Ok(());
+        };
+        let mut exclude: Vec<_> = configs
+            .iter()
+            .map(|config| Rect::new(config.x, config.y, config.width, config.height))
+            .collect();
+        exclude.push(hash_region);
+        let actual = integrity::thumbnail_hash(frame, &exclude, config.scale, config.orientation)?;
+        let score = integrity::similarity(expected, actual);
+        info!(CAT, imp: self, "Integrity score {:.3}", score);
+
+        let mut state = self.state.lock().unwrap();
+        state.stats.integrity_checked += 1;
+        state.stats.integrity_score_sum += score;
+        if score >= threshold {
+            return Ok(());
+        }
+        state.stats.integrity_mismatches += 1;
+        drop(state);
+
+        warning!(
+            CAT,
+            imp: self,
+            "Frame content does not match its stamped hash (score {:.3})",
+            score
+        );
+        let mut structure = gst::Structure::builder("tslatency-integrity-mismatch")
+            .field("score", score)
+            .build();
+        if let Some(stamped_usecs) = stamped_usecs {
+            structure.set("timestamp", stamped_usecs);
+        }
+        self.post_element_message(structure);
+
+        Ok(())
+    }
+
+    /// Append a frame to the measurement log, if enabled
+    fn log_frame(
+        &self,
+        buffer: &BufferRef,
+        stamped_usecs: Option<u64>,
+        curr_usecs: u64,
+        latency_usecs: Option<i64>,
+    ) -> Result<(), FlowError> {
+        let mut state = self.state.lock().unwrap();
+        let State { log, stats, .. } = &mut *state;
+        let Some(log) = log else {
+            return Ok(());
+        };
+
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        let res = writeln!(
+            log,
+            "{},{},{},{},{}",
+            stats.frames,
+            optional(buffer.pts().map(|pts| pts.nseconds().to_string())),
+            optional(stamped_usecs.map(|value| value.to_string())),
+            curr_usecs,
+            optional(latency_usecs.map(|value| value.to_string())),
+        );
+
+        res.map_err(|err| {
+            gst::element_imp_error!(
+                self,
+                gst::ResourceError::Write,
+                ["Could not write measurement log: {}", err]
+            );
+            FlowError::Error
+        })
+    }
+
+    /// Count the decoded copies of the code
+    fn track_copies(&self, readings: &[Option<Reading>]) {
+        let mut state = self.state.lock().unwrap();
+        let copy_decoded = &mut state.stats.copy_decoded;
+
+        copy_decoded.resize(copy_decoded.len().max(readings.len()), 0);
+        for (decoded, reading) in copy_decoded.iter_mut().zip(readings) {
+            *decoded += reading.is_some() as u64;
+        }
+    }
+
+    /// Update stall and freeze tracking for an incoming frame
+    fn track_frame(&self, stamped_usecs: Option<u64>, curr_usecs: u64) {
+        let mut messages = vec![];
+        let mut state = self.state.lock().unwrap();
+
+        state.stats.frames += 1;
+
+        if state.stalled {
+            state.stalled = false;
+            let stall_usecs = curr_usecs - state.last_buffer_usecs.unwrap_or(curr_usecs);
+            info!(
+                CAT,
+                imp: self,
+                "Stall ended after {} usecs",
+                stall_usecs
+            );
+            messages.push(
+                gst::Structure::builder("tslatency-stall-end")
+                    .field("duration", ClockTime::from_useconds(stall_usecs))
+                    .build(),
+            );
+        }
+        state.last_buffer_usecs = Some(curr_usecs);
+
+        if let Some(stamped_usecs) = stamped_usecs {
+            state.stats.decoded += 1;
+
+            // A stamp far behind the latest one starts a new stream, it
+            // ends any freeze instead of extending it
+            let max_backward_usecs = DISCONTINUITY_FRAMES
+                * state
+                    .frame_interval_usecs
+                    .unwrap_or(DEFAULT_FRAME_INTERVAL_USECS);
+            let jumped_back = state
+                .last_stamp
+                .is_some_and(|last| last.saturating_sub(stamped_usecs) > max_backward_usecs);
+            if jumped_back {
+                info!(
+                    CAT,
+                    imp: self,
+                    "Timestamp jumped back from {} to {}, treating it as a discontinuity",
+                    state.last_stamp.unwrap(),
+                    stamped_usecs
+                );
+            }
+
+            let advanced = jumped_back || state.last_stamp.is_none_or(|last| stamped_usecs > last);
+            if advanced {
+                if let Some(freeze) = state.freeze.take() {
+                    let freeze_usecs = curr_usecs - freeze.start_usecs;
+                    info!(
+                        CAT,
+                        imp: self,
+                        "Freeze ended after {} usecs ({} repeated frames)",
+                        freeze_usecs,
+                        freeze.frames
+                    );
+                    messages.push(
+                        gst::Structure::builder("tslatency-freeze-end")
+                            .field("duration", ClockTime::from_useconds(freeze_usecs))
+                            .field("frames", freeze.frames)
+                            .build(),
+                    );
+                }
+                state.last_stamp = Some(stamped_usecs);
+                state.last_advance_usecs = Some(curr_usecs);
+            } else if let Some(freeze) = &mut state.freeze {
+                freeze.frames += 1;
+            } else {
+                warning!(
+                    CAT,
+                    imp: self,
+                    "Freeze detected, timestamp {} repeated",
+                    stamped_usecs
+                );
+                state.freeze = Some(Freeze {
+                    start_usecs: state.last_advance_usecs.unwrap_or(curr_usecs),
+                    frames: 1,
+                });
+                state.stats.freezes += 1;
+                messages.push(
+                    gst::Structure::builder("tslatency-freeze-start")
+                        .field("timestamp", stamped_usecs)
+                        .build(),
+                );
+            }
+        }
+
+        drop(state);
+        for structure in messages {
+            self.post_element_message(structure);
+        }
+    }
+
+    /// Map the decoded timestamp into the local running time with a
+    /// constant offset and schedule it as the buffer's new PTS/DTS.
+    ///
+    /// Frames that fail to decode keep their original timestamps.
+    fn retimestamp(&self, buffer: &BufferRef, stamped_usecs: u64) {
+        let segment = self.obj().segment();
+        let Some(segment) = segment.downcast_ref::<ClockTime>() else {
+            return;
+        };
+        let Some(running_time) = segment.to_running_time(buffer.pts()) else {
+            return;
+        };
+        let stamped_nsecs = ClockTime::from_useconds(stamped_usecs).nseconds() as i64;
+
+        let mut state = self.state.lock().unwrap();
+        let offset = *state
+            .retimestamp_offset
+            .get_or_insert(running_time.nseconds() as i64 - stamped_nsecs);
+
+        let Ok(running_time) = u64::try_from(stamped_nsecs + offset) else {
+            return;
+        };
+        state.pending_pts =
+            segment.position_from_running_time(ClockTime::from_nseconds(running_time));
+    }
+
+    /// Called periodically by the watchdog to detect missing buffers
+    fn check_stall(&self) {
+        if self.obj().current_state() != gst::State::Playing {
+            return;
+        }
+
+        let stall_timeout_usecs = self.props.lock().unwrap().stall_timeout_ms * 1000;
+        let curr_usecs = self.clock.time().unwrap().useconds();
+
+        let mut state = self.state.lock().unwrap();
+        let Some(last_buffer_usecs) = state.last_buffer_usecs else {
+            return;
+        };
+        let idle_usecs = curr_usecs.saturating_sub(last_buffer_usecs);
+        if state.stalled || idle_usecs < stall_timeout_usecs {
+            return;
+        }
+        state.stalled = true;
+        state.stats.stalls += 1;
+        drop(state);
+
+        warning!(
+            CAT,
+            imp: self,
+            "Stall detected, no buffers for {} usecs",
+            idle_usecs
+        );
+        self.post_element_message(
+            gst::Structure::builder("tslatency-stall-start")
+                .field("elapsed", ClockTime::from_useconds(idle_usecs))
+                .build(),
+        );
+    }
+
+    /// Account for the decode result of a frame and send a report to
+    /// the stamper once a window is complete
+    fn send_feedback(&self, reading: Option<&Reading>) {
+        let mut state = self.state.lock().unwrap();
+        if state.feedback_socket.is_none() {
+            return;
+        }
+        let Some(feedback) = state.feedback_window.record(reading) else {
+            return;
+        };
+
+        let socket = state.feedback_socket.as_ref().unwrap();
+        if let Err(err) = socket.send(feedback.to_datagram().as_bytes()) {
+            warning!(CAT, imp: self, "Could not send feedback: {}", err);
+        }
+    }
+
+    fn post_element_message(&self, structure: gst::Structure) {
+        let msg = gst::message::Element::builder(structure)
+            .src(&*self.obj())
+            .build();
+        let _ = self.obj().post_message(msg);
+    }
+}
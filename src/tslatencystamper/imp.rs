@@ -1,23 +1,75 @@
-use crate::stamper::{create_stamper, StamperConfig, StamperType, TimestampStamper};
+use crate::stamper::{
+    adaptive,
+    copies::{self, MAX_COPIES},
+    create_stamper,
+    integrity::{self, Rect},
+    palette, Anchor, BlockAlign, Feedback, Interleave, LayoutController, MarkerLayout, Orientation,
+    Palette, Placement, Region, Scale, StamperConfig, StamperType, TimestampStamper, Tuning,
+};
 use glib::subclass::{prelude::*, types::ObjectSubclass};
 use gst::{
     info,
     subclass::{prelude::*, ElementMetadata},
-    BufferRef, Clock, FlowError, FlowSuccess, PadDirection, PadPresence, PadTemplate, SystemClock,
+    warning, BufferRef, Clock, FlowError, FlowSuccess, PadDirection, PadPresence, PadTemplate,
+    SystemClock,
 };
 use gst_base::subclass::BaseTransformMode;
 use gst_video::{
     prelude::*,
-    subclass::prelude::{BaseTransformImpl, VideoFilterImpl},
-    VideoCapsBuilder, VideoFilter, VideoFormat, VideoFrameRef,
+    subclass::prelude::{BaseTransformImpl, VideoFilterImpl, VideoFilterImplExt},
+    VideoCapsBuilder, VideoFilter, VideoFormat, VideoFrameRef, VideoInfo,
 };
 use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::UdpSocket,
+    sync::Mutex,
+};
 
 const DEFAULT_X: u64 = 0;
 const DEFAULT_Y: u64 = 0;
 const DEFAULT_WIDTH: u64 = 64;
 const DEFAULT_HEIGHT: u64 = 64;
+const DEFAULT_MARGIN: u32 = 0;
+const DEFAULT_WIDTH_PERCENT: f64 = 0.0;
+const DEFAULT_HEIGHT_PERCENT: f64 = 0.0;
+const DEFAULT_COPIES: u32 = 1;
+const DEFAULT_REFERENCE_WIDTH: u32 = 0;
+const DEFAULT_REFERENCE_HEIGHT: u32 = 0;
+const DEFAULT_PARITY_SYMBOLS: u32 = 6;
+const DEFAULT_STRENGTH: u32 = 4;
+const DEFAULT_POST_MESSAGES: bool = false;
+const DEFAULT_CONTENT_HASH: bool = false;
+const DEFAULT_HIGH_COLOR: u32 = palette::DEFAULT_HIGH;
+const DEFAULT_LOW_COLOR: u32 = palette::DEFAULT_LOW;
+const DEFAULT_CELL_SIZE: u32 = 8;
+const DEFAULT_GRID_WIDTH: u32 = 12;
+const DEFAULT_GRID_HEIGHT: u32 = 8;
+const DEFAULT_START_MARKER: u32 = 0xA5A5;
+const DEFAULT_END_MARKER: u32 = 0x5A5A;
+const DEFAULT_BLOCK_SIZE: u32 = 4;
+const DEFAULT_GUARD_PIXELS: u32 = 0;
+const DEFAULT_FEEDBACK_PORT: u32 = 0;
+
+/// Properties the code region is resolved from
+const PLACEMENT_PROPERTIES: &[&str] = &[
+    "x",
+    "y",
+    "width",
+    "height",
+    "anchor",
+    "margin",
+    "width-percent",
+    "height-percent",
+    "block-align",
+    "reference-width",
+    "reference-height",
+    "copies",
+    "copy-anchors",
+    "content-hash",
+    "stamper-type",
+];
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -31,6 +83,7 @@ pub struct TsLatencyStamper {
     props: Mutex<Properties>,
     clock: Clock,
     stamper: Mutex<Box<dyn TimestampStamper>>,
+    state: Mutex<State>,
 }
 
 #[derive(Clone)]
@@ -39,7 +92,54 @@ struct Properties {
     y: u64,
     width: u64,
     height: u64,
+    anchor: Anchor,
+    margin: u32,
+    width_percent: f64,
+    height_percent: f64,
+    block_align: BlockAlign,
+    copies: u32,
+    copy_anchors: Option<String>,
+    reference_width: u32,
+    reference_height: u32,
     stamper_type: StamperType,
+    parity_symbols: u32,
+    interleave: Interleave,
+    strength: u32,
+    high_color: u32,
+    low_color: u32,
+    custom_palette: bool,
+    cell_size: u32,
+    grid_width: u32,
+    grid_height: u32,
+    start_marker: u32,
+    end_marker: u32,
+    block_size: u32,
+    guard_pixels: u32,
+    log_location: Option<String>,
+    post_messages: bool,
+    content_hash: bool,
+    feedback_port: u32,
+}
+
+#[derive(Default)]
+struct State {
+    /// Number of frames stamped since the element started
+    frame_count: u64,
+    /// Stamp log in CSV format: `frame,pts,timestamp`
+    log: Option<BufWriter<File>>,
+    /// Size of the negotiated frames
+    frame_size: Option<(u32, u32)>,
+    /// Code regions resolved from the placement properties, the
+    /// primary one first and then its copies
+    regions: Vec<Region>,
+    /// Region of the content hash next to the primary code region, when
+    /// enabled and it fits the frame
+    hash_region: Option<Region>,
+    /// Socket decode reports of the measure element arrive on, when
+    /// enabled
+    feedback_socket: Option<UdpSocket>,
+    /// Layout of the adaptive code, following the decode reports
+    layout: LayoutController,
 }
 
 impl Default for TsLatencyStamper {
@@ -49,6 +149,7 @@ impl Default for TsLatencyStamper {
             props: Mutex::new(Properties::default()),
             clock: SystemClock::obtain(),
             stamper: Mutex::new(create_stamper(stamper_type)),
+            state: Mutex::new(State::default()),
         }
     }
 }
@@ -60,7 +161,59 @@ impl Default for Properties {
             y: DEFAULT_Y,
             width: DEFAULT_WIDTH,
             height: DEFAULT_HEIGHT,
+            anchor: Anchor::default(),
+            margin: DEFAULT_MARGIN,
+            width_percent: DEFAULT_WIDTH_PERCENT,
+            height_percent: DEFAULT_HEIGHT_PERCENT,
+            block_align: BlockAlign::default(),
+            copies: DEFAULT_COPIES,
+            copy_anchors: None,
+            reference_width: DEFAULT_REFERENCE_WIDTH,
+            reference_height: DEFAULT_REFERENCE_HEIGHT,
             stamper_type: StamperType::default(),
+            parity_symbols: DEFAULT_PARITY_SYMBOLS,
+            interleave: Interleave::default(),
+            strength: DEFAULT_STRENGTH,
+            high_color: DEFAULT_HIGH_COLOR,
+            low_color: DEFAULT_LOW_COLOR,
+            custom_palette: false,
+            cell_size: DEFAULT_CELL_SIZE,
+            grid_width: DEFAULT_GRID_WIDTH,
+            grid_height: DEFAULT_GRID_HEIGHT,
+            start_marker: DEFAULT_START_MARKER,
+            end_marker: DEFAULT_END_MARKER,
+            block_size: DEFAULT_BLOCK_SIZE,
+            guard_pixels: DEFAULT_GUARD_PIXELS,
+            log_location: None,
+            post_messages: DEFAULT_POST_MESSAGES,
+            content_hash: DEFAULT_CONTENT_HASH,
+            feedback_port: DEFAULT_FEEDBACK_PORT,
+        }
+    }
+}
+
+impl Properties {
+    fn tuning(&self) -> Tuning {
+        Tuning {
+            layout: MarkerLayout {
+                cell_size: self.cell_size as usize,
+                grid_width: self.grid_width as usize,
+                grid_height: self.grid_height as usize,
+                start_marker: self.start_marker as u16,
+                end_marker: self.end_marker as u16,
+            },
+            block_size: self.block_size as usize,
+            guard_pixels: self.guard_pixels as usize,
+            ..Tuning::default()
+        }
+        .aligned(self.block_align)
+    }
+
+    fn palette(&self) -> Palette {
+        Palette {
+            high: self.high_color,
+            low: self.low_color,
+            custom: self.custom_palette,
         }
     }
 }
@@ -100,12 +253,184 @@ impl ObjectImpl for TsLatencyStamper {
                     .default_value(DEFAULT_HEIGHT)
                     .mutable_playing()
                     .build(),
+                glib::ParamSpecEnum::builder::<Anchor>("anchor")
+                    .nick("Anchor")
+                    .blurb("Frame corner, edge or centre the time code is placed at, x and y are measured from it")
+                    .default_value(Anchor::default())
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("margin")
+                    .nick("Margin")
+                    .blurb("Distance of the time code from the anchored frame edges")
+                    .default_value(DEFAULT_MARGIN)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("width-percent")
+                    .nick("Width Percent")
+                    .blurb("Time code width in percent of the frame width, overrides width (0 = disabled)")
+                    .minimum(0.0)
+                    .maximum(100.0)
+                    .default_value(DEFAULT_WIDTH_PERCENT)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("height-percent")
+                    .nick("Height Percent")
+                    .blurb("Time code height in percent of the frame height, overrides height (0 = disabled)")
+                    .minimum(0.0)
+                    .maximum(100.0)
+                    .default_value(DEFAULT_HEIGHT_PERCENT)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder::<BlockAlign>("block-align")
+                    .nick("Block Align")
+                    .blurb("Grid of encoder blocks or chroma samples the time code region and cells are snapped to, must match on both ends")
+                    .default_value(BlockAlign::default())
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("copies")
+                    .nick("Copies")
+                    .blurb("Number of locations the time code is stamped at, the extra copies go to other anchors")
+                    .minimum(1)
+                    .maximum(MAX_COPIES)
+                    .default_value(DEFAULT_COPIES)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecString::builder("copy-anchors")
+                    .nick("Copy Anchors")
+                    .blurb("Comma-separated anchors of the extra copies, e.g. \"top-right,bottom-left\", the remaining copies are placed automatically")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("reference-width")
+                    .nick("Reference Width")
+                    .blurb("Frame width the time code geometry is given for, scaled to the actual frame width (0 = disabled)")
+                    .default_value(DEFAULT_REFERENCE_WIDTH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("reference-height")
+                    .nick("Reference Height")
+                    .blurb("Frame height the time code geometry is given for, scaled to the actual frame height (0 = disabled)")
+                    .default_value(DEFAULT_REFERENCE_HEIGHT)
+                    .mutable_playing()
+                    .build(),
                 glib::ParamSpecEnum::builder::<StamperType>("stamper-type")
                     .nick("Stamper Type")
                     .blurb("Type of timestamp stamper to use")
                     .default_value(StamperType::default())
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecUInt::builder("parity-symbols")
+                    .nick("Parity Symbols")
+                    .blurb("Number of Reed-Solomon parity symbols, corrects up to half as many byte errors")
+                    .minimum(2)
+                    .maximum(64)
+                    .default_value(DEFAULT_PARITY_SYMBOLS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder::<Interleave>("interleave")
+                    .nick("Interleave")
+                    .blurb("Placement of code bits on the grid for error correcting codecs, must match on both ends")
+                    .default_value(Interleave::default())
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("strength")
+                    .nick("Strength")
+                    .blurb("Amplitude of the watermark stamper in luma levels, higher is more robust and more visible")
+                    .minimum(1)
+                    .maximum(64)
+                    .default_value(DEFAULT_STRENGTH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("high-color")
+                    .nick("High Color")
+                    .blurb("Color of the cells carrying one bits as 0xRRGGBB, resolved in the negotiated colorimetry, must match on both ends")
+                    .maximum(0xFFFFFF)
+                    .default_value(DEFAULT_HIGH_COLOR)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("low-color")
+                    .nick("Low Color")
+                    .blurb("Color of the cells carrying zero bits as 0xRRGGBB, resolved in the negotiated colorimetry, must match on both ends")
+                    .maximum(0xFFFFFF)
+                    .default_value(DEFAULT_LOW_COLOR)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("cell-size")
+                    .nick("Cell Size")
+                    .blurb("Side of the optimized stamper's cells in pixels, must match on both ends")
+                    .minimum(1)
+                    .maximum(64)
+                    .default_value(DEFAULT_CELL_SIZE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("grid-width")
+                    .nick("Grid Width")
+                    .blurb("Cells per row of the optimized stamper's grid, must match on both ends")
+                    .minimum(1)
+                    .maximum(96)
+                    .default_value(DEFAULT_GRID_WIDTH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("grid-height")
+                    .nick("Grid Height")
+                    .blurb("Rows of the optimized stamper's grid, must match on both ends")
+                    .minimum(1)
+                    .maximum(96)
+                    .default_value(DEFAULT_GRID_HEIGHT)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("start-marker")
+                    .nick("Start Marker")
+                    .blurb("16-bit pattern opening the optimized stamper's code, must match on both ends")
+                    .maximum(0xFFFF)
+                    .default_value(DEFAULT_START_MARKER)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("end-marker")
+                    .nick("End Marker")
+                    .blurb("16-bit pattern closing the optimized stamper's code, must match on both ends")
+                    .maximum(0xFFFF)
+                    .default_value(DEFAULT_END_MARKER)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("block-size")
+                    .nick("Block Size")
+                    .blurb("Side of the fast-robust stamper's cells in pixels, must match on both ends")
+                    .minimum(1)
+                    .maximum(64)
+                    .default_value(DEFAULT_BLOCK_SIZE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("guard-pixels")
+                    .nick("Guard Pixels")
+                    .blurb("Pixels left unstamped between the fast-robust stamper's cells, must match on both ends")
+                    .maximum(32)
+                    .default_value(DEFAULT_GUARD_PIXELS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecString::builder("log-location")
+                    .nick("Log Location")
+                    .blurb("File to log stamped values with their PTS and frame number as CSV")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("post-messages")
+                    .nick("Post Messages")
+                    .blurb("Post an element message for every stamped frame")
+                    .default_value(DEFAULT_POST_MESSAGES)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("content-hash")
+                    .nick("Content Hash")
+                    .blurb("Stamp a hash of the picture below the time code for integrity checks")
+                    .default_value(DEFAULT_CONTENT_HASH)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("feedback-port")
+                    .nick("Feedback Port")
+                    .blurb("UDP port decode reports of tslatencymeasure are received on to size the adaptive code (0 = disabled)")
+                    .maximum(u16::MAX as u32)
+                    .default_value(DEFAULT_FEEDBACK_PORT)
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -162,9 +487,233 @@ impl ObjectImpl for TsLatencyStamper {
                 );
                 props.height = height;
             }
+            "anchor" => {
+                let mut props = self.props.lock().unwrap();
+                let anchor = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing anchor from {:?} to {:?}",
+                    props.anchor,
+                    anchor
+                );
+                props.anchor = anchor;
+            }
+            "margin" => {
+                let mut props = self.props.lock().unwrap();
+                let margin = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing margin from {} to {}",
+                    props.margin,
+                    margin
+                );
+                props.margin = margin;
+            }
+            "width-percent" => {
+                let mut props = self.props.lock().unwrap();
+                let width_percent = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing width percent from {} to {}",
+                    props.width_percent,
+                    width_percent
+                );
+                props.width_percent = width_percent;
+            }
+            "height-percent" => {
+                let mut props = self.props.lock().unwrap();
+                let height_percent = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing height percent from {} to {}",
+                    props.height_percent,
+                    height_percent
+                );
+                props.height_percent = height_percent;
+            }
+            "block-align" => {
+                let mut props = self.props.lock().unwrap();
+                let block_align = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing block alignment from {:?} to {:?}",
+                    props.block_align,
+                    block_align
+                );
+                props.block_align = block_align;
+            }
+            "copies" => {
+                let mut props = self.props.lock().unwrap();
+                let copies = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing copies from {} to {}",
+                    props.copies,
+                    copies
+                );
+                props.copies = copies;
+            }
+            "copy-anchors" => {
+                let mut props = self.props.lock().unwrap();
+                let copy_anchors: Option<String> = value.get().expect("type checked upstream");
+                if let Err(err) = copies::parse_anchors(copy_anchors.as_deref().unwrap_or_default())
+                {
+                    warning!(CAT, imp: self, "Ignoring copy anchors: {}", err);
+                    return;
+                }
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing copy anchors from {:?} to {:?}",
+                    props.copy_anchors,
+                    copy_anchors
+                );
+                props.copy_anchors = copy_anchors;
+            }
+            "reference-width" => {
+                let mut props = self.props.lock().unwrap();
+                let reference_width = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing reference width from {} to {}",
+                    props.reference_width,
+                    reference_width
+                );
+                props.reference_width = reference_width;
+            }
+            "reference-height" => {
+                let mut props = self.props.lock().unwrap();
+                let reference_height = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing reference height from {} to {}",
+                    props.reference_height,
+                    reference_height
+                );
+                props.reference_height = reference_height;
+            }
+            "parity-symbols" => {
+                let mut props = self.props.lock().unwrap();
+                let parity_symbols = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing parity symbols from {} to {}",
+                    props.parity_symbols,
+                    parity_symbols
+                );
+                props.parity_symbols = parity_symbols;
+                self.check_tuning(&props, &self.state.lock().unwrap());
+            }
+            "interleave" => {
+                let mut props = self.props.lock().unwrap();
+                let interleave = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing interleave from {:?} to {:?}",
+                    props.interleave,
+                    interleave
+                );
+                props.interleave = interleave;
+                self.check_interleave(&props);
+            }
+            "strength" => {
+                let mut props = self.props.lock().unwrap();
+                let strength = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing strength from {} to {}",
+                    props.strength,
+                    strength
+                );
+                props.strength = strength;
+            }
+            "high-color" => {
+                let mut props = self.props.lock().unwrap();
+                let high_color = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing high color from {:#08x} to {:#08x}",
+                    props.high_color,
+                    high_color
+                );
+                props.high_color = high_color;
+                props.custom_palette = true;
+                self.check_palette(&props);
+            }
+            "low-color" => {
+                let mut props = self.props.lock().unwrap();
+                let low_color = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing low color from {:#08x} to {:#08x}",
+                    props.low_color,
+                    low_color
+                );
+                props.low_color = low_color;
+                props.custom_palette = true;
+                self.check_palette(&props);
+            }
+            "cell-size" => {
+                let cell_size = value.get().expect("type checked upstream");
+                self.update_tuning("cell size", |props| &mut props.cell_size, cell_size);
+            }
+            "grid-width" => {
+                let grid_width = value.get().expect("type checked upstream");
+                self.update_tuning("grid width", |props| &mut props.grid_width, grid_width);
+            }
+            "grid-height" => {
+                let grid_height = value.get().expect("type checked upstream");
+                self.update_tuning("grid height", |props| &mut props.grid_height, grid_height);
+            }
+            "start-marker" => {
+                let start_marker = value.get().expect("type checked upstream");
+                self.update_tuning(
+                    "start marker",
+                    |props| &mut props.start_marker,
+                    start_marker,
+                );
+            }
+            "end-marker" => {
+                let end_marker = value.get().expect("type checked upstream");
+                self.update_tuning("end marker", |props| &mut props.end_marker, end_marker);
+            }
+            "block-size" => {
+                let block_size = value.get().expect("type checked upstream");
+                self.update_tuning("block size", |props| &mut props.block_size, block_size);
+            }
+            "guard-pixels" => {
+                let guard_pixels = value.get().expect("type checked upstream");
+                self.update_tuning(
+                    "guard pixels",
+                    |props| &mut props.guard_pixels,
+                    guard_pixels,
+                );
+            }
             "stamper-type" => {
                 let mut props = self.props.lock().unwrap();
-                let stamper_type = value.get().expect("type checked upstream");
+                let mut stamper_type = value.get().expect("type checked upstream");
+                if stamper_type == StamperType::Auto {
+                    warning!(
+                        CAT,
+                        imp: self,
+                        "Stamper type auto is only supported by the reader, using {:?}",
+                        StamperType::default()
+                    );
+                    stamper_type = StamperType::default();
+                }
                 info!(
                     CAT,
                     imp: self,
@@ -173,9 +722,63 @@ impl ObjectImpl for TsLatencyStamper {
                 );
                 props.stamper_type = stamper_type;
                 *self.stamper.lock().unwrap() = create_stamper(stamper_type);
+                self.check_tuning(&props, &self.state.lock().unwrap());
+                self.check_interleave(&props);
+            }
+            "log-location" => {
+                let mut props = self.props.lock().unwrap();
+                let log_location = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing log location from {:?} to {:?}",
+                    props.log_location,
+                    log_location
+                );
+                props.log_location = log_location;
+            }
+            "post-messages" => {
+                let mut props = self.props.lock().unwrap();
+                let post_messages = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing post-messages from {} to {}",
+                    props.post_messages,
+                    post_messages
+                );
+                props.post_messages = post_messages;
+            }
+            "content-hash" => {
+                let mut props = self.props.lock().unwrap();
+                let content_hash = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing content-hash from {} to {}",
+                    props.content_hash,
+                    content_hash
+                );
+                props.content_hash = content_hash;
+            }
+            "feedback-port" => {
+                let mut props = self.props.lock().unwrap();
+                let feedback_port = value.get().expect("type checked upstream");
+                info!(
+                    CAT,
+                    imp: self,
+                    "Changing feedback port from {} to {}",
+                    props.feedback_port,
+                    feedback_port
+                );
+                props.feedback_port = feedback_port;
             }
             _ => unimplemented!(),
         }
+
+        if PLACEMENT_PROPERTIES.contains(&pspec.name()) {
+            self.resolve_region();
+        }
     }
 
     fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
@@ -196,10 +799,110 @@ impl ObjectImpl for TsLatencyStamper {
                 let props = self.props.lock().unwrap();
                 props.height.to_value()
             }
+            "anchor" => {
+                let props = self.props.lock().unwrap();
+                props.anchor.to_value()
+            }
+            "margin" => {
+                let props = self.props.lock().unwrap();
+                props.margin.to_value()
+            }
+            "width-percent" => {
+                let props = self.props.lock().unwrap();
+                props.width_percent.to_value()
+            }
+            "height-percent" => {
+                let props = self.props.lock().unwrap();
+                props.height_percent.to_value()
+            }
+            "block-align" => {
+                let props = self.props.lock().unwrap();
+                props.block_align.to_value()
+            }
+            "copies" => {
+                let props = self.props.lock().unwrap();
+                props.copies.to_value()
+            }
+            "copy-anchors" => {
+                let props = self.props.lock().unwrap();
+                props.copy_anchors.to_value()
+            }
+            "reference-width" => {
+                let props = self.props.lock().unwrap();
+                props.reference_width.to_value()
+            }
+            "reference-height" => {
+                let props = self.props.lock().unwrap();
+                props.reference_height.to_value()
+            }
             "stamper-type" => {
                 let props = self.props.lock().unwrap();
                 props.stamper_type.to_value()
             }
+            "parity-symbols" => {
+                let props = self.props.lock().unwrap();
+                props.parity_symbols.to_value()
+            }
+            "interleave" => {
+                let props = self.props.lock().unwrap();
+                props.interleave.to_value()
+            }
+            "strength" => {
+                let props = self.props.lock().unwrap();
+                props.strength.to_value()
+            }
+            "high-color" => {
+                let props = self.props.lock().unwrap();
+                props.high_color.to_value()
+            }
+            "low-color" => {
+                let props = self.props.lock().unwrap();
+                props.low_color.to_value()
+            }
+            "cell-size" => {
+                let props = self.props.lock().unwrap();
+                props.cell_size.to_value()
+            }
+            "grid-width" => {
+                let props = self.props.lock().unwrap();
+                props.grid_width.to_value()
+            }
+            "grid-height" => {
+                let props = self.props.lock().unwrap();
+                props.grid_height.to_value()
+            }
+            "start-marker" => {
+                let props = self.props.lock().unwrap();
+                props.start_marker.to_value()
+            }
+            "end-marker" => {
+                let props = self.props.lock().unwrap();
+                props.end_marker.to_value()
+            }
+            "block-size" => {
+                let props = self.props.lock().unwrap();
+                props.block_size.to_value()
+            }
+            "guard-pixels" => {
+                let props = self.props.lock().unwrap();
+                props.guard_pixels.to_value()
+            }
+            "log-location" => {
+                let props = self.props.lock().unwrap();
+                props.log_location.to_value()
+            }
+            "post-messages" => {
+                let props = self.props.lock().unwrap();
+                props.post_messages.to_value()
+            }
+            "content-hash" => {
+                let props = self.props.lock().unwrap();
+                props.content_hash.to_value()
+            }
+            "feedback-port" => {
+                let props = self.props.lock().unwrap();
+                props.feedback_port.to_value()
+            }
             _ => unimplemented!(),
         }
     }
@@ -230,7 +933,7 @@ impl ElementImpl for TsLatencyStamper {
                 .format_list([
                     Rgbx, Bgrx, Xrgb, Xbgr, Rgba, Bgra, Gbra, Argb, Abgr, Rgb, Bgr, Gbr, I420,
                     Yv12, Yvyu, Vyuy, Uyvy, Yuy2, Ayuv, Y41b, Y42b, Nv12, Nv16, Nv21, Nv24, Nv61,
-                    A420, Yuv9, Yvu9, Iyu1,
+                    A420, Yuv9, Yvu9, Iyu1, Y444, V308, Iyu2,
                 ])
                 .build();
 
@@ -251,25 +954,429 @@ impl BaseTransformImpl for TsLatencyStamper {
     const MODE: BaseTransformMode = BaseTransformMode::AlwaysInPlace;
     const PASSTHROUGH_ON_SAME_CAPS: bool = false;
     const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        let props = self.props.lock().unwrap();
+        let log_location = props.log_location.clone();
+        let feedback_port = props.feedback_port;
+        drop(props);
+
+        let log = match log_location {
+            Some(path) => {
+                let mut log = File::create(&path).map(BufWriter::new).map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::OpenWrite,
+                        ["Could not open stamp log {}: {}", path, err]
+                    )
+                })?;
+                writeln!(log, "frame,pts,timestamp").map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::Write,
+                        ["Could not write stamp log {}: {}", path, err]
+                    )
+                })?;
+                Some(log)
+            }
+            None => None,
+        };
+
+        let feedback_socket = if feedback_port > 0 {
+            let socket = UdpSocket::bind(("0.0.0.0", feedback_port as u16))
+                .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+                .map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::OpenRead,
+                        [
+                            "Could not listen for feedback on port {}: {}",
+                            feedback_port,
+                            err
+                        ]
+                    )
+                })?;
+            Some(socket)
+        } else {
+            None
+        };
+
+        *self.state.lock().unwrap() = State {
+            log,
+            feedback_socket,
+            ..State::default()
+        };
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(mut log) = state.log.take() {
+            log.flush().map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Write,
+                    ["Could not flush stamp log: {}", err]
+                )
+            })?;
+        }
+
+        Ok(())
+    }
 }
 
 impl VideoFilterImpl for TsLatencyStamper {
+    fn set_info(
+        &self,
+        incaps: &gst::Caps,
+        in_info: &VideoInfo,
+        outcaps: &gst::Caps,
+        out_info: &VideoInfo,
+    ) -> Result<(), gst::LoggableError> {
+        self.state.lock().unwrap().frame_size = Some((in_info.width(), in_info.height()));
+        self.resolve_region();
+
+        self.parent_set_info(incaps, in_info, outcaps, out_info)
+    }
+
     fn transform_frame_ip(
         &self,
         frame: &mut VideoFrameRef<&mut BufferRef>,
     ) -> Result<FlowSuccess, FlowError> {
+        let regions = self.state.lock().unwrap().regions.clone();
+        let (region, copy_regions) = regions.split_first().ok_or(FlowError::NotNegotiated)?;
+        let adaptive_layout = self.receive_feedback(region);
+
         let props = self.props.lock().unwrap();
         let config = StamperConfig {
+            x: region.x,
+            y: region.y,
+            width: region.width,
+            height: region.height,
+            parity_symbols: props.parity_symbols,
+            interleave: props.interleave,
+            strength: props.strength,
+            scale: Scale::new(
+                frame.width(),
+                frame.height(),
+                props.reference_width,
+                props.reference_height,
+            ),
+            fill: props.palette().resolve(frame.info()),
+            tuning: Tuning {
+                adaptive_layout,
+                ..props.tuning()
+            },
+        };
+        // Gray palettes are written by the codecs as they are
+        let tint = !props.palette().is_gray() && props.stamper_type.writes_luma_levels();
+        let post_messages = props.post_messages;
+        let content_hash = props.content_hash;
+        drop(props);
+
+        // Hash the picture before anything is stamped on it
+        let hash_region = self.state.lock().unwrap().hash_region;
+        let hash = if let Some(hash_region) = hash_region.filter(|_| content_hash) {
+            let hash_region = Rect::new(
+                hash_region.x,
+                hash_region.y,
+                hash_region.width,
+                hash_region.height,
+            );
+            let mut exclude: Vec<_> = regions
+                .iter()
+                .map(|region| Rect::new(region.x, region.y, region.width, region.height))
+                .collect();
+            exclude.push(hash_region);
+            let hash =
+                integrity::thumbnail_hash(frame, &exclude, config.scale, Orientation::Identity)?;
+            Some((hash_region, hash))
+        } else {
+            None
+        };
+
+        let mut stamper = self.stamper.lock().unwrap();
+        let stamped_usecs = stamper.stamp(frame, &self.clock, &config)?;
+        drop(stamper);
+
+        // Copies carry the pixels of the primary code, hence its payload
+        let code_rect = config.scale.rect(
+            region.x as usize,
+            region.y as usize,
+            region.width as usize,
+            region.height as usize,
+        );
+        for copy in copy_regions {
+            let (x, y) = config.scale.point(copy.x as usize, copy.y as usize);
+            copies::copy_rect(frame, code_rect, (x, y))?;
+        }
+
+        if let Some((hash_region, hash)) = hash {
+            integrity::stamp_hash(frame, hash_region, hash, config.scale, config.fill.levels())?;
+        }
+
+        if tint {
+            let rects = regions
+                .iter()
+                .map(|region| Rect::new(region.x, region.y, region.width, region.height))
+                .chain(hash.map(|(hash_region, _)| hash_region));
+            for rect in rects {
+                let rect = config.scale.rect(rect.x, rect.y, rect.width, rect.height);
+                config.fill.tint(frame, rect)?;
+            }
+        }
+
+        let pts = frame.buffer().pts();
+        let mut state = self.state.lock().unwrap();
+        let frame_count = state.frame_count;
+        state.frame_count += 1;
+
+        if let Some(log) = &mut state.log {
+            let pts = pts
+                .map(|pts| pts.nseconds().to_string())
+                .unwrap_or_default();
+            if let Err(err) = writeln!(log, "{},{},{}", frame_count, pts, stamped_usecs) {
+                gst::element_imp_error!(
+                    self,
+                    gst::ResourceError::Write,
+                    ["Could not write stamp log: {}", err]
+                );
+                return Err(FlowError::Error);
+            }
+        }
+        drop(state);
+
+        if post_messages {
+            let structure = gst::Structure::builder("tslatency-stamp")
+                .field("frame", frame_count)
+                .field("pts", pts)
+                .field("timestamp", stamped_usecs)
+                .build();
+            let msg = gst::message::Element::builder(structure)
+                .src(&*self.obj())
+                .build();
+            let _ = self.obj().post_message(msg);
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl TsLatencyStamper {
+    /// Set a tuning property unless the resulting tuning is inconsistent
+    fn update_tuning<T: Copy + std::fmt::Debug>(
+        &self,
+        name: &str,
+        field: impl Fn(&mut Properties) -> &mut T,
+        value: T,
+    ) {
+        let mut props = self.props.lock().unwrap();
+        let mut updated = props.clone();
+        *field(&mut updated) = value;
+        if let Err(err) = updated.tuning().validate() {
+            warning!(CAT, imp: self, "Ignoring {} {:?}: {}", name, value, err);
+            return;
+        }
+        info!(
+            CAT,
+            imp: self,
+            "Changing {} from {:?} to {:?}",
+            name,
+            field(&mut props),
+            value
+        );
+        *props = updated;
+        self.check_tuning(&props, &self.state.lock().unwrap());
+    }
+
+    /// Drain the decode reports received since the last frame and
+    /// return the layout of the adaptive code
+    fn receive_feedback(&self, region: &Region) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let Some(socket) = &state.feedback_socket else {
+            return state.layout.layout();
+        };
+
+        let mut reports = Vec::new();
+        let mut payload = [0u8; 512];
+        loop {
+            match socket.recv(&mut payload) {
+                Ok(len) => match Feedback::from_datagram(&payload[..len]) {
+                    Some(feedback) => reports.push(feedback),
+                    None => warning!(CAT, imp: self, "Ignoring malformed feedback datagram"),
+                },
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warning!(CAT, imp: self, "Could not receive feedback: {}", err);
+                    break;
+                }
+            }
+        }
+
+        // Fall back to the smallest layout so the stamper reports the
+        // region as too small
+        let largest = adaptive::largest_fitting(region.width, region.height).unwrap_or_default();
+        let mut changes = Vec::new();
+        for feedback in &reports {
+            if let Some(index) = state.layout.report(feedback, largest) {
+                changes.push((index, feedback.success_rate));
+            }
+        }
+        let layout = state.layout.layout();
+        drop(state);
+
+        for (index, success_rate) in changes {
+            let layout = adaptive::LAYOUTS[index];
+            info!(
+                CAT,
+                imp: self,
+                "Adaptive layout {} after {:.0}% decoded: {} pixel cells, {} parity symbols",
+                index,
+                success_rate * 100.0,
+                layout.cell_size,
+                layout.parity_symbols
+            );
+            let structure = gst::Structure::builder("tslatency-layout-changed")
+                .field("layout", index as u32)
+                .field("cell-size", layout.cell_size as u32)
+                .field("parity-symbols", layout.parity_symbols as u32)
+                .field("success-rate", success_rate)
+                .build();
+            let msg = gst::message::Element::builder(structure)
+                .src(&*self.obj())
+                .build();
+            let _ = self.obj().post_message(msg);
+        }
+
+        layout
+    }
+
+    /// Warn if the code does not fit the primary region
+    fn check_tuning(&self, props: &Properties, state: &State) {
+        let Some(region) = state.regions.first() else {
+            return;
+        };
+        if props.content_hash && state.hash_region.is_none() {
+            warning!(
+                CAT,
+                imp: self,
+                "Content hash does not fit next to the time code, frames are not hashed"
+            );
+        }
+        if let Err(err) = props.tuning().check_fit(
+            props.stamper_type,
+            props.parity_symbols,
+            region.width,
+            region.height,
+        ) {
+            warning!(CAT, imp: self, "Time code does not fit: {}", err);
+        }
+    }
+
+    /// Warn about block interleaving for codes with a single codeword,
+    /// which it leaves in order
+    fn check_interleave(&self, props: &Properties) {
+        if props.interleave == Interleave::Block
+            && !matches!(
+                props.stamper_type,
+                StamperType::FastRobust | StamperType::Auto
+            )
+        {
+            warning!(
+                CAT,
+                imp: self,
+                "Block interleaving only applies to the fast-robust code, the {} code is stamped in codeword order",
+                props.stamper_type.as_str()
+            );
+        }
+    }
+
+    /// Warn about palettes the codecs cannot tell apart reliably
+    fn check_palette(&self, props: &Properties) {
+        if !props.palette().is_legible() {
+            warning!(
+                CAT,
+                imp: self,
+                "High color {:#08x} is not clearly brighter than low color {:#08x}, codes may not decode",
+                props.high_color,
+                props.low_color
+            );
+        }
+    }
+
+    /// Resolve the code region against the negotiated frame size, in
+    /// reference pixels if a reference resolution is set
+    fn resolve_region(&self) {
+        let props = self.props.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        let Some((width, height)) = state.frame_size else {
+            return;
+        };
+
+        let scale = Scale::new(width, height, props.reference_width, props.reference_height);
+        let (code_width, code_height) = (
+            scale.code_width(width as usize) as u32,
+            scale.code_height(height as usize) as u32,
+        );
+        let configured = props
+            .copy_anchors
+            .as_deref()
+            .map(copies::parse_anchors)
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        let placement = Placement {
+            anchor: props.anchor,
+            margin: props.margin,
             x: props.x as u32,
             y: props.y as u32,
             width: props.width as u32,
             height: props.height as u32,
+            width_percent: props.width_percent,
+            height_percent: props.height_percent,
+            alignment: props.block_align,
         };
-        drop(props);
-
-        let stamper = self.stamper.lock().unwrap();
-        stamper.stamp(frame, &self.clock, &config)?;
+        // Copying the watermark would copy the picture it is spread over
+        let copy_count = if props.stamper_type == StamperType::Watermark && props.copies > 1 {
+            warning!(
+                CAT,
+                imp: self,
+                "The watermark code is spread over the picture and cannot be copied, ignoring copies"
+            );
+            1
+        } else {
+            props.copies
+        };
+        let regions: Vec<_> = copies::copy_anchors(props.anchor, copy_count, &configured)
+            .into_iter()
+            .map(|anchor| {
+                Placement {
+                    anchor,
+                    ..placement
+                }
+                .resolve(code_width, code_height)
+            })
+            .collect();
+        let hash_region = regions
+            .first()
+            .filter(|_| props.content_hash)
+            .and_then(|primary| integrity::hash_region(primary, code_width, code_height));
+        let (regions, dropped) = copies::arrange(&regions, hash_region.as_slice());
+        for copy in dropped {
+            warning!(
+                CAT,
+                imp: self,
+                "Dropping the code copy at {},{}, it overlaps another code region or the content hash",
+                copy.x,
+                copy.y
+            );
+        }
 
-        Ok(FlowSuccess::Ok)
+        info!(
+            CAT,
+            imp: self,
+            "Time code regions {:?}, content hash region {:?}",
+            regions,
+            hash_region
+        );
+        state.regions = regions;
+        state.hash_region = hash_region;
+        self.check_tuning(&props, &state);
     }
 }